@@ -2,107 +2,2117 @@
  * MosaicMaker.rs
  *
  * Creates a mosaic image
- * usage: cargo build && ./MosaicMaker TILE_SIZE "input url/filepath" "output png file"
+ * usage: cargo build && ./MosaicMaker [OPTIONS] TILE_SIZE "input url/filepath" "output file"
  * example: cargo build && ./MosaicMaker 20 "https://raw.githubusercontent.com/Maxoplata/MosaicMaker/main/_readmeAssets/sampleInput.jpg" "./mosaic.png"
  * example: cargo build && ./MosaicMaker 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker completions bash > ~/.local/share/bash-completion/completions/MosaicMaker
+ * example: cargo build && ./MosaicMaker --alpha 200 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --alpha 200 --tile-opacity 100 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --tile-image "./logo.png" 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --self-tile --tint-from-tile 0.6 40 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --tile-image "./banner.png" --preserve-tile-aspect 40 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --repeat-tile-scale 4 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --pattern checker --checker-size 5 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --tile-dir "./tiles" 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --tile-dir "./tiles" --tint-from-tile 0.3 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --tile-dir "./tiles" --tile-dither 20 "./skyline.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --tile-dir "./tiles" --match-space lab 20 "./sampleInput.jpg" "./sampleOutput.png"
+ * example: cargo build && ./MosaicMaker --tile-dir "./tiles.zip" 20 "./sampleInput.jpg" "./sampleOutput.png"
+ * example: cargo build && ./MosaicMaker --tile-image "./logoA.png" --tile-image "./logoB.png" 20 "./sampleInput.jpg" "./sampleOutput.png"
+ * example: cargo build && ./MosaicMaker --report "./report.json" 20 "./sampleInput.jpg" "./sampleOutput.png"
+ * example: cargo build && ./MosaicMaker --preserve-icc 20 "./adobeRgbPhoto.jpg" "./mosaic.jpg"
+ * example: cargo build && ./MosaicMaker --embed-params 20 "./sampleInput.jpg" "./sampleOutput.png"
+ * example: cargo build && ./MosaicMaker --output-dir "./mosaics" --no-clobber 20 "./sampleInput.jpg"
+ * example: cargo build && ./MosaicMaker --mkdir 20 "./sampleInput.jpg" "./out/sub/mosaic.png"
+ * example: cargo build && ./MosaicMaker --rotate 90 20 "./sampleInput.jpg" "./sampleOutput.png"
+ * example: cargo build && ./MosaicMaker --flip-h --crop 0,0,400,300 20 "./sampleInput.jpg" "./sampleOutput.png"
+ * example: cargo build && ./MosaicMaker --grid 100 --low-mem 20 "./hugeInput.png" "./sampleOutput.png"
+ * example: cargo build && ./MosaicMaker --frame 5 20 "./animation.gif" "./frame5.png"
+ * example: cargo build && ./MosaicMaker --grid 100 20 "./largePhoto.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --output-size 1920x1080 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --output-size 1920x1080 --fit contain --background "#000000" "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --auto-side 6000 auto "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --crop 10,10,40,30 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --tile-shape circle --background "#FFFFFF" 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --tile-shape circle --aa 4 8 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --adaptive --min-tile 4 --max-tile 64 4 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --edges 0.8 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --vignette 0.6 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --grayscale --gray-method rec709 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --grayscale --grayscale-tile --gray-output 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --sharpen 1.5 20 "./sampleInput.jpg" "./mosaic.jpg"
+ * example: cargo build && ./MosaicMaker --recolor viridis --alpha 220 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --hue-shift 180 --saturation 1.5 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --mode pixelate 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --bit-depth 16 20 "./scan16.png" "./mosaic.tif"
+ * example: cargo build && ./MosaicMaker --palette 8 --dither 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --skip-transparent --alpha-threshold 64 20 "./sprite.png" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --brightness 20 --contrast 1.2 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --auto-levels --levels-clip 1 20 "./dullPhoto.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --layout hex 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --gap 2 --gap-color "#404040" 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --border 1 --border-color "#FFFFFF" 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --grid 40 10 "./animation.gif" "./mosaic.gif"
+ * example: cargo build && ./MosaicMaker --preview "./preview.jpg" 50 "./largePhoto.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --grid 40x30 --dump-grid "./grid.png" 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --streaming --max-side 200000 --max-pixels 10000000000 50 "./largePhoto.jpg" "./hugeMosaic.png"
+ * example: cargo build --features webp && ./MosaicMaker --quality 90 20 "./sampleInput.jpg" "./mosaic.webp"
+ * example: cargo build && ./MosaicMaker --jobs 4 20 "./photos/IMG_*.jpg" "./mosaics/{name}.png"
+ * example: cargo build && ./MosaicMaker --input-list "./sources.txt" --output-dir "./mosaics" --fail-fast 20
+ * example: cargo build && ./MosaicMaker --time --threads 4 20 "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker -vv 20 "https://example.com/photo.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --verbose-errors 20 "./broken.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --tile-size 20 --input "./sampleInput.jpg" --output "./mosaic.png"
+ * example: cargo build && ./MosaicMaker info "./sampleInput.jpg" 20
+ * example: cargo build && ./MosaicMaker diff --tolerance 2 "./mosaic.png" "./reference.png"
+ * example: cargo build && ./MosaicMaker --config "./mosaic.toml" "./sampleInput.jpg" "./mosaic.png"
+ * example: cargo build && ./MosaicMaker --format png 20 "./sampleInput.jpg" - > "./mosaic.png"
+ * example: curl "https://example.com/photo.jpg" | ./MosaicMaker --format jpeg 20 - - > "./mosaic.jpg"
+ *
+ * run with --help for every option
+ * build without URL support, for local files only: cargo build --no-default-features
  *
  * @author Maxamilian Demian
  * @link https://www.maxodev.org
  * @link https://github.com/Maxoplata/MosaicMaker
  */
-use std::{env, path, process};
-use image::{DynamicImage, GenericImageView, ImageBuffer, imageops};
-use reqwest;
+use std::{env, error::Error, ffi::OsString, fs, io::{self, Read, Write}, path, process, sync::{atomic::{AtomicUsize, Ordering}, Mutex}, time::{Duration, Instant}};
+#[cfg(feature = "url")]
+use std::thread;
+use clap::{error::ErrorKind, value_parser, ArgAction, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, info, warn, LevelFilter};
+use rayon::{prelude::*, ThreadPoolBuilder};
+use serde::Serialize;
+#[cfg(feature = "url")]
+use reqwest::{blocking::{Client, Response}, header, redirect, StatusCode};
+use mosaic_maker::{image::{codecs::gif::GifDecoder, AnimationDecoder}, apply_orientation, build_gif_mosaic, build_mosaic, color_grid, compare_images, decode_downsampled, decode_frame, decode_image, default_output_name, encode_mosaic, estimate_output_bytes, exif_orientation, expand_path, flatten, icc_profile, image, is_gray, mosaic_dimensions, mosaic_tile_size, output_format, parse_color, parse_filter, parse_format, flatten16, sharpen, shift_colors, stream_mosaic_png, BitDepth, BlendMode, ColorRamp, DynamicImage, EncodeOptions, GenericImageView, Crop, Fit, FilterType, GrayMethod, Grid, ImageFormat, Layout, MatchSpace, MosaicConfig, MosaicConfigBuilder, MosaicError, Palette, RenderMode, Rgba, SampleMode, TileLibrary, TilePattern, TileShape, DEFAULT_ADAPTIVE_THRESHOLD, DEFAULT_ALPHA, DEFAULT_ALPHA_THRESHOLD, DEFAULT_AUTO_SIDE, DEFAULT_CHECKER_SIZE, DEFAULT_BACKGROUND, DEFAULT_GAMMA, DEFAULT_LEVELS_CLIP, DEFAULT_MAX_PIXELS, DEFAULT_MAX_SIDE, MAX_ANTIALIAS};
 
-fn main() {
-    let args: Vec<String> = env::args().collect();
+/// Time spent in every phase run through [`timed`], and how often it ran, in
+/// the order the phases first ran.
+static TIMINGS: Mutex<Vec<(&'static str, Duration, u32)>> = Mutex::new(Vec::new());
 
-    // if we have arguments passed to the script
-    if args.len() != 4 {
-        println!("Invalid argument count");
-        process::exit(1);
-    }
+/// Temporary files of the outputs being written, see [`PartialFile`].
+static PARTIAL_FILES: Mutex<Vec<path::PathBuf>> = Mutex::new(Vec::new());
 
-    // vars
-    let tile_size: u32 = args[1].trim().parse().expect("TILE_SIZE expects a numeric value");
-    let input_file = &args[2];
-    let output_file = &args[3];
+/// Longest side of a --preview.
+const PREVIEW_SIDE: u32 = 1024;
 
-    // validate the tile size
-    if tile_size < 2 {
-        println!("Invalid tile size (minimum 2)");
-        process::exit(1);
-    }
+/// Largest URL download unless --max-download-bytes says otherwise, 50 MiB.
+#[cfg(feature = "url")]
+const DEFAULT_MAX_DOWNLOAD_BYTES: u64 = 50 * 1024 * 1024;
 
-    // validate input file
-    let img_orig = if path::Path::new(input_file).exists() {
-        image::open(input_file).expect("Invalid image")
-    } else {
-        let img_from_url = match reqwest::blocking::get(input_file) {
-            Ok(res) => {
-                if res.status() != 200 {
-                    println!("File does not exist");
-                    process::exit(1);
-                }
+#[cfg(feature = "url")]
+const USER_AGENT: &str = concat!("MosaicMaker/", env!("CARGO_PKG_VERSION"), " (+https://github.com/Maxoplata/MosaicMaker)");
 
-                res
-            },
-            Err(_) => {
-                println!("Unknown file error");
-                process::exit(1);
+/// Creates a mosaic image
+///
+/// TILE_SIZE, INPUT and OUTPUT can be given positionally, in that order, or
+/// with their options. --output-size takes the place of TILE_SIZE. The output
+/// format is --format, or else follows the OUTPUT extension: .png, .jpg/.jpeg
+/// or .bmp (both flattened onto --background, white by default), .tif/.tiff,
+/// .webp (flattened too unless --lossless) in builds with the webp feature, or
+/// .gif for an animated GIF INPUT, which is then processed frame by frame
+/// (keeping every frame in memory). INPUT, OUTPUT or both can be "-" to read
+/// the image from stdin and write it to stdout, which then needs --format.
+///
+/// Settings are taken from the defaults, overridden by the --config file,
+/// overridden in turn by the command line.
+///
+/// "info INPUT [TILE_SIZE]" only prints what INPUT is and the size its mosaic
+/// would have with the options given before "info".
+#[derive(Parser)]
+#[command(name = "MosaicMaker", version, args_override_self = true)]
+struct Cli {
+    /// TILE_SIZE, INPUT and OUTPUT not given as options
+    #[arg(value_name = "TILE_SIZE INPUT OUTPUT", num_args = 0..=3)]
+    positional: Vec<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Side length of each tile in pixels (minimum 2), or auto to aim at --auto-side
+    #[arg(short, long, value_parser = parse_tile_size)]
+    tile_size: Option<TileSize>,
+
+    /// Longest side in pixels the mosaic of --tile-size auto aims at
+    #[arg(long, value_name = "PIXELS", default_value_t = DEFAULT_AUTO_SIDE)]
+    auto_side: u32,
+
+    /// Make the mosaic exactly WxH pixels, with the tile size that fits best instead of TILE_SIZE
+    #[arg(long, value_name = "WxH", value_parser = parse_size, conflicts_with_all = ["tile_size", "tile_dir", "streaming"])]
+    output_size: Option<(u32, u32)>,
+
+    /// Resize to --output-size of another aspect ratio by stretching, letterboxing with --background (contain) or cropping the center (cover)
+    #[arg(long, value_name = "stretch|contain|cover", default_value = "stretch", requires = "output_size")]
+    fit: Fit,
+
+    /// Input image url or filepath, or - for stdin
+    #[arg(short, long)]
+    input: Option<String>,
+
+    /// Output file, or - for stdout, with --format
+    #[arg(short, long)]
+    output: Option<String>,
+
+    /// Make the mosaic of every path or URL in this file, one per line, instead of INPUT; blank lines and # comments are skipped
+    #[arg(long, value_name = "FILE", conflicts_with = "input")]
+    input_list: Option<String>,
+
+    /// Write the tile size, alpha, blend mode and input into PNG output as text metadata
+    #[arg(long, conflicts_with = "streaming")]
+    embed_params: bool,
+
+    /// Without OUTPUT, write the mosaic here as <input>_mosaic_t<tile size>.png, creating the directory
+    #[arg(long, value_name = "DIR", conflicts_with = "output_size")]
+    output_dir: Option<String>,
+
+    /// Read default settings from this TOML file, keyed by option name (e.g. alpha = 200, tile-size = 20)
+    #[arg(long, value_name = "FILE")]
+    config: Option<path::PathBuf>,
+
+    /// TILE_SIZE from the --config file, used when not given on the command line
+    #[arg(skip)]
+    config_tile_size: Option<TileSize>,
+
+    /// Whether --output-dir named OUTPUT, set by resolve
+    #[arg(skip)]
+    output_named: bool,
+
+    /// Strength of the color tint: 0 keeps the plain repeated tile, 255 gives a solid pixelated color grid
+    #[arg(long, default_value_t = DEFAULT_ALPHA)]
+    alpha: u8,
+
+    /// Opacity of the repeated tile under the tint: 0 leaves only the tint (pure pixelation at --alpha 255), 255 keeps it
+    #[arg(long, value_name = "0..255", default_value_t = 255)]
+    tile_opacity: u8,
+
+    /// Repeat the tile N×N times within every cell, each copy TILE_SIZE/N pixels wide
+    #[arg(long, value_name = "N", default_value_t = 1, conflicts_with = "tile_dir")]
+    repeat_tile_scale: u32,
+
+    /// How the tint is composited onto the tile: normal, multiply (darken), screen (lighten) or overlay (contrast)
+    #[arg(long, default_value = "normal")]
+    blend: BlendMode,
+
+    /// Blend the tint in linear light instead of sRGB, also used to match photomosaic tiles
+    #[arg(long)]
+    linear_blend: bool,
+
+    /// Gamma used to convert to and from linear light with --linear-blend
+    #[arg(long, default_value_t = DEFAULT_GAMMA, requires = "linear_blend")]
+    gamma: f32,
+
+    /// Brighten (or darken, when negative) the input by this much out of 255 before tiling
+    #[arg(long, value_name = "-255..255", default_value_t = 0, allow_negative_numbers = true, value_parser = value_parser!(i32).range(-255..=255))]
+    brightness: i32,
+
+    /// Scale the contrast of the input before tiling: 0 is flat gray, below 1 softer, above 1 harsher
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0)]
+    contrast: f32,
+
+    /// Stretch every color channel of the input to the full range, for dull photos
+    #[arg(long)]
+    auto_levels: bool,
+
+    /// Percentage of the darkest and of the lightest input pixels --auto-levels leaves out as outliers
+    #[arg(long, value_name = "PERCENT", default_value_t = DEFAULT_LEVELS_CLIP, requires = "auto_levels")]
+    levels_clip: f32,
+
+    /// Tint with the luminance of each pixel instead of its color
+    #[arg(long)]
+    grayscale: bool,
+
+    /// Desaturate the repeated tile as well
+    #[arg(long)]
+    grayscale_tile: bool,
+
+    /// Darken the tint on edges of the input by up to STRENGTH, for an outlined look
+    #[arg(long, value_name = "0.0..1.0", default_value_t = 0.0)]
+    edges: f32,
+
+    /// Fade the tint toward the middle by STRENGTH, or toward the corners if negative
+    #[arg(long, value_name = "-1.0..1.0", default_value_t = 0.0, allow_negative_numbers = true)]
+    vignette: f32,
+
+    /// Size the tiles to the detail of the input, from --max-tile in flat areas down to --min-tile
+    #[arg(long, conflicts_with_all = ["tile_dir", "streaming"])]
+    adaptive: bool,
+
+    /// Smallest tile of --adaptive
+    #[arg(long, value_name = "PX", default_value_t = 4, requires = "adaptive")]
+    min_tile: u32,
+
+    /// Largest tile of --adaptive
+    #[arg(long, value_name = "PX", default_value_t = 64, requires = "adaptive")]
+    max_tile: u32,
+
+    /// Luminance standard deviation (0-255) above which --adaptive splits a tile
+    #[arg(long, value_name = "DEVIATION", default_value_t = DEFAULT_ADAPTIVE_THRESHOLD, requires = "adaptive")]
+    adaptive_threshold: f32,
+
+    /// How --grayscale and --grayscale-tile compute the gray: rec601, rec709, average or lightness
+    #[arg(long, default_value = "rec601")]
+    gray_method: GrayMethod,
+
+    /// Tint with the inverted color of each pixel
+    #[arg(long)]
+    invert: bool,
+
+    /// Map the tint colors onto a gradient by their luminance: viridis, sepia, grayscale, or a file of #RRGGBB colors, darkest first
+    #[arg(long, value_name = "RAMP", value_parser = parse_recolor)]
+    recolor: Option<ColorRamp>,
+
+    /// Repeat a thumbnail of this image (url or filepath) instead of the input itself, given more than once a blend of them all
+    #[arg(long, value_name = "TILE")]
+    tile_image: Vec<String>,
+
+    /// Build the picture from miniatures of itself, the default tile, recolored by --tint-from-tile instead of tinted if given
+    #[arg(long, conflicts_with_all = ["tile_image", "tile_dir"])]
+    self_tile: bool,
+
+    /// Make the cells as tall as the tile image is for its width instead of square, TILE_SIZE wide
+    #[arg(long, conflicts_with_all = ["tile_dir", "output_size", "adaptive"])]
+    preserve_tile_aspect: bool,
+
+    /// Repeat a thumbnail (image), a tile of --pattern-color (solid) or a checkerboard of --pattern-color and --checker-color (checker)
+    #[arg(long, value_name = "PATTERN", default_value = "image")]
+    pattern: TilePattern,
+
+    /// Color of --pattern solid, and of every other --pattern checker square
+    #[arg(long, value_name = "#RRGGBB", value_parser = parse_color, default_value = "#FFFFFF")]
+    pattern_color: Rgba<u8>,
+
+    /// Color of the other --pattern checker squares
+    #[arg(long, value_name = "#RRGGBB", value_parser = parse_color, default_value = "#000000")]
+    checker_color: Rgba<u8>,
+
+    /// Side of the --pattern checker squares in pixels
+    #[arg(long, value_name = "PX", default_value_t = DEFAULT_CHECKER_SIZE, value_parser = value_parser!(u32).range(1..))]
+    checker_size: u32,
+
+    /// Resize tiles with nearest (fastest, blocky), triangle, catmullrom or lanczos3 (sharpest, slowest)
+    /// [default: fast thumbnail downscaling, triangle for --tile-dir]
+    #[arg(long, value_parser = parse_filter)]
+    filter: Option<FilterType>,
+
+    /// Build a true photomosaic out of the images in this directory or .zip archive instead of tinting one repeated tile
+    #[arg(long, value_name = "DIR", conflicts_with = "tile_image")]
+    tile_dir: Option<path::PathBuf>,
+
+    /// Keep the input as stored instead of turning it upright as its EXIF orientation says
+    #[arg(long)]
+    no_auto_orient: bool,
+
+    /// Keep the resized --tile-dir tiles here for the next run [default: the OS cache directory]
+    #[arg(long, value_name = "DIR", requires = "tile_dir")]
+    cache_dir: Option<path::PathBuf>,
+
+    /// Do not reuse a --tile-dir tile within this many cells horizontally or vertically
+    #[arg(long, value_name = "N", default_value_t = 0, requires = "tile_dir")]
+    repeat_limit: u32,
+
+    /// Pull every --tile-dir or --self-tile tile toward the color of its cell: 0 keeps the tile, 1 recolors it fully like --mode pixelate
+    #[arg(long, value_name = "0.0..1.0", default_value_t = 0.0)]
+    tint_from_tile: f32,
+
+    /// Color space --tile-dir tiles are matched in: rgb, or lab for closer looking but slower matches
+    #[arg(long, default_value = "rgb", requires = "tile_dir")]
+    match_space: MatchSpace,
+
+    /// Turn every --tile-dir tile by a random multiple of 90 degrees
+    #[arg(long, requires = "tile_dir")]
+    rotate_tiles: bool,
+
+    /// Dither the cell colors --tile-dir tiles are matched with (Bayer 4x4), so gradients mix neighboring tiles instead of banding
+    #[arg(long, requires = "tile_dir")]
+    tile_dither: bool,
+
+    /// Seed of the random choices of --rotate-tiles, the same seed giving the same mosaic
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// Turn the input clockwise by 0, 90, 180 or 270 degrees, after the EXIF orientation and before --crop
+    #[arg(long, value_name = "DEGREES", default_value_t = 0)]
+    rotate: u32,
+
+    /// Only tile the WxH pixel region of the input starting at X,Y, measured on the upright, --rotate'd input
+    #[arg(long, value_name = "X,Y,W,H")]
+    crop: Option<Crop>,
+
+    /// Mirror the input left to right, after --crop
+    #[arg(long)]
+    flip_h: bool,
+
+    /// Mirror the input top to bottom, after --crop
+    #[arg(long)]
+    flip_v: bool,
+
+    /// Downsample the input to N cells along its longer side, or to exactly WxH cells, before tiling
+    #[arg(long, value_name = "N|WxH")]
+    grid: Option<Grid>,
+
+    /// Decode the input straight down to the --grid cells, averaged, to save memory on huge PNG and JPEG inputs
+    #[arg(long, requires = "grid", conflicts_with_all = ["crop", "frame"])]
+    low_mem: bool,
+
+    /// Make the mosaic of frame N alone of an animated GIF or page N of a multi-page TIFF, counting from 1
+    #[arg(long, value_name = "N", value_parser = value_parser!(u32).range(1..))]
+    frame: Option<u32>,
+
+    /// Color each --grid cell from the input resized to the grid (point) or the exact mean of its pixels (avg)
+    #[arg(long, value_name = "MODE", default_value = "point")]
+    sample: SampleMode,
+
+    /// Snap the cell colors to a palette of N colors picked from the input (median cut), for a poster look
+    #[arg(long, value_name = "N", conflicts_with = "palette_file")]
+    palette: Option<usize>,
+
+    /// Snap the cell colors to the #RRGGBB colors listed in FILE, separated by commas or whitespace
+    #[arg(long, value_name = "FILE", value_parser = read_palette_file)]
+    palette_file: Option<Palette>,
+
+    /// Diffuse the error of snapping the cell colors to the palette over their neighbors (Floyd-Steinberg)
+    #[arg(long)]
+    dither: bool,
+
+    /// Scale the tint by the input transparency and leave fully transparent pixels empty
+    #[arg(long)]
+    respect_alpha: bool,
+
+    /// Leave the cells of input pixels more transparent than --alpha-threshold empty
+    #[arg(long)]
+    skip_transparent: bool,
+
+    /// Alpha below which --skip-transparent leaves a cell empty
+    #[arg(long, value_name = "0..255", default_value_t = DEFAULT_ALPHA_THRESHOLD, requires = "skip_transparent")]
+    alpha_threshold: u8,
+
+    /// Fill every cell with the tinted tile (tiled) or a solid block of the pixel color (pixelate)
+    #[arg(long, value_name = "MODE", default_value = "tiled")]
+    mode: RenderMode,
+
+    /// Outline of every tile: square, circle or rounded
+    #[arg(long, value_name = "SHAPE", default_value = "square")]
+    tile_shape: TileShape,
+
+    /// Lay the tiles out as a square grid or a honeycomb of hexagons (hex), odd rows shifted by half a tile
+    #[arg(long, value_name = "LAYOUT", default_value = "square")]
+    layout: Layout,
+
+    /// Smooth the edges of --tile-shape circle or rounded and of --layout hex, judging every pixel at FACTOR x FACTOR points
+    #[arg(long = "aa", value_name = "FACTOR", default_value_t = 1, value_parser = value_parser!(u32).range(1..=i64::from(MAX_ANTIALIAS)))]
+    antialias: u32,
+
+    /// Leave this many pixels of grout between adjacent tiles
+    #[arg(long, value_name = "PX", default_value_t = 0)]
+    gap: u32,
+
+    /// Color of the grout between tiles
+    #[arg(long, value_name = "#RRGGBB", value_parser = parse_color, default_value = "#000000")]
+    gap_color: Rgba<u8>,
+
+    /// Frame every tile with a border this many pixels wide, inside its cell
+    #[arg(long, value_name = "PX", default_value_t = 0)]
+    border: u32,
+
+    /// Color of the --border around every tile
+    #[arg(long, value_name = "#RRGGBB", value_parser = parse_color, default_value = "#000000")]
+    border_color: Rgba<u8>,
+
+    /// Only print the size and memory footprint of the mosaic, without rendering or writing it
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Bits per channel of the mosaic: auto keeps 16 bit inputs at 16 bits, which only PNG and TIFF can store
+    #[arg(long, value_name = "auto|8|16", default_value = "auto")]
+    bit_depth: BitDepth,
+
+    /// Do not show the progress bar (it is also hidden when stderr is not a terminal)
+    #[arg(short, long)]
+    quiet: bool,
+
+    /// Print how long every phase took to stderr at the end, summed over the files of a glob INPUT
+    #[arg(long)]
+    time: bool,
+
+    /// Log what is done to stderr, -vv for details and timings, -vvv for the libraries too
+    #[arg(short, long, action = ArgAction::Count)]
+    verbose: u8,
+
+    /// On failure, print every underlying error too, such as the IO, codec or network error behind it
+    #[arg(long)]
+    verbose_errors: bool,
+
+    /// Number of files of a glob INPUT or --input-list to process at the same time
+    #[arg(long, value_name = "N", default_value_t = 1, value_parser = value_parser!(u32).range(1..))]
+    jobs: u32,
+
+    /// Stop at the first file of a glob INPUT or --input-list that fails instead of going on with the others
+    #[arg(long)]
+    fail_fast: bool,
+
+    /// Number of render threads [default: one per CPU]
+    #[arg(long)]
+    threads: Option<usize>,
+
+    /// Refuse outputs wider or higher than this many pixels
+    #[arg(long, value_name = "PX", default_value_t = DEFAULT_MAX_SIDE)]
+    max_side: u32,
+
+    /// Refuse outputs with more pixels in total than this, after --grid and --gap
+    #[arg(long, value_name = "PIXELS", default_value_t = DEFAULT_MAX_PIXELS)]
+    max_pixels: u64,
+
+    /// Flatten the mosaic onto this color before saving [default for formats without alpha: #FFFFFF]
+    #[arg(long, value_name = "#RRGGBB", value_parser = parse_color)]
+    background: Option<Rgba<u8>>,
+
+    /// Sharpen the mosaic with an unsharp mask blurring by SIGMA pixels, after flattening onto --background
+    #[arg(long, value_name = "SIGMA", value_parser = parse_sigma, conflicts_with = "streaming")]
+    sharpen: Option<f32>,
+
+    /// Turn the hue of the finished mosaic by DEGREES
+    #[arg(long, value_name = "DEGREES", default_value_t = 0.0, allow_negative_numbers = true, conflicts_with = "streaming")]
+    hue_shift: f32,
+
+    /// Scale the saturation of the finished mosaic by FACTOR, 0 for gray
+    #[arg(long, value_name = "FACTOR", default_value_t = 1.0, value_parser = parse_non_negative, conflicts_with = "streaming")]
+    saturation: f32,
+
+    /// Encode the PNG row by row while rendering instead of holding the whole mosaic in memory,
+    /// for outputs larger than the available RAM (PNG only, alpha always kept)
+    #[arg(long, conflicts_with = "background")]
+    streaming: bool,
+
+    /// Also write a JPEG of the mosaic scaled down to at most 1024px per side here, {name} working as in OUTPUT
+    #[arg(long, value_name = "FILE", conflicts_with = "streaming")]
+    preview: Option<String>,
+
+    /// Also write the sampled color of every cell here, one pixel per cell, {name} working as in OUTPUT
+    #[arg(long, value_name = "FILE")]
+    dump_grid: Option<String>,
+
+    /// Write a JSON summary of the run here once OUTPUT is saved, {name} working as in OUTPUT
+    #[arg(long, value_name = "FILE", conflicts_with = "dry_run")]
+    report: Option<String>,
+
+    /// Fail instead of overwriting an existing OUTPUT file
+    #[arg(long, overrides_with = "force")]
+    no_clobber: bool,
+
+    /// Embed the ICC color profile of a PNG or JPEG INPUT into PNG or JPEG output
+    #[arg(long, conflicts_with = "streaming")]
+    preserve_icc: bool,
+
+    /// Create the directories of OUTPUT, --preview, --dump-grid and --report if missing instead of failing
+    #[arg(long)]
+    mkdir: bool,
+
+    /// Overwrite an existing OUTPUT file without a note, undoing --no-clobber
+    #[arg(long, overrides_with = "no_clobber")]
+    force: bool,
+
+    /// Encode as png, jpeg, bmp, tiff or webp whatever the OUTPUT extension, required for stdout
+    #[arg(long, value_parser = parse_format)]
+    format: Option<ImageFormat>,
+
+    /// JPEG or lossy WebP quality, ignored for other formats
+    #[arg(long, value_parser = value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+
+    /// Write lossless WebP, keeping the alpha channel
+    #[arg(long)]
+    lossless: bool,
+
+    /// Write PNG as gray (Luma, or LumaA with transparency), which is smaller for a gray mosaic
+    #[arg(long, conflicts_with = "streaming")]
+    gray_output: bool,
+
+    /// Send this extra header with URL downloads, e.g. "Authorization: Bearer TOKEN" (repeatable)
+    #[cfg(feature = "url")]
+    #[arg(long, value_name = "KEY: VALUE", value_parser = parse_header)]
+    header: Vec<(header::HeaderName, header::HeaderValue)>,
+
+    /// Retry URL downloads this many times on connection errors, timeouts and 5xx or 429 answers
+    #[cfg(feature = "url")]
+    #[arg(long, value_name = "N", default_value_t = 3)]
+    retries: u32,
+
+    /// Give up on URL downloads after this many seconds
+    #[cfg(feature = "url")]
+    #[arg(long, value_name = "SECS", default_value_t = 30)]
+    timeout_secs: u64,
+
+    /// Refuse URL downloads larger than this many bytes
+    #[cfg(feature = "url")]
+    #[arg(long, value_name = "BYTES", default_value_t = DEFAULT_MAX_DOWNLOAD_BYTES)]
+    max_download_bytes: u64,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the format, size, color type and orientation of INPUT and the size of its mosaic, then exit
+    Info {
+        /// Input image url or filepath, or - for stdin
+        input: String,
+
+        /// Tile size to report the mosaic size for [default: --tile-size or the --config file]
+        tile_size: Option<u32>,
+    },
+
+    /// Compare two images, such as a mosaic and a reference one, exiting with 1 if they differ beyond --tolerance, 2 if one cannot be read
+    Diff {
+        /// First image url or filepath, or - for stdin
+        first: String,
+
+        /// Second image url or filepath
+        second: String,
+
+        /// Largest difference of any pixel channel, on the 8 bit scale, still counted as the same
+        #[arg(long, default_value_t = 0.0, value_parser = parse_non_negative)]
+        tolerance: f32,
+    },
+
+    /// Print the completion script for SHELL to stdout, e.g. for ~/.local/share/bash-completion/completions
+    Completions {
+        /// Shell to complete the options in
+        shell: Shell,
+    },
+}
+
+/// A TILE_SIZE in pixels, or picked per input.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TileSize {
+    Pixels(u32),
+    Auto,
+}
+
+impl Cli {
+    /// Fills TILE_SIZE, INPUT and OUTPUT not given as options from the
+    /// positional arguments, in that order.
+    fn resolve(&mut self) -> Result<(u32, String, String), clap::Error> {
+        let mut positional = std::mem::take(&mut self.positional).into_iter();
+
+        // the config file tile size only fills in when the positionals are left for INPUT and OUTPUT
+        let named = usize::from(self.input.is_some() || self.input_list.is_some()) + usize::from(self.output.is_some());
+
+        let tile_size = match (self.tile_size, self.config_tile_size) {
+            (Some(tile_size), _) => tile_size,
+            // only a placeholder, replaced by the tile size fitting the output size
+            (None, _) if self.output_size.is_some() => TileSize::Pixels(2),
+            (None, Some(tile_size)) if positional.len() + named <= 2 => tile_size,
+            (None, _) => {
+                let tile_size = positional
+                    .next()
+                    .ok_or_else(|| usage_error(ErrorKind::MissingRequiredArgument, "TILE_SIZE is required"))?;
+
+                parse_tile_size(&tile_size).map_err(|_| {
+                    usage_error(ErrorKind::ValueValidation, format!("TILE_SIZE expects a numeric value or auto, got \"{}\"", tile_size))
+                })?
             },
         };
 
-        let image_from_url_bytes = match img_from_url.bytes() {
-            Ok(res) => {
-                res
-            },
-            Err(_) => {
-                println!("Unknown file error");
-                process::exit(1);
+        // the settings read it back to pick the tile size per input
+        self.tile_size = Some(tile_size);
+
+        if tile_size == TileSize::Auto && self.tile_dir.is_some() {
+            return Err(usage_error(ErrorKind::ArgumentConflict, "--tile-dir needs a fixed TILE_SIZE, its tiles are resized to it up front"));
+        }
+
+        // for paths the shell did not expand, such as quoted ones
+        let input = self
+            .input_list
+            .clone()
+            .or_else(|| self.input.take())
+            .or_else(|| positional.next())
+            .map(|input| expand_path(&input))
+            .ok_or_else(|| usage_error(ErrorKind::MissingRequiredArgument, "INPUT is required"))?;
+
+        let output = self.output.take().or_else(|| positional.next());
+        self.output_named = output.is_none() && self.output_dir.is_some();
+
+        let output = match (output, &self.output_dir) {
+            (Some(output), _) => expand_path(&output),
+            (None, Some(output_dir)) => match tile_size {
+                TileSize::Pixels(tile_size) => self.output_in_dir(path::Path::new(&expand_path(output_dir)), &input, tile_size)?,
+                TileSize::Auto => {
+                    return Err(usage_error(ErrorKind::ArgumentConflict, "--output-dir names OUTPUT after a fixed TILE_SIZE, give OUTPUT with --tile-size auto"));
+                },
             },
+            (None, None) => return Err(usage_error(ErrorKind::MissingRequiredArgument, "OUTPUT is required")),
         };
 
-        let image_from_url_bytes_loaded = match image::load_from_memory(&image_from_url_bytes) {
-            Ok(res) => {
-                res
+        if positional.next().is_some() {
+            return Err(usage_error(ErrorKind::TooManyValues, "Invalid argument count"));
+        }
+
+        let extension = ImageFormat::from_path(&output).ok();
+
+        match (self.format, extension) {
+            (None, _) if output == "-" => {
+                return Err(usage_error(ErrorKind::MissingRequiredArgument, "--format is required when OUTPUT is -"));
             },
-            Err(_) => {
-                println!("Unknown file error");
-                process::exit(1);
+            (Some(format), Some(extension)) if format != extension => {
+                eprintln!("Writing {} to {} as --format asks, despite its extension", format!("{:?}", format).to_uppercase(), output);
             },
+            _ => {},
+        }
+
+        if self.streaming && self.format.or(extension) != Some(ImageFormat::Png) {
+            return Err(usage_error(ErrorKind::ArgumentConflict, "--streaming only writes PNG, use --format png or a .png OUTPUT"));
+        }
+
+        // only a placeholder, replaced by the tile size picked per input
+        let tile_size = match tile_size {
+            TileSize::Pixels(tile_size) => tile_size,
+            TileSize::Auto => 2,
         };
 
-        image_from_url_bytes_loaded
+        Ok((tile_size, input, output))
+    }
+
+    /// OUTPUT in --output-dir, which is created if missing, named after
+    /// `input` and, with --no-clobber, [`unclobbered`].
+    fn output_in_dir(&self, output_dir: &path::Path, input: &str, tile_size: u32) -> Result<String, clap::Error> {
+        fs::create_dir_all(output_dir).map_err(|err| {
+            usage_error(ErrorKind::Io, format!("Could not create --output-dir {}: {}", output_dir.display(), err))
+        })?;
+
+        // every file of a glob INPUT or --input-list is named after itself
+        let input = if is_glob(input) || self.input_list.is_some() { "{name}" } else { input };
+        let name = default_output_name(input, tile_size, self.format.unwrap_or(ImageFormat::Png));
+        let output = output_dir.join(&name);
+
+        // the outputs of a batch are numbered one by one once main names them
+        if self.no_clobber && !name.contains("{name}") {
+            return Ok(unclobbered(&output).display().to_string());
+        }
+
+        Ok(output.display().to_string())
+    }
+}
+
+/// Parses a "Key: Value" header, its value marked sensitive so it is redacted
+/// from debug output.
+#[cfg(feature = "url")]
+fn parse_header(header: &str) -> Result<(header::HeaderName, header::HeaderValue), String> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| format!("expected \"KEY: VALUE\", got \"{}\"", header))?;
+
+    let name = header::HeaderName::from_bytes(name.trim().as_bytes()).map_err(|_| format!("invalid header name \"{}\"", name.trim()))?;
+    let mut value = header::HeaderValue::from_str(value.trim()).map_err(|_| format!("invalid value for header {}", name))?;
+    value.set_sensitive(true);
+
+    Ok((name, value))
+}
+
+/// Parses a TILE_SIZE, a number of pixels or auto.
+fn parse_tile_size(tile_size: &str) -> Result<TileSize, String> {
+    let tile_size = tile_size.trim();
+
+    if tile_size.eq_ignore_ascii_case("auto") {
+        return Ok(TileSize::Auto);
+    }
+
+    tile_size
+        .parse()
+        .map(TileSize::Pixels)
+        .map_err(|_| format!("expected a number of pixels or auto, got \"{}\"", tile_size))
+}
+
+/// Parses the WxH of --output-size.
+fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let invalid = || format!("expected WxH, e.g. 1920x1080, got \"{}\"", size);
+    let (width, height) = size.split_once(['x', 'X']).ok_or_else(invalid)?;
+
+    match (width.trim().parse(), height.trim().parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parses the positive blur radius of --sharpen.
+fn parse_sigma(sigma: &str) -> Result<f32, String> {
+    match sigma.trim().parse::<f32>() {
+        Ok(sigma) if sigma.is_finite() && sigma > 0.0 => Ok(sigma),
+        _ => Err(format!("expected a positive number, got \"{}\"", sigma)),
+    }
+}
+
+/// Parses the non-negative factor of --saturation and the --tolerance of diff.
+fn parse_non_negative(value: &str) -> Result<f32, String> {
+    match value.trim().parse::<f32>() {
+        Ok(value) if value.is_finite() && value >= 0.0 => Ok(value),
+        _ => Err(format!("expected a non-negative number, got \"{}\"", value)),
+    }
+}
+
+/// Parses a named --recolor ramp, or reads the colors of a ramp file.
+fn parse_recolor(recolor: &str) -> Result<ColorRamp, String> {
+    let ramp = match fs::read_to_string(recolor) {
+        Ok(contents) => contents.parse(),
+        Err(_) if path::Path::new(recolor).exists() => return Err(format!("could not read {}", recolor)),
+        Err(_) => recolor.parse(),
+    };
+
+    ramp.map_err(|err: MosaicError| err.to_string())
+}
+
+/// Reads the colors of a --palette-file.
+fn read_palette_file(palette_file: &str) -> Result<Palette, String> {
+    let contents = fs::read_to_string(palette_file).map_err(|err| format!("could not read {}: {}", palette_file, err))?;
+
+    contents.parse().map_err(|err: MosaicError| err.to_string())
+}
+
+fn usage_error(kind: ErrorKind, message: impl std::fmt::Display) -> clap::Error {
+    Cli::command().error(kind, message)
+}
+
+fn main() {
+    // usage errors exit with 1 like every other failure, --help/--version with 0
+    let mut cli = parse_cli().unwrap_or_else(|err| exit_usage(err));
+
+    match cli.command.take() {
+        Some(Command::Info { input, tile_size }) => {
+            init_logging(cli.verbose);
+
+            // the tile size after info goes first
+            cli.tile_size = tile_size.map(TileSize::Pixels).or(cli.tile_size).or(cli.config_tile_size);
+
+            if let Err(err) = print_info(&cli, &expand_path(&input)) {
+                eprintln!("{}", error_report(&err, cli.verbose_errors));
+                process::exit(1);
+            }
+            return;
+        },
+        Some(Command::Diff { first, second, tolerance }) => {
+            init_logging(cli.verbose);
+
+            match diff_images(&cli, &expand_path(&first), &expand_path(&second), tolerance) {
+                Ok(true) => return,
+                Ok(false) => process::exit(1),
+                // like cmp, so failing to read is told apart from differing
+                Err(err) => {
+                    eprintln!("{}", error_report(&err, cli.verbose_errors));
+                    process::exit(2);
+                },
+            }
+        },
+        Some(Command::Completions { shell }) => {
+            write_completions(shell, &mut io::stdout());
+            return;
+        },
+        None => {},
+    }
+
+    let (tile_size, input_file, output_file) = cli.resolve().unwrap_or_else(|err| exit_usage(err));
+    init_logging(cli.verbose);
+
+    // an interrupted run leaves the outputs as they were
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Err(err) = ctrlc::set_handler(|| {
+        remove_partial_files();
+        process::exit(130);
+    }) {
+        warn!("Could not handle Ctrl-C, an interrupted run may leave temporary files behind: {}", err);
+    }
+
+    let batch = match &cli.input_list {
+        Some(_) => {
+            let contents = fs::read_to_string(&input_file)
+                .map_err(|err| usage_error(ErrorKind::Io, format!("Could not read --input-list {}: {}", input_file, err)));
+            contents.and_then(|contents| input_list_files(&contents, &input_file, &output_file)).map(Some)
+        },
+        None => batch_files(&input_file, &output_file),
+    };
+    let batch = batch.unwrap_or_else(|err| exit_usage(err));
+
+    // only now that a batch is expanded can its outputs in --output-dir be numbered
+    let batch = batch.map(|files| if cli.no_clobber && cli.output_named { number_outputs(files) } else { files });
+    let batch_input = if cli.input_list.is_some() { "--input-list" } else { "A glob INPUT" };
+
+    if batch.is_some() && cli.preview.as_ref().is_some_and(|preview| !preview.contains("{name}")) {
+        exit_usage(usage_error(ErrorKind::ValueValidation, format!("{} needs {{name}} in --preview too, e.g. \"out/{{name}}-preview.jpg\"", batch_input)));
+    }
+
+    if batch.is_some() && cli.dump_grid.as_ref().is_some_and(|dump_grid| !dump_grid.contains("{name}")) {
+        exit_usage(usage_error(ErrorKind::ValueValidation, format!("{} needs {{name}} in --dump-grid too, e.g. \"out/{{name}}-grid.png\"", batch_input)));
+    }
+
+    if batch.is_some() && cli.report.as_ref().is_some_and(|report| !report.contains("{name}")) {
+        exit_usage(usage_error(ErrorKind::ValueValidation, format!("{} needs {{name}} in --report too, e.g. \"out/{{name}}.json\"", batch_input)));
+    }
+
+    let start = Instant::now();
+    let result = match &batch {
+        Some(files) => run_batch(&cli, tile_size, files).map(|failed| failed == 0),
+        None => run(&cli, tile_size, &input_file, &output_file).map(|()| true),
     };
 
-    // create image tile
-    let img_tile = img_orig.thumbnail(tile_size, tile_size).to_rgba8();
+    // stderr, like the progress bar, so a mosaic written to stdout stays clean
+    if cli.time {
+        print_timings(start);
+    }
+
+    match result {
+        Ok(true) => {},
+        Ok(false) => process::exit(1),
+        Err(err) => {
+            eprintln!("{}", error_report(&err, cli.verbose_errors));
+            process::exit(1);
+        },
+    }
+}
+
+/// The message of `err`, followed with `verbose_errors` by a line for every
+/// error that caused it, down to the first one.
+fn error_report(err: &dyn Error, verbose_errors: bool) -> String {
+    let mut report = err.to_string();
+
+    if verbose_errors {
+        let mut source = err.source();
+        while let Some(cause) = source {
+            report.push_str(&format!("\n  caused by: {}", cause));
+            source = cause.source();
+        }
+    }
+    report
+}
+
+/// Writes the completion script of `shell` for the options of the binary.
+fn write_completions(shell: Shell, out: &mut impl Write) {
+    clap_complete::generate(shell, &mut Cli::command(), env!("CARGO_BIN_NAME"), out);
+}
+
+/// Prints how far the images at `first` and `second` are apart, returning
+/// whether they are the same within `tolerance`.
+fn diff_images(cli: &Cli, first: &str, second: &str, tolerance: f32) -> Result<bool, MosaicError> {
+    let downloader = Downloader::new(cli)?;
+    let load = |input_file| load_input(input_file, &downloader, !cli.no_auto_orient);
+    let (a, b) = (load(first)?, load(second)?);
 
-    // get width/height of image
-    let (width_orig, height_orig) = img_orig.dimensions();
+    let Some(difference) = compare_images(&a, &b) else {
+        println!("Dimensions differ: {}x{} and {}x{}", a.width(), a.height(), b.width(), b.height());
+        return Ok(false);
+    };
 
-    // create new image
-    let mut img_new = DynamicImage::ImageRgba8(ImageBuffer::new(width_orig * tile_size, height_orig * tile_size));
+    let same = difference.max <= tolerance;
+    println!("Dimensions: {}x{}", a.width(), a.height());
+    println!("Difference: mean {:.3}, max {:.3}", difference.mean, difference.max);
+    if same {
+        println!("Same within a tolerance of {}", tolerance);
+    } else {
+        println!("Different beyond a tolerance of {}", tolerance);
+    }
 
-    // iterate through original image pixels
-    for x in 0..width_orig {
-        for y in 0..height_orig {
-            // copy image tile to new image
-            imageops::overlay(&mut img_new, &img_tile, x * tile_size, y * tile_size);
+    Ok(same)
+}
 
-            // get pixel color from original image
-            let pixel = img_orig.get_pixel(x, y);
+/// Prints what the info command tells about `input_file`, the mosaic size at
+/// the tile size of the command line included if there is one.
+fn print_info(cli: &Cli, input_file: &str) -> Result<(), MosaicError> {
+    let bytes = read_input(input_file, &Downloader::new(cli)?)?;
+    let img = decode_image(&bytes, input_file)?;
+    let format = image::guess_format(&bytes).ok();
+    let color = img.color();
 
-            // create color tile
-            let img_color = DynamicImage::ImageRgba8(ImageBuffer::from_fn(tile_size, tile_size, |_x, _y| {
-                image::Rgba([pixel.0[0], pixel.0[1], pixel.0[2], 127])
-            }));
+    println!("Format: {}", format.map_or("unknown".to_string(), |format| format!("{:?}", format).to_uppercase()));
+    println!("Dimensions: {}x{}", img.width(), img.height());
+    println!("Color type: {:?}, {} bits per channel", color, u16::from(color.bytes_per_pixel()) * 8 / u16::from(color.channel_count()));
+    println!("Alpha: {}", if color.has_alpha() { "yes" } else { "no" });
 
-            // copy color tile to new image
-            imageops::overlay(&mut img_new, &img_color, x * tile_size, y * tile_size);
+    // sideways orientations swap the sides of the mosaic
+    let mut dimensions = img.dimensions();
+    if let Some(orientation) = exif_orientation(&bytes) {
+        if (5..=8).contains(&orientation) && !cli.no_auto_orient {
+            dimensions = (img.height(), img.width());
         }
+        println!("EXIF orientation: {}{}", orientation, if cli.no_auto_orient { ", ignored with --no-auto-orient" } else { "" });
+    }
+
+    if format == Some(ImageFormat::Gif) {
+        let decode_error = |source| MosaicError::Decode {
+            input: input_file.to_string(),
+            source,
+        };
+        let frames = GifDecoder::new(io::Cursor::new(&bytes)).map_err(decode_error)?.into_frames().count();
+        println!("Frames: {}", frames);
+    }
+
+    // an output size or auto decides the tile size, the one given is a placeholder
+    let tile_size = match cli.tile_size {
+        Some(TileSize::Pixels(tile_size)) => tile_size,
+        Some(TileSize::Auto) => 2,
+        None if cli.output_size.is_some() => 2,
+        None => {
+            println!("Mosaic: give a TILE_SIZE for its size");
+            return Ok(());
+        },
+    };
+
+    let config = settings(cli, tile_size, false).build()?;
+    match mosaic_dimensions(dimensions, &config).and_then(|size| Ok((size, mosaic_tile_size(dimensions, &config)?))) {
+        Ok(((width, height), _)) if cli.output_size.is_some() => println!("Mosaic: {}x{}", width, height),
+        Ok(((width, height), tile_size)) => println!("Mosaic: {}x{} at {}px tiles", width, height, tile_size),
+        Err(err) => println!("Mosaic: {}", err),
+    }
+
+    Ok(())
+}
+
+/// Sends the log to stderr, only the messages of MosaicMaker and its library below -vvv.
+/// RUST_LOG overrides the level picked by -v.
+fn init_logging(verbose: u8) {
+    let mut builder = env_logger::Builder::new();
+
+    match verbose {
+        0 => builder.filter_level(LevelFilter::Warn),
+        1 => builder.filter_module(module_path!(), LevelFilter::Info).filter_module("mosaic_maker", LevelFilter::Info),
+        2 => builder.filter_module(module_path!(), LevelFilter::Debug).filter_module("mosaic_maker", LevelFilter::Debug),
+        _ => builder.filter_level(LevelFilter::Trace),
+    };
+
+    builder.format_timestamp(None).parse_default_env().init();
+}
+
+/// Runs `phase`, logging how long it took and adding it up for --time.
+fn timed<T>(phase: &'static str, job: impl FnOnce() -> T) -> T {
+    let start = Instant::now();
+    let result = job();
+    let elapsed = start.elapsed();
+    debug!("{} took {:.2?}", phase, elapsed);
+
+    let mut timings = TIMINGS.lock().expect("no phase panics while holding the timings");
+    match timings.iter_mut().find(|(other, ..)| *other == phase) {
+        Some((_, total, count)) => {
+            *total += elapsed;
+            *count += 1;
+        },
+        None => timings.push((phase, elapsed, 1)),
+    }
+
+    result
+}
+
+/// Prints the --time table of the phases, and the total run time since `start`.
+fn print_timings(start: Instant) {
+    let timings = TIMINGS.lock().expect("no phase panics while holding the timings");
+    let width = timings.iter().map(|(phase, ..)| phase.len()).max().unwrap_or(0).max("Total".len());
+
+    for (phase, total, count) in timings.iter() {
+        let count = if *count > 1 { format!(" ({} times)", count) } else { String::new() };
+        eprintln!("{:<width$}  {:>10.2?}{}", phase, total, count, width = width);
+    }
+    eprintln!("{:<width$}  {:>10.2?}", "Total", start.elapsed(), width = width);
+}
+
+/// The `(input, output)` pairs of a glob INPUT, each output being OUTPUT with
+/// `{name}` replaced by the input file name without extension, or None when
+/// INPUT is a single file, URL or stdin.
+fn batch_files(input_file: &str, output_file: &str) -> Result<Option<Vec<(String, String)>>, clap::Error> {
+    if !is_glob(input_file) {
+        return Ok(None);
     }
 
-    img_new.save(output_file).expect("Error saving image file");
+    if !output_file.contains("{name}") {
+        return Err(usage_error(ErrorKind::ValueValidation, "A glob INPUT needs {name} in OUTPUT, e.g. \"out/{name}.png\""));
+    }
+
+    let paths = glob::glob(input_file).map_err(|err| usage_error(ErrorKind::ValueValidation, format!("Invalid glob \"{}\": {}", input_file, err)))?;
+    let inputs: Vec<_> = paths.filter_map(Result::ok).filter(|path| path.is_file()).map(|path| path.display().to_string()).collect();
+
+    if inputs.is_empty() {
+        return Err(usage_error(ErrorKind::ValueValidation, format!("No files match {}", input_file)));
+    }
+
+    name_outputs(inputs, output_file).map(Some)
+}
+
+/// The `(input, output)` pairs of the paths and URLs of an --input-list,
+/// one per line of `contents`, skipping blank lines and lines starting with
+/// `#`. Every output is `output_file` with `{name}` replaced by the file
+/// name of its input, without the extension.
+fn input_list_files(contents: &str, list_file: &str, output_file: &str) -> Result<Vec<(String, String)>, clap::Error> {
+    if !output_file.contains("{name}") {
+        return Err(usage_error(ErrorKind::ValueValidation, "--input-list needs {name} in OUTPUT, e.g. \"out/{name}.png\", or --output-dir"));
+    }
+
+    // only whole lines are comments, URLs may well contain a #
+    let inputs: Vec<_> = contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| if is_url(line) { line.to_string() } else { expand_path(line) })
+        .collect();
+
+    if inputs.is_empty() {
+        return Err(usage_error(ErrorKind::ValueValidation, format!("--input-list {} lists no inputs", list_file)));
+    }
+
+    name_outputs(inputs, output_file)
+}
+
+/// Pairs every input with `output_file`, `{name}` in it replaced by the file
+/// name of the input, refusing inputs that would be written to the same output.
+fn name_outputs(inputs: Vec<String>, output_file: &str) -> Result<Vec<(String, String)>, clap::Error> {
+    let mut files: Vec<(String, String)> = Vec::new();
+
+    for input in inputs {
+        // the query and fragment of a URL are no part of its file name
+        let file = if is_url(&input) { input.split(['?', '#']).next().unwrap_or_default() } else { &input };
+        let name = path::Path::new(file).file_stem().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let output = output_file.replace("{name}", &name);
+
+        if let Some((other, _)) = files.iter().find(|(_, other_output)| *other_output == output) {
+            return Err(usage_error(ErrorKind::ValueValidation, format!("{} and {} would both be written to {}", other, input, output)));
+        }
+        files.push((input, output));
+    }
+
+    Ok(files)
+}
+
+/// `output`, or with `_1`, `_2`, ... after its stem if a file of that name
+/// exists, whichever comes first that does not.
+fn unclobbered(output: &path::Path) -> path::PathBuf {
+    let stem = output.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = output.extension().unwrap_or_default().to_string_lossy();
+    let mut numbered = output.to_path_buf();
+
+    for counter in 1.. {
+        if !numbered.exists() {
+            break;
+        }
+        numbered = output.with_file_name(format!("{}_{}.{}", stem, counter, extension));
+    }
+
+    numbered
+}
+
+/// The `files` of a batch, every output [`unclobbered`].
+fn number_outputs(files: Vec<(String, String)>) -> Vec<(String, String)> {
+    files.into_iter().map(|(input, output)| (input, unclobbered(path::Path::new(&output)).display().to_string())).collect()
+}
+
+/// Whether `input_file` is a glob rather than a single file or URL.
+fn is_glob(input_file: &str) -> bool {
+    // an existing file wins over a glob, its name may well contain brackets
+    input_file.contains(['*', '?', '[']) && !is_url(input_file) && !path::Path::new(input_file).exists()
+}
+
+/// Parses the command line on top of the settings of the --config file, if any.
+fn parse_cli() -> Result<Cli, clap::Error> {
+    let cli = Cli::try_parse()?;
+
+    let Some(config_file) = &cli.config else {
+        return Ok(cli);
+    };
+
+    // config file options go first so the command line overrides them
+    let (config_args, config_tile_size) = read_config_file(config_file)?;
+    let mut args = env::args_os();
+    let bin = args.next();
+
+    let mut merged = Cli::try_parse_from(bin.into_iter().chain(config_args.into_iter().map(OsString::from)).chain(args))?;
+    merged.config_tile_size = config_tile_size;
+
+    // repeated options add up, so on the command line they replace those of the config file instead
+    if !cli.tile_image.is_empty() {
+        merged.tile_image = cli.tile_image;
+    }
+
+    Ok(merged)
+}
+
+/// Turns the settings of a TOML config file into command line options, and
+/// its tile size, which cannot be one without clashing with positionals. An
+/// array of strings gives the option once per string.
+fn read_config_file(config_file: &path::Path) -> Result<(Vec<String>, Option<TileSize>), clap::Error> {
+    let invalid = |message: String| {
+        usage_error(ErrorKind::ValueValidation, format!("Invalid config file {}: {}", config_file.display(), message))
+    };
+
+    let contents = fs::read_to_string(config_file).map_err(|err| {
+        usage_error(ErrorKind::Io, format!("Could not read config file {}: {}", config_file.display(), err))
+    })?;
+    let table: toml::Table = contents.parse().map_err(|err: toml::de::Error| invalid(err.to_string()))?;
+
+    let mut args = Vec::new();
+    let mut tile_size = None;
+
+    for (key, value) in table {
+        let name = key.replace('_', "-");
+
+        match (name.as_str(), value) {
+            ("tile-size", value) => {
+                let size = match &value {
+                    toml::Value::String(size) if size.eq_ignore_ascii_case("auto") => Some(TileSize::Auto),
+                    value => value.as_integer().and_then(|size| u32::try_from(size).ok()).map(TileSize::Pixels),
+                };
+                tile_size = Some(size.ok_or_else(|| invalid(format!("tile-size must be a number or \"auto\", got {}", value)))?);
+            },
+            ("config" | "input" | "output", _) => return Err(invalid(format!("{} can only be given on the command line", name))),
+            (_, toml::Value::Boolean(true)) => args.push(format!("--{}", name)),
+            (_, toml::Value::Boolean(false)) => {},
+            (_, toml::Value::String(value)) => args.push(format!("--{}={}", name, value)),
+            (_, value @ (toml::Value::Integer(_) | toml::Value::Float(_))) => args.push(format!("--{}={}", name, value)),
+            // options given more than once, such as tile-image
+            (_, toml::Value::Array(values)) => {
+                for value in values {
+                    match value {
+                        toml::Value::String(value) => args.push(format!("--{}={}", name, value)),
+                        value => return Err(invalid(format!("{} must list strings, got {}", name, value.type_str()))),
+                    }
+                }
+            },
+            (_, value) => return Err(invalid(format!("{} must be a string, number or boolean, got {}", name, value.type_str()))),
+        }
+    }
+
+    Ok((args, tile_size))
+}
+
+fn exit_usage(err: clap::Error) -> ! {
+    let code = if err.use_stderr() { 1 } else { 0 };
+    let _ = err.print();
+    process::exit(code);
+}
+
+fn run(cli: &Cli, tile_size: u32, input_file: &str, output_file: &str) -> Result<(), MosaicError> {
+    let (config, downloader) = prepare(cli, tile_size, !cli.quiet)?;
+
+    make_mosaic(cli, &config, &downloader, input_file, output_file)
+}
+
+/// Makes the mosaic of every `(input, output)` pair of a glob INPUT or an
+/// --input-list, returning
+/// the number of files that failed.
+fn run_batch(cli: &Cli, tile_size: u32, files: &[(String, String)]) -> Result<usize, MosaicError> {
+    // bars of files rendered side by side would overwrite each other
+    let (config, downloader) = prepare(cli, tile_size, !cli.quiet && cli.jobs == 1)?;
+    let failed = AtomicUsize::new(0);
+
+    let make = |(input_file, output_file): &(String, String)| match make_mosaic(cli, &config, &downloader, input_file, output_file) {
+        Ok(()) => {
+            if !cli.quiet && !cli.dry_run {
+                eprintln!("{} -> {}", input_file, output_file);
+            }
+            Ok(())
+        },
+        Err(err) => {
+            eprintln!("{}: {}", input_file, error_report(&err, cli.verbose_errors));
+            failed.fetch_add(1, Ordering::Relaxed);
+            if cli.fail_fast { Err(()) } else { Ok(()) }
+        },
+    };
+
+    // an error only stops files not started yet, those already rendering finish
+    let _ = ThreadPoolBuilder::new()
+        .num_threads(cli.jobs as usize)
+        .build()?
+        .install(|| files.par_iter().with_max_len(1).try_for_each(make));
+
+    let failed = failed.into_inner();
+
+    if failed > 0 {
+        eprintln!("{} of {} files failed", failed, files.len());
+    }
+
+    Ok(failed)
+}
+
+/// Builds the mosaic settings and the downloader shared by every input.
+fn prepare(cli: &Cli, tile_size: u32, progress: bool) -> Result<(MosaicConfig, Downloader), MosaicError> {
+    // http client for URL inputs
+    let downloader = Downloader::new(cli)?;
+
+    // validate the mosaic settings
+    let mut config = settings(cli, tile_size, progress);
+
+    // pixelation draws no tiles, do not even load them
+    if cli.mode == RenderMode::Pixelate {
+        if !cli.tile_image.is_empty() || cli.tile_dir.is_some() {
+            eprintln!("Ignoring --tile-image and --tile-dir, --mode pixelate draws no tiles");
+        }
+
+        return Ok((config.build()?, downloader));
+    }
+
+    // validate tile file, defaulting to the input itself
+    if !cli.tile_image.is_empty() && cli.pattern != TilePattern::Image {
+        eprintln!("Ignoring --tile-image, --pattern {} draws the tile itself", format!("{:?}", cli.pattern).to_lowercase());
+    } else {
+        for tile_file in &cli.tile_image {
+            config = config.tile_image(load_input(tile_file, &downloader, !cli.no_auto_orient)?);
+        }
+    }
+
+    // load the photomosaic tiles
+    if let Some(tile_dir) = &cli.tile_dir {
+        let library_filter = cli.filter.unwrap_or(FilterType::Triangle);
+        let is_zip = tile_dir.is_file() && tile_dir.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("zip"));
+
+        if is_zip && cli.cache_dir.is_some() {
+            eprintln!("Ignoring --cache-dir, the tiles of a .zip --tile-dir are not cached");
+        }
+
+        let tile_library = timed("Loading the tile library", || match cli.cache_dir.clone().or_else(|| dirs::cache_dir().map(|cache_dir| cache_dir.join("MosaicMaker"))) {
+            _ if is_zip => {
+                let archive = fs::File::open(tile_dir).map_err(|source| MosaicError::Read {
+                    path: tile_dir.display().to_string(),
+                    source,
+                })?;
+                TileLibrary::from_zip(io::BufReader::new(archive), tile_size, library_filter)
+            },
+            Some(cache_dir) => {
+                debug!("Caching resized tiles in {}", cache_dir.display());
+                TileLibrary::from_dir_cached(tile_dir, tile_size, library_filter, &cache_dir)
+            },
+            None => TileLibrary::from_dir(tile_dir, tile_size, library_filter),
+        })?;
+        info!("Loaded {} tiles from {}", tile_library.len(), tile_dir.display());
+        config = config.tile_library(tile_library);
+    }
+
+    Ok((config.build()?, downloader))
+}
+
+/// The mosaic settings of the command line, without the tiles to load.
+fn settings(cli: &Cli, tile_size: u32, progress: bool) -> MosaicConfigBuilder {
+    let mut config = MosaicConfig::new(tile_size)
+        .alpha(cli.alpha)
+        .tile_opacity(cli.tile_opacity)
+        .preserve_tile_aspect(cli.preserve_tile_aspect)
+        .repeat_tile_scale(cli.repeat_tile_scale)
+        .blend(cli.blend)
+        .brightness(cli.brightness)
+        .contrast(cli.contrast)
+        .grayscale(cli.grayscale)
+        .grayscale_tile(cli.grayscale_tile)
+        .gray_method(cli.gray_method)
+        .edges(cli.edges)
+        .vignette(cli.vignette)
+        .adaptive_threshold(cli.adaptive_threshold)
+        .invert(cli.invert)
+        .respect_alpha(cli.respect_alpha)
+        .repeat_limit(cli.repeat_limit)
+        .rotate_tiles(cli.rotate_tiles)
+        .tile_dither(cli.tile_dither)
+        .tint_from_tile(cli.tint_from_tile)
+        .self_tile(cli.self_tile)
+        .match_space(cli.match_space)
+        .seed(cli.seed)
+        .mode(cli.mode)
+        .tile_shape(cli.tile_shape)
+        .pattern(cli.pattern)
+        .pattern_color(cli.pattern_color)
+        .checker_color(cli.checker_color)
+        .checker_size(cli.checker_size)
+        .layout(cli.layout)
+        .antialias(cli.antialias)
+        .gap(cli.gap)
+        .gap_color(cli.gap_color)
+        .border(cli.border)
+        .border_color(cli.border_color)
+        .rotate(cli.rotate)
+        .flip_horizontal(cli.flip_h)
+        .flip_vertical(cli.flip_v)
+        .max_side(cli.max_side)
+        .max_pixels(cli.max_pixels)
+        .bit_depth(cli.bit_depth);
+
+    if cli.linear_blend {
+        config = config.linear_blend(cli.gamma);
+    }
+
+    if let Some(threads) = cli.threads {
+        config = config.threads(threads);
+    }
+
+    if let Some(crop) = cli.crop {
+        config = config.crop(crop);
+    }
+
+    if let Some(grid) = cli.grid {
+        config = config.grid(grid).sample(cli.sample);
+    }
+
+    if let Some((width, height)) = cli.output_size {
+        config = config.output_size(width, height).fit(cli.fit);
+    }
+
+    if cli.skip_transparent {
+        config = config.skip_transparent(cli.alpha_threshold);
+    }
+
+    if cli.auto_levels {
+        config = config.auto_levels(cli.levels_clip);
+    }
+
+    if let Some(ramp) = &cli.recolor {
+        config = config.color_ramp(ramp.clone());
+    }
+
+    if cli.tile_size == Some(TileSize::Auto) {
+        config = config.auto_tile_size(cli.auto_side);
+    }
+
+    if cli.adaptive {
+        config = config.adaptive(cli.min_tile, cli.max_tile);
+    }
+
+    match (&cli.palette_file, cli.palette) {
+        (Some(palette), _) => config = config.palette(palette.clone()),
+        (None, Some(size)) => config = config.adaptive_palette(size),
+        (None, None) if cli.dither => eprintln!("Ignoring --dither, it only applies with --palette or --palette-file"),
+        (None, None) => {},
+    }
+    config = config.dither(cli.dither);
+
+    if cli.antialias > 1 && cli.tile_shape == TileShape::Square && cli.layout == Layout::Square {
+        eprintln!("Ignoring --aa, it only applies to --tile-shape circle or rounded and --layout hex");
+    }
+
+    if cli.tint_from_tile > 0.0 && cli.tile_dir.is_none() && !cli.self_tile {
+        eprintln!("Ignoring --tint-from-tile, it only applies to --tile-dir and --self-tile");
+    }
+
+    // progress goes to stderr so stdout stays clean for "-"
+    if progress {
+        let progress = ProgressBar::new(0).with_style(
+            ProgressStyle::with_template("{bar:40} {percent}% ETA {eta}").expect("progress template is valid"),
+        );
+        config = config.on_progress(move |done, total| match done {
+            0 => {
+                progress.reset();
+                progress.set_length(u64::from(total));
+            },
+            _ if done == total => progress.finish_and_clear(),
+            _ => progress.set_position(u64::from(done)),
+        });
+    }
+
+    if let Some(filter) = cli.filter {
+        config = config.filter(filter);
+    }
+
+    config
+}
+
+fn make_mosaic(cli: &Cli, config: &MosaicConfig, downloader: &Downloader, input_file: &str, output_file: &str) -> Result<(), MosaicError> {
+    let start = Instant::now();
+    let report = |input_size: (u32, u32), output_size: (u32, u32), format: ImageFormat, bytes: Option<u64>| match &cli.report {
+        Some(report_file) => {
+            let name = path::Path::new(input_file).file_stem().map(|name| name.to_string_lossy()).unwrap_or_default();
+            let report = Report {
+                input: input_file,
+                input_width: input_size.0,
+                input_height: input_size.1,
+                // an output size or adaptive tiles leave no single tile size to tell
+                tile_size: (cli.output_size.is_none() && !cli.adaptive).then(|| mosaic_tile_size(input_size, config).ok()).flatten(),
+                output: output_file,
+                output_width: output_size.0,
+                output_height: output_size.1,
+                format: format!("{:?}", format).to_lowercase(),
+                bytes,
+                elapsed_seconds: start.elapsed().as_secs_f64(),
+            };
+            write_report(&report, &report_file.replace("{name}", &name), cli)
+        },
+        None => Ok(()),
+    };
+
+    // validate input file
+    let input_bytes = timed("Reading the input", || read_input(input_file, downloader))?;
+    debug!("Read {} bytes", input_bytes.len());
+
+    // report the size the mosaic would have without rendering it
+    if cli.dry_run {
+        let img_orig = decode_source(&input_bytes, input_file, cli)?;
+        let (width, height) = mosaic_dimensions(img_orig.dimensions(), config)?;
+        let mut bytes = estimate_output_bytes(img_orig.dimensions(), config);
+
+        // the estimate cannot tell a 16 bit source by its size
+        if cli.bit_depth == BitDepth::Auto && cli.bit_depth.bits(&img_orig) == 16 {
+            bytes *= 2;
+        }
+
+        println!("{}x{} pixels, about {:.1} MiB in memory", width, height, bytes as f64 / (1024.0 * 1024.0));
+        return Ok(());
+    }
+
+    // a missing directory fails before rendering in vain, not when saving
+    if output_file != "-" {
+        prepare_dir(output_file, cli)?;
+    }
+
+    // animated GIF in, animated GIF out
+    if cli.format.is_none() && cli.frame.is_none() && is_gif(output_file) && image::guess_format(&input_bytes).ok() == Some(ImageFormat::Gif) {
+        // refused rather than dropped, as they only apply to a single image
+        let unsupported = [("--quality", cli.quality.is_some()), ("--background", cli.background.is_some()), ("--gray-output", cli.gray_output), ("--embed-params", cli.embed_params)];
+        if let Some((option, _)) = unsupported.into_iter().find(|&(_, given)| given) {
+            return Err(MosaicError::UnsupportedWithAnimation(option));
+        }
+
+        info!("Making an animated GIF mosaic, frame by frame");
+        if cli.sharpen.is_some() || cli.preview.is_some() || cli.dump_grid.is_some() || cli.hue_shift != 0.0 || cli.saturation != 1.0 || cli.preserve_icc {
+            eprintln!("Ignoring --sharpen, --preview, --dump-grid, --hue-shift, --saturation and --preserve-icc, they do not apply to animated GIF output");
+        }
+        let gif = timed("Rendering and encoding the frames", || build_gif_mosaic(&input_bytes, config))?;
+        timed("Writing the output", || write_output(&gif, output_file, cli))?;
+
+        let input_size = image::io::Reader::new(io::Cursor::new(&input_bytes))
+            .with_guessed_format()?
+            .into_dimensions()
+            .map_err(|source| MosaicError::Decode {
+                input: input_file.to_string(),
+                source,
+            })?;
+        return report(input_size, mosaic_dimensions(input_size, config)?, ImageFormat::Gif, Some(gif.len() as u64));
+    }
+
+    let img_orig = timed("Decoding the input", || decode_source(&input_bytes, input_file, cli))?;
+    let (width, height) = mosaic_dimensions(img_orig.dimensions(), config)?;
+    let tile_size = mosaic_tile_size(img_orig.dimensions(), config)?;
+
+    if cli.tile_size == Some(TileSize::Auto) && !cli.quiet {
+        eprintln!("Using a tile size of {} for {}", tile_size, input_file);
+    }
+    info!("Decoded a {}x{} image, the mosaic will be {}x{}", img_orig.width(), img_orig.height(), width, height);
+
+    let name = path::Path::new(input_file).file_stem().map(|name| name.to_string_lossy()).unwrap_or_default();
+    if let Some(grid_file) = &cli.dump_grid {
+        timed("Writing the color grid", || write_grid(&img_orig, config, &grid_file.replace("{name}", &name), cli))?;
+    }
+
+    // render straight into the PNG, never holding the whole mosaic
+    if cli.streaming {
+        timed("Rendering and streaming the PNG", || match output_file {
+            "-" => stream_mosaic_png(&img_orig, config, io::BufWriter::new(io::stdout())),
+            _ => {
+                let mut partial = PartialFile::create(output_file, cli)?;
+                stream_mosaic_png(&img_orig, config, io::BufWriter::new(&mut partial.file))?;
+                partial.commit(cli)
+            },
+        })?;
+
+        // nothing counts the bytes streamed to stdout
+        let bytes = if output_file == "-" { None } else { fs::metadata(output_file).ok().map(|metadata| metadata.len()) };
+        return report(img_orig.dimensions(), (width, height), ImageFormat::Png, bytes);
+    }
+
+    // build the mosaic
+    let img_new = timed("Rendering", || build_mosaic(&img_orig, config))?;
+
+    // read from the input as it came, decoding drops it
+    let icc_profile = cli.preserve_icc.then(|| icc_profile(&input_bytes)).flatten();
+    if cli.preserve_icc && icc_profile.is_none() {
+        eprintln!("Ignoring --preserve-icc, {} has no ICC profile that can be read", input_file);
+    }
+
+    let (format, bytes) = save_output(&img_new, output_file, embedded_params(cli, tile_size, input_file), icc_profile, cli)?;
+
+    if let Some(preview_file) = &cli.preview {
+        timed("Writing the preview", || write_preview(&img_new, &preview_file.replace("{name}", &name), cli))?;
+    }
+
+    report(img_orig.dimensions(), img_new.dimensions(), format, Some(bytes))
+}
+
+/// The summary --report writes once OUTPUT is saved, for scripts to check.
+#[derive(Serialize)]
+struct Report<'a> {
+    input: &'a str,
+    input_width: u32,
+    input_height: u32,
+    tile_size: Option<u32>,
+    output: &'a str,
+    output_width: u32,
+    output_height: u32,
+    format: String,
+    bytes: Option<u64>,
+    elapsed_seconds: f64,
+}
+
+fn write_report(report: &Report, report_file: &str, cli: &Cli) -> Result<(), MosaicError> {
+    let json = serde_json::to_string_pretty(report).expect("a report always serializes");
+    info!("Writing the report to {}", report_file);
+    prepare_dir(report_file, cli)?;
+
+    fs::write(report_file, json + "\n").map_err(|source| MosaicError::Save {
+        path: report_file.to_string(),
+        source,
+    })
+}
+
+/// Writes `img` scaled down to at most [`PREVIEW_SIDE`] per side as a JPEG.
+fn write_preview(img: &DynamicImage, preview_file: &str, cli: &Cli) -> Result<(), MosaicError> {
+    // mosaics small enough already are only converted
+    let resized;
+    let preview = if img.width().max(img.height()) > PREVIEW_SIDE {
+        resized = img.resize(PREVIEW_SIDE, PREVIEW_SIDE, FilterType::Triangle);
+        &resized
+    } else {
+        img
+    };
+
+    let options = EncodeOptions {
+        background: cli.background.unwrap_or(DEFAULT_BACKGROUND),
+        ..EncodeOptions::default()
+    };
+    let bytes = encode_mosaic(preview, ImageFormat::Jpeg, &options)?;
+    info!("Writing a {}x{} preview to {}", preview.width(), preview.height(), preview_file);
+
+    write_file(&bytes, preview_file, cli)
+}
+
+/// Writes the color grid of the mosaic of `img`, in the format of the
+/// extension of `grid_file`, PNG for an unknown one.
+fn write_grid(img: &DynamicImage, config: &MosaicConfig, grid_file: &str, cli: &Cli) -> Result<(), MosaicError> {
+    let grid = color_grid(img, config)?;
+
+    let options = EncodeOptions {
+        background: cli.background.unwrap_or(DEFAULT_BACKGROUND),
+        ..EncodeOptions::default()
+    };
+    let bytes = encode_mosaic(&grid, ImageFormat::from_path(grid_file).unwrap_or(ImageFormat::Png), &options)?;
+    info!("Writing the {}x{} color grid to {}", grid.width(), grid.height(), grid_file);
+
+    write_file(&bytes, grid_file, cli)
+}
+
+fn is_gif(output_file: &str) -> bool {
+    ImageFormat::from_path(output_file).ok() == Some(ImageFormat::Gif)
+}
+
+/// The settings --embed-params writes into a PNG, to tell later how it was made.
+fn embedded_params(cli: &Cli, tile_size: u32, input_file: &str) -> Vec<(String, String)> {
+    if !cli.embed_params {
+        return Vec::new();
+    }
+
+    let size = match cli.output_size {
+        Some((width, height)) => ("Output Size", format!("{}x{}", width, height)),
+        None => ("Tile Size", tile_size.to_string()),
+    };
+    let source = if input_file == "-" { "stdin" } else { input_file };
+
+    vec![
+        ("Software".to_string(), format!("MosaicMaker {}", env!("CARGO_PKG_VERSION"))),
+        (size.0.to_string(), size.1),
+        ("Alpha".to_string(), cli.alpha.to_string()),
+        ("Blend Mode".to_string(), format!("{:?}", cli.blend).to_lowercase()),
+        ("Source".to_string(), source.to_string()),
+    ]
+}
+
+/// Encodes and writes `img`, returning the format and the number of bytes written.
+fn save_output(img: &DynamicImage, output_file: &str, text: Vec<(String, String)>, icc_profile: Option<Vec<u8>>, cli: &Cli) -> Result<(ImageFormat, u64), MosaicError> {
+    let (background, quality, lossless, gray) = (cli.background, cli.quality, cli.lossless, cli.gray_output);
+
+    let format = match cli.format {
+        Some(format) => format,
+        None => output_format(path::Path::new(output_file))?,
+    };
+
+    if lossless && format != ImageFormat::WebP {
+        eprintln!("Ignoring --lossless, it only applies to WebP output");
+    }
+
+    if gray && format != ImageFormat::Png {
+        eprintln!("Ignoring --gray-output, it only applies to PNG output");
+    } else if gray && !is_gray(img) {
+        eprintln!("Writing the colors of the mosaic as gray, render with --grayscale and --grayscale-tile to keep them");
+    }
+
+    if icc_profile.is_some() && !matches!(format, ImageFormat::Png | ImageFormat::Jpeg) {
+        eprintln!("Ignoring --preserve-icc, it only applies to PNG and JPEG output");
+    }
+
+    if quality.is_some() && format != ImageFormat::Jpeg && (format != ImageFormat::WebP || lossless) {
+        eprintln!("Ignoring --quality, it only applies to JPEG and lossy WebP output");
+    }
+
+    let sixteen_bit = matches!(img, DynamicImage::ImageRgba16(_));
+    if sixteen_bit && !matches!(format, ImageFormat::Png | ImageFormat::Tiff) && cli.bit_depth == BitDepth::Sixteen {
+        eprintln!("Writing {} with 8 bits per channel, --bit-depth 16 only applies to PNG and TIFF output", format!("{:?}", format).to_uppercase());
+    }
+
+    // shifted before flattening so the background keeps its color
+    let shifted;
+    let img = if cli.hue_shift != 0.0 || cli.saturation != 1.0 {
+        shifted = timed("Shifting the colors", || shift_colors(img, cli.hue_shift, cli.saturation));
+        &shifted
+    } else {
+        img
+    };
+
+    // an explicit background is applied to every format, not only those without alpha
+    let flattened;
+    let img = match background {
+        Some(background) => {
+            flattened = if sixteen_bit {
+                DynamicImage::ImageRgb16(flatten16(img, background))
+            } else {
+                DynamicImage::ImageRgb8(flatten(img, background))
+            };
+            &flattened
+        },
+        None => img,
+    };
+    let options = EncodeOptions {
+        background: background.unwrap_or(DEFAULT_BACKGROUND),
+        quality,
+        lossless,
+        gray,
+        text,
+        icc_profile,
+    };
+
+    // sharpened after flattening, transparent edges would ring otherwise
+    let sharpened;
+    let img = match cli.sharpen {
+        Some(sigma) => {
+            sharpened = timed("Sharpening", || sharpen(img, sigma));
+            &sharpened
+        },
+        None => img,
+    };
+
+    // encode up front so nothing half-written reaches the output on failure
+    let bytes = timed("Encoding", || encode_mosaic(img, format, &options))?;
+    info!("Writing {} bytes of {:?} to {}", bytes.len(), format, output_file);
+
+    timed("Writing the output", || write_output(&bytes, output_file, cli))?;
+
+    Ok((format, bytes.len() as u64))
+}
+
+fn write_output(bytes: &[u8], output_file: &str, cli: &Cli) -> Result<(), MosaicError> {
+    if output_file != "-" {
+        return write_file(bytes, output_file, cli);
+    }
+
+    let stdout = io::stdout();
+    let mut handle = stdout.lock();
+    handle.write_all(bytes)?;
+    handle.flush()?;
+
+    Ok(())
+}
+
+fn write_file(bytes: &[u8], output_file: &str, cli: &Cli) -> Result<(), MosaicError> {
+    let mut partial = PartialFile::create(output_file, cli)?;
+    partial.file.write_all(bytes).map_err(|source| partial.save_error(source))?;
+
+    partial.commit(cli)
+}
+
+/// A file written next to OUTPUT under a temporary name and only moved into
+/// place once complete, so OUTPUT is either the whole mosaic or what it was
+/// before, never a truncated file. Dropped before [`commit`](Self::commit),
+/// the temporary file is removed.
+struct PartialFile {
+    file: fs::File,
+    path: path::PathBuf,
+    output_file: String,
+}
+
+impl PartialFile {
+    fn create(output_file: &str, cli: &Cli) -> Result<PartialFile, MosaicError> {
+        // checked again when committing, this only saves rendering in vain
+        if cli.no_clobber && path::Path::new(output_file).exists() {
+            return Err(MosaicError::OutputExists(output_file.to_string()));
+        }
+
+        prepare_dir(output_file, cli)?;
+
+        // the same directory keeps the final rename on one file system
+        let output = path::Path::new(output_file);
+        let name = output.file_name().map(|name| name.to_string_lossy()).unwrap_or_default();
+        let path = output.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+
+        let file = fs::File::create(&path).map_err(|source| MosaicError::Save {
+            path: output_file.to_string(),
+            source,
+        })?;
+        PARTIAL_FILES.lock().expect("no partial file panics while holding the list").push(path.clone());
+
+        Ok(PartialFile {
+            file,
+            path,
+            output_file: output_file.to_string(),
+        })
+    }
+
+    fn save_error(&self, source: io::Error) -> MosaicError {
+        MosaicError::Save {
+            path: self.output_file.clone(),
+            source,
+        }
+    }
+
+    /// Moves the complete file to OUTPUT.
+    fn commit(self, cli: &Cli) -> Result<(), MosaicError> {
+        self.file.sync_all().map_err(|source| self.save_error(source))?;
+
+        // a hard link fails if OUTPUT exists, where a rename would replace it
+        if cli.no_clobber {
+            return fs::hard_link(&self.path, &self.output_file).map_err(|err| match err.kind() {
+                io::ErrorKind::AlreadyExists => MosaicError::OutputExists(self.output_file.clone()),
+                _ => self.save_error(err),
+            });
+        }
+
+        if !cli.force && path::Path::new(&self.output_file).exists() {
+            eprintln!("Overwriting {}", self.output_file);
+        }
+
+        fs::rename(&self.path, &self.output_file).map_err(|source| self.save_error(source))
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        // gone already once renamed, the hard link of --no-clobber leaves it
+        let _ = fs::remove_file(&self.path);
+        PARTIAL_FILES.lock().expect("no partial file panics while holding the list").retain(|path| *path != self.path);
+    }
+}
+
+/// Creates the directory `file` goes in with --mkdir, or fails with
+/// [`MosaicError::OutputDirMissing`] if it does not exist, instead of the
+/// bare error of creating the file.
+fn prepare_dir(file: &str, cli: &Cli) -> Result<(), MosaicError> {
+    let Some(dir) = path::Path::new(file).parent().filter(|dir| !dir.as_os_str().is_empty() && !dir.is_dir()) else {
+        return Ok(());
+    };
+
+    if !cli.mkdir {
+        return Err(MosaicError::OutputDirMissing(dir.display().to_string()));
+    }
+
+    info!("Creating the directory {}", dir.display());
+    fs::create_dir_all(dir).map_err(|source| MosaicError::Save {
+        path: file.to_string(),
+        source,
+    })
+}
+
+/// Removes the temporary files of every [`PartialFile`] not committed yet.
+fn remove_partial_files() {
+    if let Ok(paths) = PARTIAL_FILES.lock() {
+        for path in paths.iter() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn load_input(input_file: &str, downloader: &Downloader, auto_orient: bool) -> Result<DynamicImage, MosaicError> {
+    decode_bytes(&read_input(input_file, downloader)?, input_file, auto_orient)
+}
+
+fn read_input(input_file: &str, downloader: &Downloader) -> Result<Vec<u8>, MosaicError> {
+    if input_file == "-" {
+        info!("Reading the input from stdin");
+        let mut image_from_stdin_bytes = Vec::new();
+        io::stdin().lock().read_to_end(&mut image_from_stdin_bytes)?;
+
+        return Ok(image_from_stdin_bytes);
+    }
+
+    // a mistyped file name is reported as such instead of being downloaded
+    if !is_url(input_file) {
+        if !path::Path::new(input_file).exists() {
+            return Err(MosaicError::InputNotFound(input_file.to_string()));
+        }
+
+        info!("Reading the input from file {}", input_file);
+        return fs::read(input_file).map_err(|source| MosaicError::Read {
+            path: input_file.to_string(),
+            source,
+        });
+    }
+
+    info!("Downloading the input from URL {}", input_file);
+    downloader.fetch(input_file)
+}
+
+/// Whether `input_file` is an http or https URL rather than a path.
+fn is_url(input_file: &str) -> bool {
+    let scheme = input_file.split_once("://").map(|(scheme, _)| scheme.to_ascii_lowercase());
+    matches!(scheme.as_deref(), Some("http" | "https"))
+}
+
+/// Fetches URL inputs, retrying transient failures.
+#[cfg(feature = "url")]
+struct Downloader {
+    client: Client,
+    headers: header::HeaderMap,
+    retries: u32,
+    max_bytes: u64,
+}
+
+#[cfg(feature = "url")]
+impl Downloader {
+    fn new(cli: &Cli) -> Result<Downloader, MosaicError> {
+        let client = Client::builder()
+            .connect_timeout(Duration::from_secs(cli.timeout_secs))
+            .timeout(Duration::from_secs(cli.timeout_secs))
+            .user_agent(USER_AGENT)
+            .redirect(redirect::Policy::limited(10))
+            .build()?;
+
+        Ok(Downloader {
+            client,
+            headers: cli.header.iter().cloned().collect(),
+            retries: cli.retries,
+            max_bytes: cli.max_download_bytes,
+        })
+    }
+
+    /// Downloads the image at `url`, checking that an image is what came back.
+    fn fetch(&self, url: &str) -> Result<Vec<u8>, MosaicError> {
+        let img_from_url = self.get(url)?;
+
+        // redirects have already been followed, anything else but 2xx is an error
+        if !img_from_url.status().is_success() {
+            return Err(MosaicError::HttpStatus {
+                url: url.to_string(),
+                status: img_from_url.status().as_u16(),
+            });
+        }
+
+        let content_type = img_from_url
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|content_type| content_type.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string();
+        debug!("{} answered {}, Content-Type: {}", img_from_url.url(), img_from_url.status(), content_type);
+
+        let content_length = img_from_url.content_length();
+        let image_from_url_bytes = read_capped(url, img_from_url, content_length, self.max_bytes)?;
+        debug!("Downloaded {} bytes", image_from_url_bytes.len());
+
+        // sniff the magic number, only trusting the server for formats without one
+        if image::guess_format(&image_from_url_bytes).is_err() && !content_type.starts_with("image/") {
+            return Err(MosaicError::NotAnImage {
+                url: url.to_string(),
+                content_type,
+            });
+        }
+
+        Ok(image_from_url_bytes)
+    }
+
+    /// GETs `url`, retrying connection errors, timeouts and 5xx or 429 answers
+    /// up to `retries` times with exponential backoff. Other answers, 404
+    /// included, are returned as they are.
+    fn get(&self, url: &str) -> Result<Response, MosaicError> {
+        let mut attempt = 0;
+
+        loop {
+            let err = match self.client.get(url).headers(self.headers.clone()).send() {
+                Ok(response) if response.status().is_server_error() || response.status() == StatusCode::TOO_MANY_REQUESTS => {
+                    MosaicError::HttpStatus {
+                        url: url.to_string(),
+                        status: response.status().as_u16(),
+                    }
+                },
+                Ok(response) => return Ok(response),
+                Err(err) if err.is_connect() || err.is_timeout() => MosaicError::Download(err),
+                Err(err) => return Err(MosaicError::Download(err)),
+            };
+
+            if attempt >= self.retries {
+                return Err(err);
+            }
+
+            let delay = Duration::from_millis(500 << attempt.min(10));
+            attempt += 1;
+
+            eprintln!("{}, retrying in {:.1}s ({}/{})", err, delay.as_secs_f32(), attempt, self.retries);
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// Reads the `body` of the download of `url`, refusing it up front if its
+/// `content_length` is over `max_bytes`, or once it sends more otherwise.
+#[cfg(feature = "url")]
+fn read_capped(url: &str, body: impl Read, content_length: Option<u64>, max_bytes: u64) -> Result<Vec<u8>, MosaicError> {
+    let too_large = || MosaicError::DownloadTooLarge {
+        url: url.to_string(),
+        max_bytes,
+    };
+
+    if content_length.is_some_and(|length| length > max_bytes) {
+        return Err(too_large());
+    }
+
+    // one byte over the limit tells a body of exactly max_bytes from a larger one
+    let mut bytes = Vec::new();
+    body.take(max_bytes.saturating_add(1)).read_to_end(&mut bytes).map_err(|source| MosaicError::Read {
+        path: url.to_string(),
+        source,
+    })?;
+
+    if bytes.len() as u64 > max_bytes {
+        return Err(too_large());
+    }
+
+    Ok(bytes)
+}
+
+/// Stands in for the downloader in builds without URL support.
+#[cfg(not(feature = "url"))]
+struct Downloader;
+
+#[cfg(not(feature = "url"))]
+impl Downloader {
+    fn new(_cli: &Cli) -> Result<Downloader, MosaicError> {
+        Ok(Downloader)
+    }
+
+    fn fetch(&self, url: &str) -> Result<Vec<u8>, MosaicError> {
+        Err(MosaicError::UrlSupportDisabled { url: url.to_string() })
+    }
+}
+
+/// Decodes the input to tile, with --low-mem only down to its --grid cells.
+fn decode_source(bytes: &[u8], input_name: &str, cli: &Cli) -> Result<DynamicImage, MosaicError> {
+    let auto_orient = !cli.no_auto_orient;
+
+    if let Some(frame) = cli.frame {
+        let img = decode_frame(bytes, input_name, frame)?;
+
+        return match exif_orientation(bytes).filter(|_| auto_orient) {
+            Some(orientation) => Ok(apply_orientation(img, orientation)),
+            None => Ok(img),
+        };
+    }
+
+    let Some(grid) = cli.grid.filter(|_| cli.low_mem) else {
+        return decode_bytes(bytes, input_name, auto_orient);
+    };
+
+    if cli.sample == SampleMode::Point {
+        debug!("Averaging the --grid cells, --low-mem never holds the input to resize it");
+    }
+
+    // an exact grid is meant for the upright, --rotate'd input, so turn it along with the input
+    let orientation = exif_orientation(bytes).filter(|_| auto_orient);
+    let sideways = matches!(orientation, Some(5..=8)) != matches!(cli.rotate, 90 | 270);
+    let grid = match grid {
+        Grid::Exact(width, height) if sideways => Grid::Exact(height, width),
+        _ => grid,
+    };
+    let img = decode_downsampled(bytes, input_name, grid)?;
+
+    match orientation {
+        Some(orientation) => Ok(apply_orientation(img, orientation)),
+        None => Ok(img),
+    }
+}
+
+fn decode_bytes(bytes: &[u8], input_name: &str, auto_orient: bool) -> Result<DynamicImage, MosaicError> {
+    let img = decode_image(bytes, input_name)?;
+
+    // phone photos are stored sideways with an EXIF tag saying how to turn them
+    match exif_orientation(bytes) {
+        Some(orientation) if auto_orient => Ok(apply_orientation(img, orientation)),
+        _ => Ok(img),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completions_are_written_for_every_shell() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut script = Vec::new();
+            write_completions(shell, &mut script);
+
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("--tile-size"), "{:?} completes no options", shell);
+        }
+    }
+
+    #[test]
+    fn input_lists_skip_blank_lines_and_comments() {
+        let contents = "# holiday photos\n./beach.jpg\n\n  https://example.com/photos/sunset.png?size=large#top  \n\t# done\n";
+        let files = input_list_files(contents, "sources.txt", "out/{name}.png").unwrap();
+
+        assert_eq!(files, [
+            ("./beach.jpg".to_string(), "out/beach.png".to_string()),
+            ("https://example.com/photos/sunset.png?size=large#top".to_string(), "out/sunset.png".to_string()),
+        ]);
+
+        // the outputs must tell the inputs apart
+        assert!(input_list_files(contents, "sources.txt", "out/mosaic.png").is_err());
+        assert!(input_list_files("./a/beach.jpg\n./b/beach.jpg\n", "sources.txt", "out/{name}.png").is_err());
+        assert!(input_list_files("# nothing yet\n", "sources.txt", "out/{name}.png").is_err());
+    }
+
+    #[test]
+    fn missing_output_directories_are_created_with_mkdir() {
+        let dir = env::temp_dir().join(format!("mosaic-maker-{}-mkdir", process::id()));
+        let output_file = dir.join("sub/mosaic.png").display().to_string();
+        let cli = |args: &[&str]| Cli::parse_from(["mosaicmaker"].iter().chain(args).chain(&["4", "in.png", &output_file]));
+
+        let err = write_file(b"mosaic", &output_file, &cli(&[])).unwrap_err();
+        assert!(matches!(err, MosaicError::OutputDirMissing(ref missing) if missing.ends_with("sub")), "{}", err);
+
+        write_file(b"mosaic", &output_file, &cli(&["--mkdir"])).unwrap();
+        assert_eq!(fs::read(&output_file).unwrap(), b"mosaic");
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(feature = "url")]
+    #[test]
+    fn oversized_downloads_are_refused() {
+        let url = "https://example.com/huge.png";
+        let body = || io::Cursor::new(vec![0; 1024]);
+        let too_large = |result| matches!(result, Err(MosaicError::DownloadTooLarge { max_bytes: 1000, .. }));
+
+        // announced up front, or only found out while reading a body without a length
+        assert!(too_large(read_capped(url, io::empty(), Some(1024), 1000)));
+        assert!(too_large(read_capped(url, body(), None, 1000)));
+
+        assert_eq!(read_capped(url, body(), Some(1024), 1024).unwrap().len(), 1024);
+        assert_eq!(read_capped(url, body(), None, 1024).unwrap().len(), 1024);
+    }
+
+    #[test]
+    fn batch_outputs_in_a_dir_are_numbered_one_by_one() {
+        let dir = env::temp_dir().join(format!("mosaic-maker-{}-numbered", process::id()));
+        let (inputs, outputs) = (dir.join("in"), dir.join("out"));
+        for path in [&inputs, &outputs] {
+            fs::create_dir_all(path).unwrap();
+        }
+        for input in ["beach.png", "sunset.png"] {
+            fs::write(inputs.join(input), b"").unwrap();
+        }
+        fs::write(outputs.join("beach_mosaic_t4.png"), b"").unwrap();
+
+        let glob = inputs.join("*.png").display().to_string();
+        let mut cli = Cli::parse_from(["mosaicmaker", "--output-dir", &outputs.display().to_string(), "--no-clobber", "4", &glob]);
+        let (_, input_file, output_file) = cli.resolve().unwrap();
+
+        // only the outputs of the inputs are numbered, not the {name} template
+        assert!(cli.output_named);
+        let files = number_outputs(batch_files(&input_file, &output_file).unwrap().unwrap());
+        let names: Vec<_> = files.iter().map(|(_, output)| path::Path::new(output).file_name().unwrap().to_string_lossy().into_owned()).collect();
+        assert_eq!(names, ["beach_mosaic_t4_1.png", "sunset_mosaic_t4.png"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
 }
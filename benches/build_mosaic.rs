@@ -0,0 +1,89 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use mosaic_maker::{build_mosaic, DynamicImage, FilterType, MatchSpace, MosaicConfig, Rgba, RgbaImage, TileLibrary};
+
+// a fixed gradient so every run tints the same colors
+fn source(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgba8(RgbaImage::from_fn(width, height, |x, y| {
+        Rgba([(x * 255 / width) as u8, (y * 255 / height) as u8, 128, 255])
+    }))
+}
+
+fn tile_sizes(c: &mut Criterion) {
+    let source = source(64, 64);
+    let mut group = c.benchmark_group("tile_size");
+
+    for tile_size in [4, 16, 32] {
+        let config = MosaicConfig::new(tile_size).build().unwrap();
+
+        group.throughput(Throughput::Elements(u64::from(64 * 64 * tile_size * tile_size)));
+        group.bench_with_input(BenchmarkId::from_parameter(tile_size), &config, |b, config| {
+            b.iter(|| build_mosaic(&source, config).unwrap());
+        });
+    }
+
+    group.finish();
+}
+
+// many tiny cells, where tinting and placing every cell dominates over the
+// one-time tile thumbnail
+fn color_tiles(c: &mut Criterion) {
+    let source = source(512, 512);
+    let config = MosaicConfig::new(2).build().unwrap();
+
+    c.bench_function("color_tiles", |b| b.iter(|| build_mosaic(&source, &config).unwrap()));
+}
+
+// a fixed linear congruential sequence, so every run matches the same colors
+fn random_colors(count: usize) -> Vec<Rgba<u8>> {
+    let mut state = 1u32;
+
+    (0..count)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            let [r, g, b, _] = state.to_le_bytes();
+            Rgba([r, g, b, 255])
+        })
+        .collect()
+}
+
+// finding the nearest of 2000 library tiles by comparing them all against
+// the k-d tree of the matcher
+fn tile_matching(c: &mut Criterion) {
+    let tiles = random_colors(2000).into_iter().map(|color| DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, color)));
+    let library = TileLibrary::new(tiles, 2, FilterType::Nearest).unwrap();
+    let cells = random_colors(1000);
+    let mut group = c.benchmark_group("tile_matching");
+
+    group.throughput(Throughput::Elements(cells.len() as u64));
+    group.bench_function("linear_scan", |b| {
+        b.iter(|| cells.iter().map(|&color| library.nearest_index(color, None, MatchSpace::Rgb)).sum::<usize>());
+    });
+    group.bench_function("kd_tree", |b| {
+        let matcher = library.matcher(None, MatchSpace::Rgb);
+        b.iter(|| cells.iter().map(|&color| matcher.nearest(color)).sum::<usize>());
+    });
+
+    group.finish();
+}
+
+// the 1000x1000 input the rendering threads were added for, on 1 to 8 of them
+fn threads(c: &mut Criterion) {
+    let source = source(1000, 1000);
+    let mut group = c.benchmark_group("threads");
+
+    // every iteration renders 16 million pixels
+    group.sample_size(10);
+
+    for threads in [1, 2, 4, 8] {
+        let config = MosaicConfig::new(4).threads(threads).build().unwrap();
+
+        group.bench_with_input(BenchmarkId::from_parameter(threads), &config, |b, config| {
+            b.iter(|| build_mosaic(&source, config).unwrap());
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, tile_sizes, color_tiles, tile_matching, threads);
+criterion_main!(benches);
@@ -0,0 +1,44 @@
+use std::io::Cursor;
+use image::{
+    codecs::gif::{GifDecoder, GifEncoder, Repeat},
+    AnimationDecoder, DynamicImage, Frame,
+};
+use crate::{build_mosaic, MosaicConfig, MosaicError};
+
+/// Builds a mosaic of every frame of the animated GIF `gif` and encodes them
+/// back into a looping animated GIF with the original frame delays.
+///
+/// Every decoded frame and every mosaic frame is held in memory at once, and
+/// each mosaic frame is `tile_size²` times larger than its source frame, so
+/// long or large animations need a small tile size or a [`Grid`](crate::Grid).
+pub fn build_gif_mosaic(gif: &[u8], config: &MosaicConfig) -> Result<Vec<u8>, MosaicError> {
+    let decode_error = |source| MosaicError::Decode {
+        input: "the GIF input".to_string(),
+        source,
+    };
+
+    let frames = GifDecoder::new(Cursor::new(gif))
+        .map_err(decode_error)?
+        .into_frames()
+        .collect_frames()
+        .map_err(decode_error)?;
+
+    let frames = frames
+        .into_iter()
+        .map(|frame| {
+            let delay = frame.delay();
+            let img_new = build_mosaic(&DynamicImage::ImageRgba8(frame.into_buffer()), config)?;
+
+            Ok(Frame::from_parts(img_new.to_rgba8(), 0, 0, delay))
+        })
+        .collect::<Result<Vec<_>, MosaicError>>()?;
+
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = GifEncoder::new(&mut bytes);
+        encoder.set_repeat(Repeat::Infinite).map_err(MosaicError::Encode)?;
+        encoder.encode_frames(frames).map_err(MosaicError::Encode)?;
+    }
+
+    Ok(bytes)
+}
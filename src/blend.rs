@@ -0,0 +1,104 @@
+use std::str::FromStr;
+use image::{Pixel, Rgba};
+use crate::{color, depth::Channel, MosaicError};
+
+/// How the color tint is composited onto the repeated tile.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BlendMode {
+    /// Plain alpha blending of the tint over the tile.
+    #[default]
+    Normal,
+    /// Darkens the tile toward the tint color.
+    Multiply,
+    /// Lightens the tile toward the tint color.
+    Screen,
+    /// Multiplies the tile's darks and screens its lights, boosting contrast.
+    Overlay,
+}
+
+impl BlendMode {
+    /// Composites `tint` onto `base` in place, `tint`'s alpha giving the
+    /// strength of the effect.
+    pub fn composite(self, base: &mut Rgba<u8>, tint: Rgba<u8>) {
+        self.mix(base, tint);
+    }
+
+    /// Like [`composite`](BlendMode::composite), but mixes the colors in
+    /// linear light, converting with `gamma`, instead of directly in sRGB.
+    pub fn composite_linear(self, base: &mut Rgba<u8>, tint: Rgba<u8>, gamma: f32) {
+        self.mix_linear(base, tint, gamma);
+    }
+
+    /// [`composite`](BlendMode::composite) for any channel depth.
+    pub(crate) fn mix<T: Channel>(self, base: &mut Rgba<T>, tint: Rgba<T>) {
+        if self == BlendMode::Normal {
+            base.blend(&tint);
+            return;
+        }
+
+        // where the base is transparent there is nothing to blend with, so the
+        // tint color shows through unchanged (W3C separable blend modes)
+        let base_alpha = base.0[3].to_unit();
+        let mut mixed = tint;
+
+        for channel in 0..3 {
+            let cb = base.0[channel].to_unit();
+            let cs = tint.0[channel].to_unit();
+            let blended = (1.0 - base_alpha) * cs + base_alpha * self.channel(cb, cs);
+
+            mixed.0[channel] = T::from_unit(blended);
+        }
+
+        base.blend(&mixed);
+    }
+
+    /// [`composite_linear`](BlendMode::composite_linear) for any channel depth.
+    pub(crate) fn mix_linear<T: Channel>(self, base: &mut Rgba<T>, tint: Rgba<T>, gamma: f32) {
+        let base_alpha = base.0[3].to_unit();
+        let tint_alpha = tint.0[3].to_unit();
+        let alpha = tint_alpha + base_alpha * (1.0 - tint_alpha);
+
+        if alpha == 0.0 {
+            return;
+        }
+
+        for channel in 0..3 {
+            let cb = color::to_linear(base.0[channel], gamma);
+            let cs = color::to_linear(tint.0[channel], gamma);
+            let mixed = (1.0 - base_alpha) * cs + base_alpha * self.channel(cb, cs);
+
+            // source over, with the blended color as the source
+            base.0[channel] = color::from_linear((mixed * tint_alpha + cb * base_alpha * (1.0 - tint_alpha)) / alpha, gamma);
+        }
+
+        base.0[3] = T::from_unit(alpha);
+    }
+
+    fn channel(self, cb: f32, cs: f32) -> f32 {
+        match self {
+            BlendMode::Normal => cs,
+            BlendMode::Multiply => cb * cs,
+            BlendMode::Screen => cb + cs - cb * cs,
+            BlendMode::Overlay if cb <= 0.5 => 2.0 * cb * cs,
+            BlendMode::Overlay => 1.0 - 2.0 * (1.0 - cb) * (1.0 - cs),
+        }
+    }
+}
+
+impl FromStr for BlendMode {
+    type Err = MosaicError;
+
+    fn from_str(value: &str) -> Result<BlendMode, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "normal" => Ok(BlendMode::Normal),
+            "multiply" => Ok(BlendMode::Multiply),
+            "screen" => Ok(BlendMode::Screen),
+            "overlay" => Ok(BlendMode::Overlay),
+            _ => Err(MosaicError::InvalidValue {
+                kind: "blend mode",
+                value: value.to_string(),
+                expected: "normal, multiply, screen or overlay",
+            }),
+        }
+    }
+}
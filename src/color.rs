@@ -0,0 +1,216 @@
+use std::str::FromStr;
+use image::Rgba;
+use crate::{depth::Channel, MosaicError};
+
+/// How [`MosaicConfigBuilder::grayscale`](crate::MosaicConfigBuilder::grayscale)
+/// turns a color into a gray value.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GrayMethod {
+    /// Luminance with the Rec. 601 weights, 0.299 R + 0.587 G + 0.114 B.
+    #[default]
+    Rec601,
+    /// Luminance with the Rec. 709 weights of HD video and sRGB,
+    /// 0.2126 R + 0.7152 G + 0.0722 B, darker reds and brighter greens.
+    Rec709,
+    /// The mean of the three channels.
+    Average,
+    /// Halfway between the brightest and the darkest channel.
+    Lightness,
+}
+
+impl GrayMethod {
+    /// The gray value of `pixel`, alpha ignored.
+    pub fn gray(self, pixel: Rgba<u8>) -> u8 {
+        self.gray_of(pixel)
+    }
+
+    pub(crate) fn gray_of<T: Channel>(self, pixel: Rgba<T>) -> T {
+        let [r, g, b, _] = pixel.0.map(Channel::to_f32);
+
+        match self {
+            GrayMethod::Rec601 => luminance_of(pixel),
+            GrayMethod::Rec709 => T::from_f32(0.2126 * r + 0.7152 * g + 0.0722 * b),
+            GrayMethod::Average => T::from_f32((r + g + b) / 3.0),
+            GrayMethod::Lightness => T::from_f32((r.max(g).max(b) + r.min(g).min(b)) / 2.0),
+        }
+    }
+}
+
+impl FromStr for GrayMethod {
+    type Err = MosaicError;
+
+    fn from_str(value: &str) -> Result<GrayMethod, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "rec601" => Ok(GrayMethod::Rec601),
+            "rec709" => Ok(GrayMethod::Rec709),
+            "average" => Ok(GrayMethod::Average),
+            "lightness" => Ok(GrayMethod::Lightness),
+            _ => Err(MosaicError::InvalidValue {
+                kind: "gray method",
+                value: value.to_string(),
+                expected: "rec601, rec709, average or lightness",
+            }),
+        }
+    }
+}
+
+/// Luminance of `pixel` using the Rec. 601 weights, alpha ignored.
+pub fn luminance(pixel: Rgba<u8>) -> u8 {
+    luminance_of(pixel)
+}
+
+/// `pixel` desaturated to its [`luminance`], alpha kept.
+pub fn grayscale(pixel: Rgba<u8>) -> Rgba<u8> {
+    grayscale_of(pixel, GrayMethod::Rec601)
+}
+
+/// `pixel` with its color channels inverted (`255 - channel`), alpha kept.
+pub fn invert(pixel: Rgba<u8>) -> Rgba<u8> {
+    invert_of(pixel)
+}
+
+/// Converts an sRGB `channel` to linear light in `0.0..=1.0`, approximating
+/// the transfer curve with a plain `gamma` power.
+pub fn srgb_to_linear(channel: u8, gamma: f32) -> f32 {
+    to_linear(channel, gamma)
+}
+
+/// Converts a linear light `value` back to an sRGB channel, the inverse of
+/// [`srgb_to_linear`].
+pub fn linear_to_srgb(value: f32, gamma: f32) -> u8 {
+    from_linear(value, gamma)
+}
+
+/// `color` in CIELAB as `[L, a, b]` for the D65 white point, alpha ignored:
+/// L from 0, black, to 100, white, a from green to red and b from blue to
+/// yellow. Distances in Lab follow how different two colors look far better
+/// than distances in RGB.
+pub fn srgb_to_lab(color: Rgba<u8>) -> [f32; 3] {
+    // Lab is defined on the exact sRGB curve, not the plain gamma power of srgb_to_linear
+    let [r, g, b] = [0, 1, 2].map(|channel| {
+        let value = f32::from(color.0[channel]) / 255.0;
+        if value <= 0.04045 { value / 12.92 } else { ((value + 0.055) / 1.055).powf(2.4) }
+    });
+
+    // XYZ relative to the white point
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| if t > 216.0 / 24389.0 { t.cbrt() } else { (24389.0 / 27.0 * t + 16.0) / 116.0 };
+    let (x, y, z) = (f(x), f(y), f(z));
+
+    [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)]
+}
+
+/// `color` in HSV as `[hue, saturation, value]`, alpha ignored: the hue in
+/// degrees from `0.0` up to `360.0`, saturation and value from `0.0` to `1.0`.
+pub fn rgb_to_hsv(color: Rgba<u8>) -> [f32; 3] {
+    to_hsv([0, 1, 2].map(|channel| color.0[channel].to_unit()))
+}
+
+/// Converts `hsv` back to an opaque color, the inverse of [`rgb_to_hsv`].
+/// Hues outside of `0.0..360.0` wrap around.
+pub fn hsv_to_rgb(hsv: [f32; 3]) -> Rgba<u8> {
+    let [r, g, b] = from_hsv(hsv).map(u8::from_unit);
+
+    Rgba([r, g, b, 255])
+}
+
+// the same for any channel depth, see the public versions above
+
+/// Channels in `0.0..=1.0` to HSV, see [`rgb_to_hsv`].
+pub(crate) fn to_hsv([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let max = r.max(g).max(b);
+    let range = max - r.min(g).min(b);
+
+    // grays have no hue, red is 0, green 120 and blue 240 degrees
+    let hue = if range == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / range).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / range + 2.0)
+    } else {
+        60.0 * ((r - g) / range + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { range / max };
+
+    [hue, saturation, max]
+}
+
+/// HSV to channels in `0.0..=1.0`, see [`hsv_to_rgb`].
+pub(crate) fn from_hsv([hue, saturation, value]: [f32; 3]) -> [f32; 3] {
+    let chroma = value * saturation;
+    let sector = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (sector.rem_euclid(2.0) - 1.0).abs());
+
+    let [r, g, b] = match sector as u32 {
+        0 => [chroma, x, 0.0],
+        1 => [x, chroma, 0.0],
+        2 => [0.0, chroma, x],
+        3 => [0.0, x, chroma],
+        4 => [x, 0.0, chroma],
+        _ => [chroma, 0.0, x],
+    };
+    let lift = value - chroma;
+
+    [r + lift, g + lift, b + lift]
+}
+
+pub(crate) fn luminance_of<T: Channel>(pixel: Rgba<T>) -> T {
+    let [r, g, b, _] = pixel.0;
+
+    T::from_f32(0.299 * r.to_f32() + 0.587 * g.to_f32() + 0.114 * b.to_f32())
+}
+
+pub(crate) fn grayscale_of<T: Channel>(pixel: Rgba<T>, method: GrayMethod) -> Rgba<T> {
+    let l = method.gray_of(pixel);
+
+    Rgba([l, l, l, pixel.0[3]])
+}
+
+pub(crate) fn invert_of<T: Channel>(pixel: Rgba<T>) -> Rgba<T> {
+    let [r, g, b, a] = pixel.0;
+    let max = T::from_u8(255);
+
+    Rgba([max - r, max - g, max - b, a])
+}
+
+/// `pixel` with `brightness` (on the 8 bit scale) added to its color channels
+/// after scaling their distance from mid-gray by `contrast`, alpha kept.
+pub(crate) fn adjust<T: Channel>(pixel: Rgba<T>, brightness: i32, contrast: f32) -> Rgba<T> {
+    let mut pixel = pixel;
+    let offset = brightness as f32 / 255.0;
+
+    for channel in &mut pixel.0[..3] {
+        *channel = T::from_unit(((channel.to_unit() - 0.5) * contrast + 0.5 + offset).clamp(0.0, 1.0));
+    }
+
+    pixel
+}
+
+pub(crate) fn to_linear<T: Channel>(channel: T, gamma: f32) -> f32 {
+    channel.to_unit().powf(gamma)
+}
+
+pub(crate) fn from_linear<T: Channel>(value: f32, gamma: f32) -> T {
+    T::from_unit(value.clamp(0.0, 1.0).powf(1.0 / gamma))
+}
+
+/// Parses an opaque `#RRGGBB` color, the `#` being optional.
+pub fn parse_color(value: &str) -> Result<Rgba<u8>, MosaicError> {
+    let hex = value.strip_prefix('#').unwrap_or(value);
+
+    if hex.len() != 6 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(MosaicError::InvalidValue {
+            kind: "color",
+            value: value.to_string(),
+            expected: "#RRGGBB",
+        });
+    }
+
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).expect("validated hex digits");
+
+    Ok(Rgba([channel(0), channel(2), channel(4), 255]))
+}
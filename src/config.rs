@@ -0,0 +1,1245 @@
+use std::{
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use image::{imageops::FilterType, DynamicImage, GenericImageView, Rgba};
+use crate::{check_max_side, check_pixels, check_tile_size, color, depth::Channel, BitDepth, BlendMode, ColorRamp, GrayMethod, MatchSpace, MosaicError, Layout, Palette, TileLibrary, TilePattern, TileShape, DEFAULT_ADAPTIVE_THRESHOLD, DEFAULT_ALPHA, DEFAULT_BORDER_COLOR, DEFAULT_CHECKER_COLOR, DEFAULT_CHECKER_SIZE, DEFAULT_GAP_COLOR, DEFAULT_MAX_PIXELS, DEFAULT_MAX_SIDE, DEFAULT_PATTERN_COLOR, MAX_ANTIALIAS, MAX_TILE_BYTES};
+
+/// Everything [`build_mosaic`](crate::build_mosaic) needs to know besides the
+/// source image.
+///
+/// Built with [`MosaicConfig::new`]:
+///
+/// ```no_run
+/// # use mosaic_maker::{DynamicImage, MosaicConfig};
+/// # let logo = DynamicImage::new_rgba8(1, 1);
+/// let config = MosaicConfig::new(20).alpha(200).tile_image(logo).build()?;
+/// # Ok::<(), mosaic_maker::MosaicError>(())
+/// ```
+#[derive(Clone, Debug)]
+pub struct MosaicConfig {
+    pub(crate) tile_size: u32,
+    pub(crate) mode: RenderMode,
+    pub(crate) alpha: u8,
+    pub(crate) tile_opacity: u8,
+    pub(crate) tile_repeat: u32,
+    pub(crate) blend: BlendMode,
+    pub(crate) gamma: Option<f32>,
+    pub(crate) brightness: i32,
+    pub(crate) contrast: f32,
+    pub(crate) auto_levels: Option<f32>,
+    pub(crate) grayscale: bool,
+    pub(crate) grayscale_tile: bool,
+    pub(crate) gray_method: GrayMethod,
+    pub(crate) edges: f32,
+    pub(crate) vignette: f32,
+    pub(crate) adaptive: Option<(u32, u32)>,
+    pub(crate) adaptive_threshold: f32,
+    pub(crate) invert: bool,
+    pub(crate) ramp: Option<ColorRamp>,
+    pub(crate) respect_alpha: bool,
+    pub(crate) skip_below: Option<u8>,
+    pub(crate) tile_shape: TileShape,
+    pub(crate) layout: Layout,
+    pub(crate) antialias: u32,
+    pub(crate) tile_images: Vec<DynamicImage>,
+    pub(crate) preserve_tile_aspect: bool,
+    pub(crate) pattern: TilePattern,
+    pub(crate) pattern_color: Rgba<u8>,
+    pub(crate) checker_color: Rgba<u8>,
+    pub(crate) checker_size: u32,
+    pub(crate) filter: Option<FilterType>,
+    pub(crate) tile_library: Option<TileLibrary>,
+    pub(crate) repeat_limit: u32,
+    pub(crate) rotate_tiles: bool,
+    pub(crate) tile_dither: bool,
+    pub(crate) tile_tint: f32,
+    pub(crate) self_tile: bool,
+    pub(crate) match_space: MatchSpace,
+    pub(crate) seed: u64,
+    pub(crate) rotation: u32,
+    pub(crate) crop: Option<Crop>,
+    pub(crate) flip_horizontal: bool,
+    pub(crate) flip_vertical: bool,
+    pub(crate) grid: Option<Grid>,
+    pub(crate) sample: SampleMode,
+    pub(crate) palette: Option<Palette>,
+    pub(crate) palette_size: Option<usize>,
+    pub(crate) dither: bool,
+    pub(crate) gap: u32,
+    pub(crate) gap_color: Rgba<u8>,
+    pub(crate) border: u32,
+    pub(crate) border_color: Rgba<u8>,
+    pub(crate) output_size: Option<(u32, u32)>,
+    pub(crate) fit: Fit,
+    pub(crate) auto_side: Option<u32>,
+    pub(crate) max_side: u32,
+    pub(crate) max_pixels: u64,
+    pub(crate) bit_depth: BitDepth,
+    pub(crate) threads: Option<usize>,
+    pub(crate) progress: Option<Progress>,
+}
+
+impl MosaicConfig {
+    /// Starts a config for `tile_size` × `tile_size` tiles, everything else at
+    /// its default.
+    #[allow(clippy::new_ret_no_self)]
+    pub fn new(tile_size: u32) -> MosaicConfigBuilder {
+        MosaicConfigBuilder {
+            config: MosaicConfig {
+                tile_size,
+                mode: RenderMode::Tiled,
+                alpha: DEFAULT_ALPHA,
+                tile_opacity: 255,
+                tile_repeat: 1,
+                blend: BlendMode::Normal,
+                gamma: None,
+                brightness: 0,
+                contrast: 1.0,
+                auto_levels: None,
+                grayscale: false,
+                grayscale_tile: false,
+                gray_method: GrayMethod::Rec601,
+                edges: 0.0,
+                vignette: 0.0,
+                adaptive: None,
+                adaptive_threshold: DEFAULT_ADAPTIVE_THRESHOLD,
+                invert: false,
+                ramp: None,
+                respect_alpha: false,
+                skip_below: None,
+                tile_shape: TileShape::Square,
+                layout: Layout::Square,
+                antialias: 1,
+                tile_images: Vec::new(),
+                preserve_tile_aspect: false,
+                pattern: TilePattern::Image,
+                pattern_color: DEFAULT_PATTERN_COLOR,
+                checker_color: DEFAULT_CHECKER_COLOR,
+                checker_size: DEFAULT_CHECKER_SIZE,
+                filter: None,
+                tile_library: None,
+                repeat_limit: 0,
+                rotate_tiles: false,
+                tile_dither: false,
+                tile_tint: 0.0,
+                self_tile: false,
+                match_space: MatchSpace::Rgb,
+                seed: 0,
+                rotation: 0,
+                crop: None,
+                flip_horizontal: false,
+                flip_vertical: false,
+                grid: None,
+                sample: SampleMode::Point,
+                palette: None,
+                palette_size: None,
+                dither: false,
+                gap: 0,
+                gap_color: DEFAULT_GAP_COLOR,
+                border: 0,
+                border_color: DEFAULT_BORDER_COLOR,
+                output_size: None,
+                fit: Fit::Stretch,
+                auto_side: None,
+                max_side: DEFAULT_MAX_SIDE,
+                max_pixels: DEFAULT_MAX_PIXELS,
+                bit_depth: BitDepth::Auto,
+                threads: None,
+                progress: None,
+            },
+        }
+    }
+
+    /// Side length of a single tile in pixels.
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    /// The tile size the mosaic of `cells` columns and rows of cells is
+    /// rendered with: the configured one, or with an output size or an
+    /// automatic tile size the one bringing the mosaic closest to it.
+    pub(crate) fn tile_size_for(&self, (columns, rows): (u32, u32)) -> u32 {
+        let Some((width, height)) = self.output_size else {
+            return match self.auto_side {
+                Some(side) => self.auto_tile_size_for(columns.max(rows), side),
+                None => self.tile_size,
+            };
+        };
+
+        let (width, height, gap) = (f64::from(width), f64::from(height), f64::from(self.gap));
+        let (columns, rows) = (f64::from(columns.max(1)), f64::from(rows.max(1)));
+
+        let (across, down) = match self.layout {
+            Layout::Square => ((width - gap) / columns - gap, (height - gap) / rows - gap),
+            // odd rows stick out by half a tile, rows overlap by a quarter of one
+            Layout::Hex => (
+                width / if rows > 1.0 { columns + 0.5 } else { columns },
+                height / ((rows - 1.0) * 0.75 + 1.0),
+            ),
+        };
+
+        // the tighter side decides, the resize to the output size stretches the other
+        (across.min(down).round() as u32).max(2)
+    }
+
+    /// The tile size bringing the longest side of `cells` cells closest to
+    /// `side`, within what the tile and mosaic limits allow.
+    fn auto_tile_size_for(&self, cells: u32, side: u32) -> u32 {
+        let cells = cells.max(1);
+
+        // the largest square tile under the byte limit, and the tile keeping the longest side under the maximum
+        let max_bytes = MAX_TILE_BYTES.min(self.max_pixels.saturating_mul(4));
+        let largest = ((max_bytes / 4) as f64).sqrt() as u32;
+        let longest = self.max_side / cells;
+
+        ((f64::from(side) / f64::from(cells)).round() as u32).min(largest).min(longest).max(2)
+    }
+
+    /// The height of the cells `tile_size` wide, as tall as the repeated tile
+    /// is for its width when its aspect is preserved. `source` is the size of
+    /// the cropped source, which makes the tile without a tile image.
+    pub(crate) fn tile_height(&self, tile_size: u32, source: (u32, u32)) -> u32 {
+        if !self.preserve_tile_aspect || self.mode == RenderMode::Pixelate || self.pattern != TilePattern::Image {
+            return tile_size;
+        }
+
+        // several tile images are blended into a tile shaped like the first
+        let (width, height) = self.tile_images.first().map_or(source, |tile_image| tile_image.dimensions());
+
+        ((f64::from(tile_size) * f64::from(height) / f64::from(width.max(1))).round() as u32).max(1)
+    }
+
+    /// Whether the cell of a source pixel is left empty for its transparency.
+    pub(crate) fn skips<T: Channel>(&self, pixel: Rgba<T>) -> bool {
+        let alpha = pixel.0[3];
+
+        self.respect_alpha && alpha == T::zero() || self.skip_below.is_some_and(|threshold| alpha < T::from_u8(threshold))
+    }
+
+    /// The tint laid over the cell of a source pixel.
+    pub(crate) fn tint<T: Channel>(&self, pixel: Rgba<T>) -> Rgba<T> {
+        let alpha = if self.respect_alpha {
+            T::scale_alpha(self.alpha, pixel.0[3])
+        } else {
+            T::from_u8(self.alpha)
+        };
+
+        self.recolor_unsnapped(Rgba([pixel.0[0], pixel.0[1], pixel.0[2], alpha]))
+    }
+
+    /// The solid block of a source pixel in [`RenderMode::Pixelate`], opaque
+    /// unless the source transparency is respected.
+    pub(crate) fn block<T: Channel>(&self, pixel: Rgba<T>) -> Rgba<T> {
+        let alpha = if self.respect_alpha { pixel.0[3] } else { T::from_u8(255) };
+
+        self.recolor_unsnapped(Rgba([pixel.0[0], pixel.0[1], pixel.0[2], alpha]))
+    }
+
+    // colors snapped to the palette were recolored before snapping
+    fn recolor_unsnapped<T: Channel>(&self, color: Rgba<T>) -> Rgba<T> {
+        if self.has_palette() {
+            color
+        } else {
+            self.recolor(color)
+        }
+    }
+
+    pub(crate) fn has_palette(&self) -> bool {
+        self.palette.is_some() || self.palette_size.is_some()
+    }
+
+    /// The source `pixel` with the brightness and contrast adjusted, if
+    /// they are.
+    pub(crate) fn adjust<T: Channel>(&self, pixel: Rgba<T>) -> Rgba<T> {
+        if self.brightness == 0 && self.contrast == 1.0 {
+            return pixel;
+        }
+
+        color::adjust(pixel, self.brightness, self.contrast)
+    }
+
+    /// `color` turned gray, inverted and mapped onto the color ramp as
+    /// configured.
+    pub(crate) fn recolor<T: Channel>(&self, mut color: Rgba<T>) -> Rgba<T> {
+        if self.grayscale {
+            color = color::grayscale_of(color, self.gray_method);
+        }
+
+        if self.invert {
+            color = color::invert_of(color);
+        }
+
+        if let Some(ramp) = &self.ramp {
+            let [r, g, b] = ramp.at(self.gray_method.gray_of(color).to_unit());
+            color = Rgba([r, g, b, color.0[3]]);
+        }
+
+        color
+    }
+}
+
+/// The progress callback of a config, shared by its clones and called by one
+/// thread at a time.
+#[derive(Clone)]
+pub(crate) struct Progress(Arc<Mutex<dyn FnMut(u32, u32) + Send>>);
+
+impl Progress {
+    pub(crate) fn report(&self, done: u32, total: u32) {
+        let mut progress = self.0.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        progress(done, total);
+    }
+}
+
+impl fmt::Debug for Progress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Progress(..)")
+    }
+}
+
+/// Builder for [`MosaicConfig`], see [`MosaicConfig::new`].
+#[derive(Clone, Debug)]
+pub struct MosaicConfigBuilder {
+    config: MosaicConfig,
+}
+
+impl MosaicConfigBuilder {
+    /// Strength of the per-pixel color tint laid over the repeated tile: `0`
+    /// leaves the untinted repeated tile, `255` hides the tile entirely and
+    /// produces a solid pixelated color grid. Defaults to [`DEFAULT_ALPHA`].
+    pub fn alpha(mut self, alpha: u8) -> Self {
+        self.config.alpha = alpha;
+        self
+    }
+
+    /// Opacity of the repeated tile under the tint, independent of the
+    /// tint's [`alpha`](Self::alpha): `0` leaves only the tint, over
+    /// transparency, `255` keeps the tile as is. Defaults to `255`.
+    ///
+    /// With the normal blend an alpha of `255` gives pure pixelation whatever
+    /// the tile opacity, and an alpha of `0` the repeated tile faded by it; in
+    /// between the tint is blended over the faded tile, so a lower opacity
+    /// lets the tint color show more and the mosaic turn more transparent.
+    /// Photomosaic tiles are not faded.
+    pub fn tile_opacity(mut self, tile_opacity: u8) -> Self {
+        self.config.tile_opacity = tile_opacity;
+        self
+    }
+
+    /// Repeats the tile `repeat` × `repeat` times within every cell, each
+    /// copy `tile_size / repeat` pixels wide, rounded up so the copies on the
+    /// right and bottom are cut off rather than leaving a strip uncovered.
+    /// `0` and more copies than pixels are an error. Defaults to `1`.
+    pub fn repeat_tile_scale(mut self, repeat: u32) -> Self {
+        self.config.tile_repeat = repeat;
+        self
+    }
+
+    /// How the tint is composited onto the repeated tile. Defaults to
+    /// [`BlendMode::Normal`].
+    pub fn blend(mut self, blend: BlendMode) -> Self {
+        self.config.blend = blend;
+        self
+    }
+
+    /// Brightens (or, when negative, darkens) the source by `brightness` out
+    /// of 255 on every color channel, from `-255` (black) to `255` (white).
+    /// Both the tint colors and the repeated tile of the source reflect it.
+    /// Defaults to `0`.
+    pub fn brightness(mut self, brightness: i32) -> Self {
+        self.config.brightness = brightness;
+        self
+    }
+
+    /// Scales the distance of every color channel of the source from
+    /// mid-gray by `contrast`: `0.0` turns it all gray, below `1.0` flattens
+    /// and above `1.0` boosts the contrast. Applied before the brightness and
+    /// reflected by the tint colors and the repeated tile of the source.
+    /// Defaults to `1.0`.
+    pub fn contrast(mut self, contrast: f32) -> Self {
+        self.config.contrast = contrast;
+        self
+    }
+
+    /// Stretches every color channel of the cells to the full range, from
+    /// the darkest to the lightest cell, for dull sources. `clip` percent of
+    /// the cells at either end, such as
+    /// [`DEFAULT_LEVELS_CLIP`](crate::DEFAULT_LEVELS_CLIP), are left out as
+    /// outliers and saturate. Applied after the brightness and contrast, to
+    /// the tint colors only. Defaults to no stretching.
+    pub fn auto_levels(mut self, clip: f32) -> Self {
+        self.config.auto_levels = Some(clip);
+        self
+    }
+
+    /// Blends the tint in linear light instead of sRGB, converting with
+    /// `gamma` (usually [`DEFAULT_GAMMA`](crate::DEFAULT_GAMMA)). Photomosaic
+    /// tiles are matched in linear light as well.
+    pub fn linear_blend(mut self, gamma: f32) -> Self {
+        self.config.gamma = Some(gamma);
+        self
+    }
+
+    /// Desaturates the tint to the luminance of the source pixel, leaving the
+    /// repeated tile in color.
+    pub fn grayscale(mut self, grayscale: bool) -> Self {
+        self.config.grayscale = grayscale;
+        self
+    }
+
+    /// Desaturates the repeated tile as well.
+    pub fn grayscale_tile(mut self, grayscale_tile: bool) -> Self {
+        self.config.grayscale_tile = grayscale_tile;
+        self
+    }
+
+    /// How the tint and the tile are desaturated. Defaults to
+    /// [`GrayMethod::Rec601`].
+    pub fn gray_method(mut self, gray_method: GrayMethod) -> Self {
+        self.config.gray_method = gray_method;
+        self
+    }
+
+    /// Darkens the tint of cells on edges of the source by up to `strength`,
+    /// from `0.0` to `1.0`, the edges found with a Sobel filter over the
+    /// cells. Flat areas keep their color. Defaults to `0.0`, no darkening.
+    pub fn edges(mut self, strength: f32) -> Self {
+        self.config.edges = strength;
+        self
+    }
+
+    /// Fades the tint alpha of every cell with its distance from the center
+    /// of the source by `strength`, from `-1.0` to `1.0`: positive strengths
+    /// lighten the middle down to `1.0 - strength` of the alpha, the corners
+    /// keeping all of it, negative ones lighten the corners instead. The
+    /// falloff is linear in the distance. Defaults to `0.0`, no fading.
+    pub fn vignette(mut self, strength: f32) -> Self {
+        self.config.vignette = strength;
+        self
+    }
+
+    /// Sizes the tiles to the detail of the source instead of making them all
+    /// the tile size: the mosaic is cut into `max_tile` squares, which are
+    /// split in four again and again while the source under them varies more
+    /// than the [`adaptive_threshold`](Self::adaptive_threshold) and the
+    /// quarters stay at least `min_tile`. Every block gets the tile resized
+    /// to it, tinted with the mean color under it, so flat areas get large
+    /// tiles and detailed ones small. The tile size still sets the size of the
+    /// mosaic.
+    ///
+    /// Cannot be combined with the hex layout, gaps, borders, tile shapes,
+    /// edges, a vignette or a tile library.
+    pub fn adaptive(mut self, min_tile: u32, max_tile: u32) -> Self {
+        self.config.adaptive = Some((min_tile, max_tile));
+        self
+    }
+
+    /// Standard deviation of the luminance under an adaptive tile, on the 8
+    /// bit scale, above which it is split. Defaults to
+    /// [`DEFAULT_ADAPTIVE_THRESHOLD`].
+    pub fn adaptive_threshold(mut self, threshold: f32) -> Self {
+        self.config.adaptive_threshold = threshold;
+        self
+    }
+
+    /// Inverts the tint color for a negative-style mosaic, after any
+    /// desaturation.
+    pub fn invert(mut self, invert: bool) -> Self {
+        self.config.invert = invert;
+        self
+    }
+
+    /// Maps the tint color onto `ramp` by its luminance, after any
+    /// desaturation and inversion, so the darkest cells get the first color
+    /// of the ramp and the brightest the last. Defaults to none.
+    pub fn color_ramp(mut self, ramp: ColorRamp) -> Self {
+        self.config.ramp = Some(ramp);
+        self
+    }
+
+    /// Scales the tint strength by the alpha of the source pixel and leaves
+    /// the cells of fully transparent source pixels empty, so the color hidden
+    /// behind transparency does not bleed into the mosaic.
+    pub fn respect_alpha(mut self, respect_alpha: bool) -> Self {
+        self.config.respect_alpha = respect_alpha;
+        self
+    }
+
+    /// Leaves the cells of source pixels with an alpha below `threshold`,
+    /// such as [`DEFAULT_ALPHA_THRESHOLD`](crate::DEFAULT_ALPHA_THRESHOLD),
+    /// empty and transparent instead of tiling them, for logos and sprites
+    /// on a transparent background. Fully transparent pixels are skipped
+    /// with [`respect_alpha`](Self::respect_alpha) alone already. Defaults to
+    /// skipping nothing.
+    pub fn skip_transparent(mut self, threshold: u8) -> Self {
+        self.config.skip_below = Some(threshold);
+        self
+    }
+
+    /// Outline of every tile, pixels outside of it show the gap color (or
+    /// transparency without gaps). Defaults to [`TileShape::Square`].
+    pub fn tile_shape(mut self, tile_shape: TileShape) -> Self {
+        self.config.tile_shape = tile_shape;
+        self
+    }
+
+    /// How the tiles are laid out. Defaults to [`Layout::Square`];
+    /// [`Layout::Hex`] makes the tiles hexagons and allows neither gaps nor
+    /// another tile shape.
+    pub fn layout(mut self, layout: Layout) -> Self {
+        self.config.layout = layout;
+        self
+    }
+
+    /// Smooths the edges of the tile shapes and of the hexagons of the hex
+    /// layout: every pixel is judged at `factor` × `factor` points, as if the
+    /// tile were rendered `factor` times larger and scaled down, so pixels on
+    /// an edge mix the tile into the gap color or the neighboring hexagon.
+    /// At most [`MAX_ANTIALIAS`](crate::MAX_ANTIALIAS). Defaults to `1`, hard
+    /// edges.
+    pub fn antialias(mut self, factor: u32) -> Self {
+        self.config.antialias = factor;
+        self
+    }
+
+    /// Repeats a thumbnail of `tile_image` instead of the source, which is then
+    /// only used for the colors. Given more than once, the thumbnails of all
+    /// the tile images are averaged pixel by pixel into one blended tile.
+    pub fn tile_image(mut self, tile_image: DynamicImage) -> Self {
+        self.config.tile_images.push(tile_image);
+        self
+    }
+
+    /// Keeps the aspect of the repeated tile: the cells stay `tile_size` wide
+    /// and are as tall as the tile image, or the source without one, is for
+    /// its width, so a 2:1 tile image gets cells twice as wide as tall. The
+    /// tint fills the same cells. Patterns and pixelation keep square cells.
+    /// Defaults to `false`.
+    ///
+    /// Cannot be combined with the hex layout, tile shapes, a tile library,
+    /// an output size or adaptive tile sizes.
+    pub fn preserve_tile_aspect(mut self, preserve_tile_aspect: bool) -> Self {
+        self.config.preserve_tile_aspect = preserve_tile_aspect;
+        self
+    }
+
+    /// Repeats a generated pattern instead of a thumbnail, which then needs no
+    /// image at all and takes precedence over the tile image. Defaults to
+    /// [`TilePattern::Image`].
+    pub fn pattern(mut self, pattern: TilePattern) -> Self {
+        self.config.pattern = pattern;
+        self
+    }
+
+    /// Color of [`TilePattern::Solid`] and of the checkerboard square in the
+    /// top left corner. Defaults to [`DEFAULT_PATTERN_COLOR`].
+    pub fn pattern_color(mut self, pattern_color: Rgba<u8>) -> Self {
+        self.config.pattern_color = pattern_color;
+        self
+    }
+
+    /// Color of the checkerboard squares next to those of the pattern color.
+    /// Defaults to [`DEFAULT_CHECKER_COLOR`].
+    pub fn checker_color(mut self, checker_color: Rgba<u8>) -> Self {
+        self.config.checker_color = checker_color;
+        self
+    }
+
+    /// Side of the checkerboard squares in pixels, starting over in every
+    /// tile. Defaults to [`DEFAULT_CHECKER_SIZE`].
+    pub fn checker_size(mut self, checker_size: u32) -> Self {
+        self.config.checker_size = checker_size;
+        self
+    }
+
+    /// What every cell is filled with. Defaults to [`RenderMode::Tiled`].
+    pub fn mode(mut self, mode: RenderMode) -> Self {
+        self.config.mode = mode;
+        self
+    }
+
+    /// Resizes the tile image with `filter` instead of the default fast
+    /// thumbnail downscaling, from [`FilterType::Nearest`], the fastest and
+    /// blockiest, to [`FilterType::Lanczos3`], the sharpest and slowest.
+    pub fn filter(mut self, filter: FilterType) -> Self {
+        self.config.filter = Some(filter);
+        self
+    }
+
+    /// Builds a true photomosaic: every cell gets the library tile nearest to
+    /// the source pixel color, untinted, instead of the tinted repeated tile.
+    /// The library must be built for the same tile size.
+    pub fn tile_library(mut self, tile_library: TileLibrary) -> Self {
+        self.config.tile_library = Some(tile_library);
+        self
+    }
+
+    /// Keeps a photomosaic tile from being used again within `repeat_limit`
+    /// cells horizontally or vertically, falling back to the next nearest
+    /// tile. Rendering fails with [`MosaicError::InsufficientTiles`] if the
+    /// library is too small for the limit, which takes more than
+    /// `2 * repeat_limit` tiles to be sure of. Defaults to `0`, no limit.
+    pub fn repeat_limit(mut self, repeat_limit: u32) -> Self {
+        self.config.repeat_limit = repeat_limit;
+        self
+    }
+
+    /// Pulls every photomosaic tile toward the color of its cell by
+    /// `strength`, from `0.0`, the tile as it is, to `1.0`, a solid block of
+    /// the color as in [`RenderMode::Pixelate`], so a small library still
+    /// matches the source. The alpha of the tile is kept. With
+    /// [`self_tile`](Self::self_tile) it pulls the miniatures of the source
+    /// the same way, instead of blending the tint over them. Defaults to `0.0`.
+    pub fn tint_from_tile(mut self, strength: f32) -> Self {
+        self.config.tile_tint = strength;
+        self
+    }
+
+    /// Makes a self-mosaic, the whole picture built from miniatures of
+    /// itself: the tile of every cell is the source scaled down, colored by
+    /// its cell. That is the tile a mosaic without a tile image, pattern or
+    /// library draws anyway, but asked for by name a setting replacing it is a
+    /// [`MosaicError::UnsupportedWithSelfTile`], and
+    /// [`tint_from_tile`](Self::tint_from_tile) sets how far the miniatures
+    /// are recolored. Defaults to `false`.
+    pub fn self_tile(mut self, self_tile: bool) -> Self {
+        self.config.self_tile = self_tile;
+        self
+    }
+
+    /// Compares the colors of the photomosaic tiles and cells in `space`.
+    /// [`MatchSpace::Lab`] picks the tiles that look closest, at the cost of
+    /// converting the color of every cell. Defaults to [`MatchSpace::Rgb`].
+    pub fn match_space(mut self, space: MatchSpace) -> Self {
+        self.config.match_space = space;
+        self
+    }
+
+    /// Turns every photomosaic tile by a random multiple of 90°, which keeps
+    /// its average color but breaks up the uniform look.
+    pub fn rotate_tiles(mut self, rotate_tiles: bool) -> Self {
+        self.config.rotate_tiles = rotate_tiles;
+        self
+    }
+
+    /// Offsets the color every photomosaic cell is matched with by a 4 × 4
+    /// Bayer matrix, so a smooth gradient mixes the tiles on either side of
+    /// each step instead of showing wide bands of one tile. The offsets only
+    /// depend on the cell position, so the mosaic stays the same from run to
+    /// run. Defaults to `false`.
+    pub fn tile_dither(mut self, tile_dither: bool) -> Self {
+        self.config.tile_dither = tile_dither;
+        self
+    }
+
+    /// Seed of the random choices, the same seed giving the same mosaic.
+    /// Defaults to `0`.
+    ///
+    /// Every choice is derived from the seed and the cell alone, so the
+    /// mosaic does not depend on the number of threads or the order cells are
+    /// rendered in. Only [`rotate_tiles`](Self::rotate_tiles) is random, and
+    /// the seed does nothing without it.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.config.seed = seed;
+        self
+    }
+
+    /// Turns the source clockwise by `degrees`, 0, 90, 180 or 270, before it
+    /// is cropped, so 90 and 270 swap the width and height of the mosaic.
+    /// Other angles fail when building with [`MosaicError::InvalidValue`].
+    /// Defaults to `0`.
+    pub fn rotate(mut self, degrees: u32) -> Self {
+        self.config.rotation = degrees;
+        self
+    }
+
+    /// Only tiles the `crop` region of the source, which must lie within it,
+    /// measured after the rotation, if any. The grid, if any, divides the
+    /// region rather than the whole source.
+    pub fn crop(mut self, crop: Crop) -> Self {
+        self.config.crop = Some(crop);
+        self
+    }
+
+    /// Mirrors the source left to right before tiling. The crop, if any, is
+    /// measured before mirroring. Defaults to `false`.
+    pub fn flip_horizontal(mut self, flip: bool) -> Self {
+        self.config.flip_horizontal = flip;
+        self
+    }
+
+    /// Mirrors the source top to bottom before tiling, like
+    /// [`flip_horizontal`](Self::flip_horizontal). Defaults to `false`.
+    pub fn flip_vertical(mut self, flip: bool) -> Self {
+        self.config.flip_vertical = flip;
+        self
+    }
+
+    /// Downsamples the source to `grid` cells before tiling, so the mosaic is
+    /// a fixed number of tiles across regardless of the source resolution.
+    pub fn grid(mut self, grid: Grid) -> Self {
+        self.config.grid = Some(grid);
+        self
+    }
+
+    /// How the color of a grid cell is taken from the source region it covers.
+    /// Defaults to [`SampleMode::Point`].
+    pub fn sample(mut self, sample: SampleMode) -> Self {
+        self.config.sample = sample;
+        self
+    }
+
+    /// Snaps the color of every cell to the nearest color of `palette`
+    /// before tinting, for a poster-like mosaic.
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.config.palette = Some(palette);
+        self
+    }
+
+    /// Like [`palette`](Self::palette), with a palette of `size` colors
+    /// picked from the cell colors of every source by
+    /// [`Palette::median_cut`]. An explicit palette takes precedence.
+    pub fn adaptive_palette(mut self, size: usize) -> Self {
+        self.config.palette_size = Some(size);
+        self
+    }
+
+    /// Diffuses the error of snapping every cell to the
+    /// [`palette`](Self::palette) over the cells to its right and below
+    /// (Floyd–Steinberg), so gradients look smooth instead of banded. Does
+    /// nothing without a palette.
+    pub fn dither(mut self, dither: bool) -> Self {
+        self.config.dither = dither;
+        self
+    }
+
+    /// Leaves `gap` pixels of grout between adjacent tiles and around the
+    /// edges, filled with the gap color. Defaults to `0`.
+    pub fn gap(mut self, gap: u32) -> Self {
+        self.config.gap = gap;
+        self
+    }
+
+    /// Color of the gaps between tiles. Defaults to [`DEFAULT_GAP_COLOR`].
+    pub fn gap_color(mut self, gap_color: Rgba<u8>) -> Self {
+        self.config.gap_color = gap_color;
+        self
+    }
+
+    /// Makes the mosaic exactly `width` × `height`: it is rendered with the
+    /// tile size that comes closest, which replaces the one given to
+    /// [`MosaicConfig::new`], and resized to that size if the cells do not
+    /// divide it evenly, as [`fit`](Self::fit) asks.
+    pub fn output_size(mut self, width: u32, height: u32) -> Self {
+        self.config.output_size = Some((width, height));
+        self
+    }
+
+    /// How the mosaic is resized to the [`output_size`](Self::output_size)
+    /// when its aspect ratio differs. Defaults to [`Fit::Stretch`].
+    pub fn fit(mut self, fit: Fit) -> Self {
+        self.config.fit = fit;
+        self
+    }
+
+    /// Picks the tile size per source, the one bringing the longest side of
+    /// the mosaic closest to `side` pixels, such as [`DEFAULT_AUTO_SIDE`](crate::DEFAULT_AUTO_SIDE),
+    /// which replaces the one given to [`MosaicConfig::new`]. The tile size is
+    /// kept at least `2` and within the [`max_side`](Self::max_side) and
+    /// [`max_pixels`](Self::max_pixels), see [`mosaic_tile_size`] for the one
+    /// picked. An output size takes precedence.
+    ///
+    /// [`mosaic_tile_size`]: crate::mosaic_tile_size
+    pub fn auto_tile_size(mut self, side: u32) -> Self {
+        self.config.auto_side = Some(side);
+        self
+    }
+
+    /// Frames every tile with a `border` pixels wide border along the edges of
+    /// its cell, drawn over the tile whatever its shape, inside any gap.
+    /// Defaults to `0`. Not supported by the hex layout.
+    pub fn border(mut self, border: u32) -> Self {
+        self.config.border = border;
+        self
+    }
+
+    /// Color of the borders. Defaults to [`DEFAULT_BORDER_COLOR`].
+    pub fn border_color(mut self, border_color: Rgba<u8>) -> Self {
+        self.config.border_color = border_color;
+        self
+    }
+
+    /// Largest width or height the mosaic may have. Defaults to
+    /// [`DEFAULT_MAX_SIDE`].
+    pub fn max_side(mut self, max_side: u32) -> Self {
+        self.config.max_side = max_side;
+        self
+    }
+
+    /// Largest total pixel count (width × height) the mosaic may have.
+    /// Defaults to [`DEFAULT_MAX_PIXELS`].
+    pub fn max_pixels(mut self, max_pixels: u64) -> Self {
+        self.config.max_pixels = max_pixels;
+        self
+    }
+
+    /// Bits per channel of the mosaic. Defaults to [`BitDepth::Auto`], which
+    /// renders 16 bit sources in 16 bits instead of rounding them to 8.
+    pub fn bit_depth(mut self, bit_depth: BitDepth) -> Self {
+        self.config.bit_depth = bit_depth;
+        self
+    }
+
+    /// Renders on a dedicated pool of `threads` threads instead of the global
+    /// rayon pool.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config.threads = Some(threads);
+        self
+    }
+
+    /// Calls `progress` with `(done, total)` rows of tiles, once with `0` when
+    /// rendering starts and again for every finished row, `done` reaching
+    /// `total` at the end. Every mosaic built with the config starts again
+    /// from `0`, every frame of an animated GIF too.
+    ///
+    /// Rows are rendered in parallel, so `progress` is called from the rayon
+    /// threads, but never by two at once and always with `done` going up.
+    ///
+    /// ```
+    /// use std::sync::{atomic::{AtomicU32, Ordering}, Arc};
+    /// use mosaic_maker::{build_mosaic, DynamicImage, MosaicConfig};
+    ///
+    /// let rows = Arc::new(AtomicU32::new(0));
+    /// let counter = Arc::clone(&rows);
+    /// let config = MosaicConfig::new(4)
+    ///     .on_progress(move |done, _total| counter.store(done, Ordering::Relaxed))
+    ///     .build()?;
+    ///
+    /// build_mosaic(&DynamicImage::new_rgb8(3, 2), &config)?;
+    /// assert_eq!(rows.load(Ordering::Relaxed), 2);
+    /// # Ok::<(), mosaic_maker::MosaicError>(())
+    /// ```
+    pub fn on_progress(mut self, progress: impl FnMut(u32, u32) + Send + 'static) -> Self {
+        self.config.progress = Some(Progress(Arc::new(Mutex::new(progress))));
+        self
+    }
+
+    /// Validates the settings, refusing tile sizes below 2 or with tiles
+    /// beyond [`MAX_TILE_BYTES`](crate::MAX_TILE_BYTES), gammas that are not
+    /// positive, tile libraries built for another tile size and output
+    /// sizes beyond the size limits.
+    pub fn build(self) -> Result<MosaicConfig, MosaicError> {
+        if self.config.tile_size < 2 {
+            return Err(MosaicError::InvalidTileSize(self.config.tile_size));
+        }
+        check_tile_size(self.config.tile_size, self.config.max_pixels)?;
+
+        if let Some(gamma) = self.config.gamma {
+            if !(gamma.is_finite() && gamma > 0.0) {
+                return Err(MosaicError::InvalidValue {
+                    kind: "gamma",
+                    value: gamma.to_string(),
+                    expected: "a positive number",
+                });
+            }
+        }
+
+        if !(-255..=255).contains(&self.config.brightness) {
+            return Err(MosaicError::InvalidValue {
+                kind: "brightness",
+                value: self.config.brightness.to_string(),
+                expected: "-255 to 255",
+            });
+        }
+
+        if !(self.config.contrast.is_finite() && self.config.contrast >= 0.0) {
+            return Err(MosaicError::InvalidValue {
+                kind: "contrast",
+                value: self.config.contrast.to_string(),
+                expected: "a non-negative number",
+            });
+        }
+
+        if let Some(clip) = self.config.auto_levels.filter(|clip| !(0.0..50.0).contains(clip)) {
+            return Err(MosaicError::InvalidValue {
+                kind: "auto levels clip",
+                value: clip.to_string(),
+                expected: "a percentage from 0 up to 50",
+            });
+        }
+
+        for tile_image in &self.config.tile_images {
+            let (width, height) = tile_image.dimensions();
+            if width == 0 || height == 0 {
+                return Err(MosaicError::EmptyImage {
+                    input: "The tile image".to_string(),
+                    width,
+                    height,
+                });
+            }
+        }
+
+        if ![0, 90, 180, 270].contains(&self.config.rotation) {
+            return Err(MosaicError::InvalidValue {
+                kind: "rotation",
+                value: self.config.rotation.to_string(),
+                expected: "0, 90, 180 or 270",
+            });
+        }
+
+        // with an output size the tile size is only known per source, and copies are cut off there
+        if self.config.tile_repeat == 0 || self.config.output_size.is_none() && self.config.tile_repeat > self.config.tile_size {
+            return Err(MosaicError::InvalidValue {
+                kind: "tile repeat",
+                value: self.config.tile_repeat.to_string(),
+                expected: "1 up to the tile size",
+            });
+        }
+
+        if self.config.checker_size == 0 {
+            return Err(MosaicError::InvalidValue {
+                kind: "checker size",
+                value: "0".to_string(),
+                expected: "at least 1",
+            });
+        }
+
+        if self.config.layout == Layout::Hex {
+            if self.config.gap > 0 {
+                return Err(MosaicError::UnsupportedWithHexLayout("gaps between tiles"));
+            }
+            if self.config.border > 0 {
+                return Err(MosaicError::UnsupportedWithHexLayout("borders"));
+            }
+            if self.config.tile_shape != TileShape::Square {
+                return Err(MosaicError::UnsupportedWithHexLayout("tile shapes, the tiles are hexagons"));
+            }
+        }
+
+        if !(1..=MAX_ANTIALIAS).contains(&self.config.antialias) {
+            return Err(MosaicError::InvalidValue {
+                kind: "anti-aliasing factor",
+                value: self.config.antialias.to_string(),
+                expected: "1 to 16",
+            });
+        }
+
+        if self.config.preserve_tile_aspect {
+            let unsupported = [
+                (self.config.layout == Layout::Hex, "the hex layout"),
+                (self.config.tile_shape != TileShape::Square, "tile shapes"),
+                (self.config.tile_library.is_some(), "a tile library"),
+                (self.config.output_size.is_some(), "an output size"),
+                (self.config.adaptive.is_some(), "adaptive tile sizes"),
+            ];
+            if let Some(&(_, setting)) = unsupported.iter().find(|&&(used, _)| used) {
+                return Err(MosaicError::UnsupportedWithTileAspect(setting));
+            }
+        }
+
+        if self.config.self_tile {
+            let unsupported = [
+                (!self.config.tile_images.is_empty(), "a tile image"),
+                (self.config.tile_library.is_some(), "a tile library"),
+                (self.config.pattern != TilePattern::Image, "tile patterns"),
+                (self.config.mode == RenderMode::Pixelate, "pixelation"),
+            ];
+            if let Some(&(_, setting)) = unsupported.iter().find(|&&(used, _)| used) {
+                return Err(MosaicError::UnsupportedWithSelfTile(setting));
+            }
+        }
+
+        if let Some((min_tile, max_tile)) = self.config.adaptive {
+            if min_tile == 0 || min_tile > max_tile {
+                return Err(MosaicError::InvalidValue {
+                    kind: "adaptive tile sizes",
+                    value: format!("{} to {}", min_tile, max_tile),
+                    expected: "a minimum of at least 1 up to the maximum",
+                });
+            }
+            check_tile_size(max_tile, self.config.max_pixels)?;
+
+            let unsupported = [
+                (self.config.layout == Layout::Hex, "the hex layout"),
+                (self.config.gap > 0, "gaps between tiles"),
+                (self.config.border > 0, "borders"),
+                (self.config.tile_shape != TileShape::Square, "tile shapes"),
+                (self.config.edges > 0.0, "edges"),
+                (self.config.vignette != 0.0, "a vignette"),
+                (self.config.tile_library.is_some(), "a tile library"),
+            ];
+            if let Some(&(_, setting)) = unsupported.iter().find(|&&(used, _)| used) {
+                return Err(MosaicError::UnsupportedWithAdaptive(setting));
+            }
+        }
+
+        if !(self.config.adaptive_threshold.is_finite() && self.config.adaptive_threshold >= 0.0) {
+            return Err(MosaicError::InvalidValue {
+                kind: "adaptive threshold",
+                value: self.config.adaptive_threshold.to_string(),
+                expected: "a non-negative number",
+            });
+        }
+
+        if !(0.0..=1.0).contains(&self.config.edges) {
+            return Err(MosaicError::InvalidValue {
+                kind: "edge strength",
+                value: self.config.edges.to_string(),
+                expected: "0.0 to 1.0",
+            });
+        }
+
+        if !(-1.0..=1.0).contains(&self.config.vignette) {
+            return Err(MosaicError::InvalidValue {
+                kind: "vignette strength",
+                value: self.config.vignette.to_string(),
+                expected: "-1.0 to 1.0",
+            });
+        }
+
+        if !(0.0..=1.0).contains(&self.config.tile_tint) {
+            return Err(MosaicError::InvalidValue {
+                kind: "tile tint strength",
+                value: self.config.tile_tint.to_string(),
+                expected: "0.0 to 1.0",
+            });
+        }
+
+        if let Some((width, height)) = self.config.output_size {
+            if width == 0 || height == 0 {
+                return Err(MosaicError::InvalidValue {
+                    kind: "output size",
+                    value: format!("{}x{}", width, height),
+                    expected: "at least 1x1",
+                });
+            }
+            check_max_side(width.into(), height.into(), self.config.max_side)?;
+            check_pixels(width, height, self.config.max_pixels)?;
+        }
+
+        if self.config.auto_side == Some(0) {
+            return Err(MosaicError::InvalidValue {
+                kind: "automatic mosaic side",
+                value: "0".to_string(),
+                expected: "at least 1",
+            });
+        }
+
+        // with an output size or an automatic tile size the tile size is only known per source, and checked when rendering
+        let per_source = self.config.output_size.is_some() || self.config.auto_side.is_some();
+        if let Some(tile_library) = self.config.tile_library.as_ref().filter(|_| !per_source) {
+            if tile_library.tile_size() != self.config.tile_size {
+                return Err(MosaicError::TileLibrarySize {
+                    library: tile_library.tile_size(),
+                    mosaic: self.config.tile_size,
+                });
+            }
+        }
+
+        if let Some(size) = self.config.palette_size {
+            if size < 2 {
+                return Err(MosaicError::PaletteTooSmall(size));
+            }
+        }
+
+        Ok(self.config)
+    }
+}
+
+/// A region of the source, `width` × `height` pixels from (`x`, `y`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Crop {
+    /// Size of the region, or a [`MosaicError::CropOutOfBounds`] if it does
+    /// not lie within a `width` × `height` source.
+    pub fn dimensions(self, (width, height): (u32, u32)) -> Result<(u32, u32), MosaicError> {
+        let fits = |start: u32, size: u32, source: u32| u64::from(start) + u64::from(size) <= u64::from(source);
+
+        if fits(self.x, self.width, width) && fits(self.y, self.height, height) {
+            Ok((self.width, self.height))
+        } else {
+            Err(MosaicError::CropOutOfBounds { crop: self, width, height })
+        }
+    }
+}
+
+impl fmt::Display for Crop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{},{},{},{}", self.x, self.y, self.width, self.height)
+    }
+}
+
+impl FromStr for Crop {
+    type Err = MosaicError;
+
+    /// Parses `X,Y,W,H`, `W` and `H` being positive.
+    fn from_str(value: &str) -> Result<Crop, MosaicError> {
+        let invalid = || MosaicError::InvalidValue {
+            kind: "crop",
+            value: value.to_string(),
+            expected: "X,Y,W,H with a positive W and H, e.g. 10,10,200,100",
+        };
+
+        let numbers = value
+            .split(',')
+            .map(|number| number.trim().parse().map_err(|_| invalid()))
+            .collect::<Result<Vec<u32>, MosaicError>>()?;
+
+        match numbers[..] {
+            [x, y, width, height] if width > 0 && height > 0 => Ok(Crop { x, y, width, height }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// How many cells the source is downsampled to before tiling.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Grid {
+    /// This many cells along the longer side, the shorter side following the
+    /// source aspect ratio.
+    Cells(u32),
+    /// Exactly `width` × `height` cells.
+    Exact(u32, u32),
+}
+
+impl Grid {
+    /// Grid size in cells for a `width` × `height` source.
+    pub fn dimensions(self, (width, height): (u32, u32)) -> (u32, u32) {
+        match self {
+            Grid::Exact(width, height) => (width, height),
+            Grid::Cells(cells) => {
+                let longer = width.max(height).max(1);
+                let scale = |side: u32| ((u64::from(side) * u64::from(cells) + u64::from(longer) / 2) / u64::from(longer)).max(1) as u32;
+
+                (scale(width), scale(height))
+            },
+        }
+    }
+}
+
+impl FromStr for Grid {
+    type Err = MosaicError;
+
+    /// Parses `N` as [`Grid::Cells`] and `WxH` as [`Grid::Exact`].
+    fn from_str(value: &str) -> Result<Grid, MosaicError> {
+        let invalid = || MosaicError::InvalidGrid(value.to_string());
+        let cells = |cells: &str| match cells.trim().parse() {
+            Ok(0) | Err(_) => Err(invalid()),
+            Ok(cells) => Ok(cells),
+        };
+
+        match value.split_once(['x', 'X']) {
+            Some((width, height)) => Ok(Grid::Exact(cells(width)?, cells(height)?)),
+            None => Ok(Grid::Cells(cells(value)?)),
+        }
+    }
+}
+
+/// Parses a resampling filter name: `nearest`, `triangle`, `catmullrom` or
+/// `lanczos3`.
+pub fn parse_filter(value: &str) -> Result<FilterType, MosaicError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "nearest" => Ok(FilterType::Nearest),
+        "triangle" => Ok(FilterType::Triangle),
+        "catmullrom" => Ok(FilterType::CatmullRom),
+        "lanczos3" => Ok(FilterType::Lanczos3),
+        _ => Err(MosaicError::InvalidValue {
+            kind: "filter",
+            value: value.to_string(),
+            expected: "nearest, triangle, catmullrom or lanczos3",
+        }),
+    }
+}
+
+/// What every cell of the mosaic is filled with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RenderMode {
+    /// The tile tinted with the source pixel color, or the best matching
+    /// library tile for photomosaics.
+    #[default]
+    Tiled,
+    /// A solid block of the source pixel color, a plain pixelation without
+    /// any tile. Tile images and libraries are ignored.
+    Pixelate,
+}
+
+impl FromStr for RenderMode {
+    type Err = MosaicError;
+
+    fn from_str(value: &str) -> Result<RenderMode, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "tiled" => Ok(RenderMode::Tiled),
+            "pixelate" => Ok(RenderMode::Pixelate),
+            _ => Err(MosaicError::InvalidValue {
+                kind: "mode",
+                value: value.to_string(),
+                expected: "tiled or pixelate",
+            }),
+        }
+    }
+}
+
+/// How a mosaic is resized to an output size of another aspect ratio.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Fit {
+    /// Scaled to exactly the output size, distorting the tiles.
+    #[default]
+    Stretch,
+    /// Scaled to fit inside the output size, centered between transparent
+    /// bars, which take the background color once flattened.
+    Contain,
+    /// Scaled to fill the output size, cropping the overflow of the sides
+    /// around the center.
+    Cover,
+}
+
+impl FromStr for Fit {
+    type Err = MosaicError;
+
+    fn from_str(value: &str) -> Result<Fit, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "stretch" => Ok(Fit::Stretch),
+            "contain" => Ok(Fit::Contain),
+            "cover" => Ok(Fit::Cover),
+            _ => Err(MosaicError::InvalidValue {
+                kind: "fit",
+                value: value.to_string(),
+                expected: "stretch, contain or cover",
+            }),
+        }
+    }
+}
+
+/// How the color of a grid cell is taken from the source region it covers.
+/// Without a grid every cell is a single source pixel and both are the same.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SampleMode {
+    /// The source resized to the grid, weighting pixels near the cell center.
+    #[default]
+    Point,
+    /// The exact mean of every source pixel in the cell, for smoother tints.
+    Average,
+}
+
+impl FromStr for SampleMode {
+    type Err = MosaicError;
+
+    fn from_str(value: &str) -> Result<SampleMode, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "point" => Ok(SampleMode::Point),
+            "avg" | "average" => Ok(SampleMode::Average),
+            _ => Err(MosaicError::InvalidValue {
+                kind: "sample mode",
+                value: value.to_string(),
+                expected: "point or avg",
+            }),
+        }
+    }
+}
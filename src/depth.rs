@@ -0,0 +1,178 @@
+use std::{borrow::Cow, fmt::Debug, str::FromStr};
+use image::{ColorType, DynamicImage, ImageBuffer, Primitive, Rgba};
+use crate::MosaicError;
+
+/// Bits per color channel the mosaic is rendered with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BitDepth {
+    /// 16 bits for sources with 16 bit channels, 8 bits otherwise.
+    #[default]
+    Auto,
+    /// Always 8 bits, 16 bit sources are rounded down.
+    Eight,
+    /// Always 16 bits, which only adds precision to 16 bit sources.
+    Sixteen,
+}
+
+impl BitDepth {
+    /// Bits per channel of the mosaic of `source`, 8 or 16.
+    pub fn bits(self, source: &DynamicImage) -> u8 {
+        match self {
+            BitDepth::Auto if is_sixteen_bit(source.color()) => 16,
+            BitDepth::Auto | BitDepth::Eight => 8,
+            BitDepth::Sixteen => 16,
+        }
+    }
+}
+
+impl FromStr for BitDepth {
+    type Err = MosaicError;
+
+    fn from_str(value: &str) -> Result<BitDepth, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "auto" => Ok(BitDepth::Auto),
+            "8" => Ok(BitDepth::Eight),
+            "16" => Ok(BitDepth::Sixteen),
+            _ => Err(MosaicError::InvalidValue {
+                kind: "bit depth",
+                value: value.to_string(),
+                expected: "auto, 8 or 16",
+            }),
+        }
+    }
+}
+
+/// Whether `color` has 16 bit channels.
+pub(crate) fn is_sixteen_bit(color: ColorType) -> bool {
+    matches!(color, ColorType::L16 | ColorType::La16 | ColorType::Rgb16 | ColorType::Rgba16)
+}
+
+/// A channel type the mosaic is rendered in, `u8` or `u16`.
+///
+/// Settings such as the tint alpha and the gap color stay 8 bit and are
+/// widened where they are used, and so do the photomosaic tiles.
+pub(crate) trait Channel: Primitive + Debug + Into<u64> + Send + Sync + 'static {
+    /// Bits of the channel.
+    const BITS: u8;
+
+    /// Largest value of the channel.
+    const MAX: f32;
+
+    /// The 8 bit `value` widened to the full range of the channel.
+    fn from_u8(value: u8) -> Self;
+
+    /// The channel rounded to 8 bits.
+    fn to_u8(self) -> u8;
+
+    /// The channel as a float in `0.0..=MAX`.
+    fn to_f32(self) -> f32;
+
+    /// The channel closest to `value` in `0.0..=MAX`, saturating outside of it.
+    fn from_f32(value: f32) -> Self;
+
+    /// The 8 bit tint strength `alpha` scaled by the source alpha `pixel_alpha`.
+    fn scale_alpha(alpha: u8, pixel_alpha: Self) -> Self;
+
+    /// `img` converted to RGBA with this channel type.
+    fn rgba(img: &DynamicImage) -> ImageBuffer<Rgba<Self>, Vec<Self>>;
+
+    /// `img` as a [`DynamicImage`].
+    fn dynamic(img: ImageBuffer<Rgba<Self>, Vec<Self>>) -> DynamicImage;
+
+    /// `channels` as the big endian bytes PNG stores.
+    fn png_bytes(channels: &[Self]) -> Cow<'_, [u8]>;
+
+    /// The channel in `0.0..=1.0`.
+    fn to_unit(self) -> f32 {
+        self.to_f32() / Self::MAX
+    }
+
+    /// The channel closest to `value` in `0.0..=1.0`, saturating outside of it.
+    fn from_unit(value: f32) -> Self {
+        Self::from_f32(value * Self::MAX)
+    }
+
+    /// The 8 bit `color` widened to this channel type.
+    fn widen(color: Rgba<u8>) -> Rgba<Self> {
+        Rgba(color.0.map(Self::from_u8))
+    }
+
+    /// `color` rounded to 8 bits.
+    fn narrow(color: Rgba<Self>) -> Rgba<u8> {
+        Rgba(color.0.map(<Self as Channel>::to_u8))
+    }
+}
+
+impl Channel for u8 {
+    const BITS: u8 = 8;
+    const MAX: f32 = 255.0;
+
+    fn from_u8(value: u8) -> u8 {
+        value
+    }
+
+    fn to_u8(self) -> u8 {
+        self
+    }
+
+    fn to_f32(self) -> f32 {
+        f32::from(self)
+    }
+
+    fn from_f32(value: f32) -> u8 {
+        value.round() as u8
+    }
+
+    fn scale_alpha(alpha: u8, pixel_alpha: u8) -> u8 {
+        ((u16::from(alpha) * u16::from(pixel_alpha) + 127) / 255) as u8
+    }
+
+    fn rgba(img: &DynamicImage) -> ImageBuffer<Rgba<u8>, Vec<u8>> {
+        img.to_rgba8()
+    }
+
+    fn dynamic(img: ImageBuffer<Rgba<u8>, Vec<u8>>) -> DynamicImage {
+        DynamicImage::ImageRgba8(img)
+    }
+
+    fn png_bytes(channels: &[u8]) -> Cow<'_, [u8]> {
+        Cow::Borrowed(channels)
+    }
+}
+
+impl Channel for u16 {
+    const BITS: u8 = 16;
+    const MAX: f32 = 65535.0;
+
+    fn from_u8(value: u8) -> u16 {
+        u16::from(value) * 257
+    }
+
+    fn to_u8(self) -> u8 {
+        ((u32::from(self) + 128) / 257) as u8
+    }
+
+    fn to_f32(self) -> f32 {
+        f32::from(self)
+    }
+
+    fn from_f32(value: f32) -> u16 {
+        value.round() as u16
+    }
+
+    fn scale_alpha(alpha: u8, pixel_alpha: u16) -> u16 {
+        ((u32::from(u16::from_u8(alpha)) * u32::from(pixel_alpha) + 32767) / 65535) as u16
+    }
+
+    fn rgba(img: &DynamicImage) -> ImageBuffer<Rgba<u16>, Vec<u16>> {
+        img.to_rgba16()
+    }
+
+    fn dynamic(img: ImageBuffer<Rgba<u16>, Vec<u16>>) -> DynamicImage {
+        DynamicImage::ImageRgba16(img)
+    }
+
+    fn png_bytes(channels: &[u16]) -> Cow<'_, [u8]> {
+        Cow::Owned(channels.iter().flat_map(|channel| channel.to_be_bytes()).collect())
+    }
+}
@@ -0,0 +1,179 @@
+use std::io;
+use thiserror::Error;
+use crate::Crop;
+
+/// Everything that can go wrong while building a mosaic.
+#[derive(Debug, Error)]
+pub enum MosaicError {
+    /// The tile size is below the minimum of 2.
+    #[error("Invalid tile size {0} (minimum 2)")]
+    InvalidTileSize(u32),
+
+    /// A single tile would take more memory than allowed.
+    #[error("Invalid tile size {tile_size}, a tile would take {bytes} bytes (at most {max_bytes})")]
+    TileTooLarge { tile_size: u32, bytes: u64, max_bytes: u64 },
+
+    /// The input URL could not be fetched (connection refused, timeout, bad body, ...).
+    #[cfg(feature = "url")]
+    #[error(
+        "{} {}: {0}",
+        if .0.is_timeout() { "Timed out downloading" } else { "Could not download" },
+        .0.url().map_or("the input URL", |url| url.as_str())
+    )]
+    Download(#[from] reqwest::Error),
+
+    /// The input URL answered with a non-success status, after following redirects.
+    #[cfg(feature = "url")]
+    #[error(
+        "{url} returned HTTP {status} {}",
+        reqwest::StatusCode::from_u16(*.status).ok().and_then(|status| status.canonical_reason()).unwrap_or("")
+    )]
+    HttpStatus { url: String, status: u16 },
+
+    /// The input URL served something that is not an image, typically a web page.
+    #[cfg(feature = "url")]
+    #[error("{url} is not an image (Content-Type: {content_type}), link directly to the image file instead of a web page")]
+    NotAnImage { url: String, content_type: String },
+
+    /// The input URL served more bytes than allowed.
+    #[cfg(feature = "url")]
+    #[error("{url} is larger than the download limit of {max_bytes} bytes")]
+    DownloadTooLarge { url: String, max_bytes: u64 },
+
+    /// The input is a URL but URL support was left out of the build.
+    #[error("Cannot download {url}, this build has no URL support (the \"url\" feature), download the image and pass the file instead")]
+    UrlSupportDisabled { url: String },
+
+    /// The input is neither an existing file nor an http or https URL.
+    #[error("Input file {0} does not exist")]
+    InputNotFound(String),
+
+    /// An input file could not be read.
+    #[error("Could not read {path}: {source}")]
+    Read { path: String, source: io::Error },
+
+    /// The input file or downloaded bytes could not be decoded as an image.
+    #[error("Could not decode {input} as an image: {source}")]
+    Decode { input: String, source: image::ImageError },
+
+    /// A decoded image, or the source of a mosaic, has no pixels.
+    #[error("{input} is empty ({width}x{height}), there is nothing to tile")]
+    EmptyImage { input: String, width: u32, height: u32 },
+
+    /// The frame asked for is past the last frame of an animation or page of a TIFF.
+    #[error("There is no frame {frame} in {input}, it has {frames} (counting from 1)")]
+    FrameOutOfRange { input: String, frame: u32, frames: u32 },
+
+    /// A ZIP archive of library tiles could not be read.
+    #[error("Could not read the ZIP archive: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
+    /// A photomosaic tile library was built without any image.
+    #[error("The tile library contains no images")]
+    EmptyTileLibrary,
+
+    /// A tile library was resized for another tile size than the mosaic uses.
+    #[error("The tile library was built for {library}px tiles but the mosaic uses {mosaic}px tiles")]
+    TileLibrarySize { library: u32, mosaic: u32 },
+
+    /// A photomosaic repeat limit blocked every tile of the library for a cell.
+    #[error("The tile library has too few tiles ({tiles}) for a repeat limit of {repeat_limit}, add tiles or lower the limit")]
+    InsufficientTiles { tiles: usize, repeat_limit: u32 },
+
+    /// The hex layout was combined with a setting it cannot honor.
+    #[error("The hex layout does not support {0}")]
+    UnsupportedWithHexLayout(&'static str),
+
+    /// Adaptive tile sizes were combined with a setting they cannot honor.
+    #[error("Adaptive tile sizes do not support {0}")]
+    UnsupportedWithAdaptive(&'static str),
+
+    /// Cells following the aspect of the tile were combined with a setting that needs square cells.
+    #[error("Preserving the tile aspect does not support {0}, the cells are not square")]
+    UnsupportedWithTileAspect(&'static str),
+
+    /// A self-mosaic was combined with a setting that replaces its tile.
+    #[error("A self-mosaic does not support {0}, its tiles are the source itself")]
+    UnsupportedWithSelfTile(&'static str),
+
+    /// A streamed mosaic was combined with a setting that needs all of it at once.
+    #[error("Streaming the mosaic does not support {0}, it is never held in memory as a whole")]
+    UnsupportedWhenStreaming(&'static str),
+
+    /// A palette was given fewer than two colors.
+    #[error("A palette needs at least 2 colors, got {0}")]
+    PaletteTooSmall(usize),
+
+    /// A named setting (blend mode, ...) was given a value it does not know.
+    #[error("Invalid {kind} \"{value}\" (expected {expected})")]
+    InvalidValue { kind: &'static str, value: String, expected: &'static str },
+
+    /// The crop region does not lie within the source.
+    #[error("Crop {crop} (X,Y,W,H) exceeds the {width}x{height} source")]
+    CropOutOfBounds { crop: Crop, width: u32, height: u32 },
+
+    /// A grid was not given as `N` or `WxH` with positive numbers.
+    #[error("Invalid grid \"{0}\" (expected N or WxH, e.g. 100 or 120x80)")]
+    InvalidGrid(String),
+
+    /// The mosaic would be wider or higher than allowed.
+    #[error("Output of {width}x{height} is too large (at most {max_side}px per side), use a smaller tile size or input")]
+    OutputTooLarge { width: u64, height: u64, max_side: u32 },
+
+    /// The mosaic would have more pixels in total than allowed.
+    #[error("Output of {pixels} pixels is too large (at most {max_pixels} pixels), use a smaller tile size or input")]
+    TooManyPixels { pixels: u64, max_pixels: u64 },
+
+    /// Rendering was stopped through the flag given to
+    /// [`build_mosaic_cancellable`](crate::build_mosaic_cancellable).
+    #[error("The mosaic was cancelled before it was done")]
+    Cancelled,
+
+    /// A size or offset the mosaic is laid out with does not fit its integer type.
+    #[error("The {0} of the mosaic overflows, use a smaller tile size, gap, grid or input")]
+    Overflow(&'static str),
+
+    /// The worker thread pool could not be set up.
+    #[error("Could not start the thread pool: {0}")]
+    ThreadPool(#[from] rayon::ThreadPoolBuildError),
+
+    /// Reading or writing a stream failed.
+    #[error("I/O error: {0}")]
+    Io(#[from] io::Error),
+
+    /// Animated GIF output was combined with an option that only applies to a single image.
+    #[error("Animated GIF output does not support {0}, its frames are encoded as they are")]
+    UnsupportedWithAnimation(&'static str),
+
+    /// The output path does not end in an extension we can encode.
+    #[error("Unsupported output format for {0} (use .png, .jpg, .jpeg, .bmp, .tif, .tiff or .webp)")]
+    UnsupportedOutputFormat(String),
+
+    /// The output is WebP but WebP support was left out of the build.
+    #[error("Cannot write {0}, this build has no WebP support (the \"webp\" feature), use another output format")]
+    WebpSupportDisabled(String),
+
+    /// The mosaic is larger than the output format can store.
+    #[error("Output of {width}x{height} is too large for {format} ({limit}), use a smaller tile size or input or another format")]
+    TooLargeForFormat { format: String, width: u32, height: u32, limit: &'static str },
+
+    /// The mosaic could not be encoded.
+    #[error("Could not encode the mosaic: {0}")]
+    Encode(image::ImageError),
+
+    /// The mosaic could not be streamed as a PNG.
+    #[error("Could not encode the mosaic: {0}")]
+    EncodePng(#[from] png::EncodingError),
+
+    /// The output file already exists and overwriting it was not allowed.
+    #[error("{0} already exists, not overwriting it")]
+    OutputExists(String),
+
+    /// The directory of the output file does not exist.
+    #[error("Output directory {0} does not exist")]
+    OutputDirMissing(String),
+
+    /// The encoded mosaic could not be written to the output file.
+    #[error("Could not save {path}: {source}")]
+    Save { path: String, source: io::Error },
+}
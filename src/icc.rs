@@ -0,0 +1,108 @@
+use std::{borrow::Cow, io::Cursor};
+use image::{ColorType, ImageFormat};
+use crate::MosaicError;
+
+/// Identifier of the JPEG APP2 segments an ICC profile is stored in.
+const JPEG_ICC_MARKER: &[u8] = b"ICC_PROFILE\0";
+
+/// Largest part of a profile a single JPEG segment holds, its 16 bit length
+/// less the length itself, the identifier and the part numbers.
+const JPEG_ICC_PART: usize = 65535 - 2 - JPEG_ICC_MARKER.len() - 2;
+
+/// The ICC color profile embedded in encoded image `bytes`, if any. Only PNG
+/// (its iCCP chunk) and JPEG (its APP2 segments) are searched.
+pub fn icc_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    match image::guess_format(bytes).ok()? {
+        ImageFormat::Png => {
+            let reader = png::Decoder::new(Cursor::new(bytes)).read_info().ok()?;
+            reader.info().icc_profile.as_ref().map(|profile| profile.to_vec())
+        },
+        ImageFormat::Jpeg => jpeg_icc_profile(bytes),
+        _ => None,
+    }
+}
+
+/// The profile split over the APP2 segments of a JPEG, put back together in
+/// the order of their part numbers.
+fn jpeg_icc_profile(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut parts: Vec<(u8, &[u8])> = Vec::new();
+    let mut rest = bytes.get(2..)?;
+
+    // segments up to the start of the scan, the profile comes before it
+    while let [0xff, marker, high, low, ..] = *rest {
+        if marker == 0xda {
+            break;
+        }
+
+        let length = usize::from(u16::from_be_bytes([high, low]));
+        let segment = rest.get(4..2 + length)?;
+        if marker == 0xe2 && segment.starts_with(JPEG_ICC_MARKER) && segment.len() > JPEG_ICC_MARKER.len() + 2 {
+            parts.push((segment[JPEG_ICC_MARKER.len()], &segment[JPEG_ICC_MARKER.len() + 2..]));
+        }
+        rest = &rest[2 + length..];
+    }
+
+    if parts.is_empty() {
+        return None;
+    }
+    parts.sort_by_key(|&(number, _)| number);
+
+    Some(parts.into_iter().flat_map(|(_, part)| part.iter().copied()).collect())
+}
+
+/// `jpeg` with `profile` in APP2 segments right after its JFIF header, or
+/// after its start without one.
+pub(crate) fn with_jpeg_icc(jpeg: &[u8], profile: &[u8]) -> Vec<u8> {
+    let jfif = match jpeg {
+        [0xff, 0xd8, 0xff, 0xe0, high, low, ..] => 2 + 2 + usize::from(u16::from_be_bytes([*high, *low])),
+        _ => 2,
+    };
+    let (header, rest) = jpeg.split_at(jfif.min(jpeg.len()));
+
+    let parts: Vec<_> = profile.chunks(JPEG_ICC_PART).collect();
+    let mut bytes = header.to_vec();
+
+    for (number, part) in parts.iter().enumerate() {
+        let length = (2 + JPEG_ICC_MARKER.len() + 2 + part.len()) as u16;
+
+        bytes.extend_from_slice(&[0xff, 0xe2]);
+        bytes.extend_from_slice(&length.to_be_bytes());
+        bytes.extend_from_slice(JPEG_ICC_MARKER);
+        bytes.extend_from_slice(&[number as u8 + 1, parts.len() as u8]);
+        bytes.extend_from_slice(part);
+    }
+    bytes.extend_from_slice(rest);
+
+    bytes
+}
+
+/// Encodes `width` × `height` pixels of `channels`, laid out as `color`, as
+/// a PNG carrying `profile` in its iCCP chunk.
+pub(crate) fn png_with_icc(channels: &[u8], width: u32, height: u32, color: ColorType, profile: &[u8]) -> Result<Vec<u8>, MosaicError> {
+    let (color_type, depth) = match color {
+        ColorType::L8 => (png::ColorType::Grayscale, png::BitDepth::Eight),
+        ColorType::La8 => (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight),
+        ColorType::L16 => (png::ColorType::Grayscale, png::BitDepth::Sixteen),
+        ColorType::La16 => (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen),
+        ColorType::Rgba16 => (png::ColorType::Rgba, png::BitDepth::Sixteen),
+        _ => (png::ColorType::Rgba, png::BitDepth::Eight),
+    };
+
+    let mut info = png::Info::with_size(width, height);
+    info.color_type = color_type;
+    info.bit_depth = depth;
+    info.icc_profile = Some(Cow::Borrowed(profile));
+
+    // PNG stores 16 bit channels big endian
+    let channels = match depth {
+        png::BitDepth::Sixteen => Cow::Owned(channels.chunks_exact(2).flat_map(|pair| u16::from_ne_bytes([pair[0], pair[1]]).to_be_bytes()).collect()),
+        _ => Cow::Borrowed(channels),
+    };
+
+    let mut bytes = Vec::new();
+    let mut writer = png::Encoder::with_info(&mut bytes, info)?.write_header()?;
+    writer.write_image_data(&channels)?;
+    writer.finish()?;
+
+    Ok(bytes)
+}
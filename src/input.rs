@@ -0,0 +1,324 @@
+use std::{env, fs, io::Cursor, path::Path};
+use image::{
+    codecs::{gif::GifDecoder, jpeg::JpegDecoder},
+    error::{DecodingError, ImageFormatHint, UnsupportedError, UnsupportedErrorKind},
+    io::Reader,
+    AnimationDecoder, DynamicImage, GenericImageView, ImageBuffer, ImageDecoder, ImageError, ImageFormat, Rgba,
+};
+use tiff::{decoder::{Decoder as TiffDecoder, DecodingResult}, ColorType as TiffColor};
+use crate::{depth::is_sixteen_bit, render::average_cells, Grid, MosaicError};
+
+/// Decodes an encoded image held in memory.
+///
+/// The format is sniffed from the magic number, falling back to the extension
+/// of `name` (the path or URL the bytes came from, also used in errors) for
+/// formats without one. An image without pixels is a
+/// [`MosaicError::EmptyImage`].
+pub fn decode_image(bytes: &[u8], name: &str) -> Result<DynamicImage, MosaicError> {
+    let mut reader = Reader::new(Cursor::new(bytes)).with_guessed_format()?;
+
+    if reader.format().is_none() {
+        if let Ok(format) = ImageFormat::from_path(name) {
+            reader.set_format(format);
+        }
+    }
+
+    let img = reader.decode().map_err(|source| MosaicError::Decode {
+        input: name.to_string(),
+        source,
+    })?;
+
+    // some malformed files still decode, to nothing
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return Err(MosaicError::EmptyImage {
+            input: name.to_string(),
+            width,
+            height,
+        });
+    }
+
+    Ok(img)
+}
+
+/// Decodes frame `frame`, counting from 1, of an animated GIF or page of a
+/// multi-page TIFF held in memory, for a mosaic of that frame alone. GIF
+/// frames are composited over the frames before them, as they are shown.
+/// Other formats only have frame 1, decoded as with [`decode_image`].
+///
+/// A frame past the last, or frame 0, is a [`MosaicError::FrameOutOfRange`]
+/// telling how many frames there are.
+pub fn decode_frame(bytes: &[u8], name: &str, frame: u32) -> Result<DynamicImage, MosaicError> {
+    let format = image::guess_format(bytes).ok().or_else(|| ImageFormat::from_path(name).ok());
+    let out_of_range = |frames| MosaicError::FrameOutOfRange {
+        input: name.to_string(),
+        frame,
+        frames,
+    };
+    let decode_error = |source| MosaicError::Decode {
+        input: name.to_string(),
+        source,
+    };
+
+    match format {
+        Some(ImageFormat::Gif) => {
+            let gif = || GifDecoder::new(Cursor::new(bytes)).map_err(decode_error);
+
+            // counting the frames takes decoding them all, only worth it for the error
+            let Some(index) = frame.checked_sub(1) else {
+                return Err(out_of_range(gif()?.into_frames().count() as u32));
+            };
+            match gif()?.into_frames().nth(index as usize) {
+                Some(decoded) => Ok(DynamicImage::ImageRgba8(decoded.map_err(decode_error)?.into_buffer())),
+                None => Err(out_of_range(gif()?.into_frames().count() as u32)),
+            }
+        },
+        Some(ImageFormat::Tiff) if frame > 1 => tiff_page(bytes, name, frame),
+        _ if frame == 1 => decode_image(bytes, name),
+        Some(ImageFormat::Tiff) => Err(out_of_range(tiff_page_count(bytes, name)?)),
+        _ => {
+            // everything else is a still image, as long as it decodes
+            decode_image(bytes, name)?;
+            Err(out_of_range(1))
+        },
+    }
+}
+
+// the image crate only ever decodes the first page of a TIFF
+fn tiff_page(bytes: &[u8], name: &str, page: u32) -> Result<DynamicImage, MosaicError> {
+    let mut decoder = TiffDecoder::new(Cursor::new(bytes)).map_err(|err| tiff_error(name, err))?;
+
+    let mut pages = 1;
+    while pages < page && decoder.more_images() {
+        decoder.next_image().map_err(|err| tiff_error(name, err))?;
+        pages += 1;
+    }
+    if pages < page {
+        return Err(MosaicError::FrameOutOfRange {
+            input: name.to_string(),
+            frame: page,
+            frames: pages,
+        });
+    }
+
+    let (width, height) = decoder.dimensions().map_err(|err| tiff_error(name, err))?;
+    let color = decoder.colortype().map_err(|err| tiff_error(name, err))?;
+    let pixels = decoder.read_image().map_err(|err| tiff_error(name, err))?;
+
+    let img = match (color, pixels) {
+        (TiffColor::Gray(8), DecodingResult::U8(pixels)) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma8),
+        (TiffColor::GrayA(8), DecodingResult::U8(pixels)) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA8),
+        (TiffColor::RGB(8), DecodingResult::U8(pixels)) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb8),
+        (TiffColor::RGBA(8), DecodingResult::U8(pixels)) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba8),
+        (TiffColor::Gray(16), DecodingResult::U16(pixels)) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLuma16),
+        (TiffColor::GrayA(16), DecodingResult::U16(pixels)) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageLumaA16),
+        (TiffColor::RGB(16), DecodingResult::U16(pixels)) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgb16),
+        (TiffColor::RGBA(16), DecodingResult::U16(pixels)) => ImageBuffer::from_raw(width, height, pixels).map(DynamicImage::ImageRgba16),
+        _ => {
+            let kind = UnsupportedErrorKind::GenericFeature(format!("{:?} pages", color));
+            return Err(MosaicError::Decode {
+                input: name.to_string(),
+                source: ImageError::Unsupported(UnsupportedError::from_format_and_kind(ImageFormat::Tiff.into(), kind)),
+            });
+        },
+    };
+
+    img.ok_or_else(|| tiff_error(name, "the page holds fewer pixels than its dimensions"))
+}
+
+fn tiff_page_count(bytes: &[u8], name: &str) -> Result<u32, MosaicError> {
+    let mut decoder = TiffDecoder::new(Cursor::new(bytes)).map_err(|err| tiff_error(name, err))?;
+
+    let mut pages = 1;
+    while decoder.more_images() {
+        decoder.next_image().map_err(|err| tiff_error(name, err))?;
+        pages += 1;
+    }
+
+    Ok(pages)
+}
+
+fn tiff_error(name: &str, err: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> MosaicError {
+    MosaicError::Decode {
+        input: name.to_string(),
+        source: ImageError::Decoding(DecodingError::new(ImageFormatHint::Exact(ImageFormat::Tiff), err)),
+    }
+}
+
+/// Decodes an encoded image held in memory straight down to the cells of
+/// `grid`, every cell the mean of the pixels it covers as with
+/// [`SampleMode::Average`](crate::SampleMode::Average), for sources too large
+/// to hold in full.
+///
+/// PNG is decoded row by row into the cells, never holding more than a row,
+/// unless it is interlaced. JPEG is decoded at the smallest of 1/8, 1/4, 1/2
+/// and full scale still giving every cell 4 × 4 pixels, which saves up to 64
+/// times the memory and comes within a few levels of the full decode. Other
+/// formats, and grids finer than the source, are decoded in full and then
+/// averaged, which only saves the memory of the later steps. Errors are those
+/// of [`decode_image`].
+pub fn decode_downsampled(bytes: &[u8], name: &str, grid: Grid) -> Result<DynamicImage, MosaicError> {
+    let format = image::guess_format(bytes).ok().or_else(|| ImageFormat::from_path(name).ok());
+
+    // anything the reduced decoders cannot handle, errors included, takes the full path
+    let reduced = match format {
+        Some(ImageFormat::Png) => png_cells(bytes, grid),
+        Some(ImageFormat::Jpeg) => scaled_jpeg(bytes, grid),
+        _ => None,
+    };
+    if let Some(img) = reduced {
+        return Ok(img);
+    }
+
+    let img = decode_image(bytes, name)?;
+    let (width, height) = grid.dimensions(img.dimensions());
+
+    Ok(if is_sixteen_bit(img.color()) {
+        DynamicImage::ImageRgba16(average_cells(&img.to_rgba16(), width, height))
+    } else {
+        DynamicImage::ImageRgba8(average_cells(&img.to_rgba8(), width, height))
+    })
+}
+
+/// The cells of `grid` summed up from the rows of the PNG `bytes` as they
+/// are decoded.
+fn png_cells(bytes: &[u8], grid: Grid) -> Option<DynamicImage> {
+    let mut decoder = png::Decoder::new(Cursor::new(bytes));
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().ok()?;
+
+    let (width_orig, height_orig) = (reader.info().width, reader.info().height);
+    let (width, height) = grid.dimensions((width_orig, height_orig));
+
+    // interlaced rows come pass by pass, and cells without a pixel of their own need the source
+    if reader.info().interlaced || width > width_orig || height > height_orig {
+        return None;
+    }
+
+    let (color, depth) = reader.output_color_type();
+    let sixteen_bit = depth == png::BitDepth::Sixteen;
+    let max = if sixteen_bit { u64::from(u16::MAX) } else { u64::from(u8::MAX) };
+
+    let cell = |x: u32, size_orig: u32, size: u32| (u64::from(x) * u64::from(size) / u64::from(size_orig)) as usize;
+    let mut sums = vec![[0u64; 5]; width as usize * height as usize];
+
+    for y in 0..height_orig {
+        let row = reader.next_row().ok()??;
+        let samples: Vec<u64> = if sixteen_bit {
+            row.data().chunks_exact(2).map(|bytes| u64::from(u16::from_be_bytes([bytes[0], bytes[1]]))).collect()
+        } else {
+            row.data().iter().map(|&sample| u64::from(sample)).collect()
+        };
+        let row_sums = &mut sums[cell(y, height_orig, height) * width as usize..][..width as usize];
+
+        for (x, pixel) in samples.chunks_exact(color.samples()).enumerate() {
+            let rgba = match *pixel {
+                [l] => [l, l, l, max],
+                [l, a] => [l, l, l, a],
+                [r, g, b] => [r, g, b, max],
+                [r, g, b, a] => [r, g, b, a],
+                _ => return None,
+            };
+            let sum = &mut row_sums[cell(x as u32, width_orig, width)];
+
+            for (total, channel) in sum.iter_mut().zip(rgba) {
+                *total += channel;
+            }
+            sum[4] += 1;
+        }
+    }
+
+    let mean = |x: u32, y: u32| {
+        let sum = sums[y as usize * width as usize + x as usize];
+        [0, 1, 2, 3].map(|channel| (sum[channel] + sum[4] / 2) / sum[4])
+    };
+
+    Some(if sixteen_bit {
+        DynamicImage::ImageRgba16(ImageBuffer::from_fn(width, height, |x, y| Rgba(mean(x, y).map(|channel| channel as u16))))
+    } else {
+        DynamicImage::ImageRgba8(ImageBuffer::from_fn(width, height, |x, y| Rgba(mean(x, y).map(|channel| channel as u8))))
+    })
+}
+
+/// The cells of `grid` averaged from the JPEG `bytes` decoded at a reduced
+/// scale.
+fn scaled_jpeg(bytes: &[u8], grid: Grid) -> Option<DynamicImage> {
+    let mut decoder = JpegDecoder::new(Cursor::new(bytes)).ok()?;
+    let (width, height) = grid.dimensions(decoder.dimensions());
+
+    // the decoder picks the smallest scale at least this large, and cells
+    // spanning several pixels barely shift where the scaled rows fall
+    let side = |side: u32| side.saturating_mul(4).min(u32::from(u16::MAX)) as u16;
+    decoder.scale(side(width), side(height)).ok()?;
+
+    let img = DynamicImage::from_decoder(decoder).ok()?;
+
+    Some(DynamicImage::ImageRgba8(average_cells(&img.to_rgba8(), width, height)))
+}
+
+/// Reads and decodes the image file at `path`.
+pub fn open_image(path: &Path) -> Result<DynamicImage, MosaicError> {
+    let bytes = fs::read(path).map_err(|source| MosaicError::Read {
+        path: path.display().to_string(),
+        source,
+    })?;
+
+    decode_image(&bytes, &path.display().to_string())
+}
+
+/// Expands a leading `~` to the home directory and `$VAR`, `${VAR}` and
+/// `%VAR%` to the value of the environment variable, as a shell would for
+/// an unquoted path. Unset variables, `~user` and URLs are left as they are.
+pub fn expand_path(path: &str) -> String {
+    if path.contains("://") {
+        return path.to_string();
+    }
+
+    let mut expanded = String::with_capacity(path.len());
+    let mut rest = path;
+
+    if let Some(after) = path.strip_prefix('~').filter(|after| after.is_empty() || after.starts_with(['/', '\\'])) {
+        if let Some(home) = dirs::home_dir() {
+            expanded.push_str(&home.to_string_lossy());
+            rest = after;
+        }
+    }
+
+    while let Some(start) = rest.find(['$', '%']) {
+        expanded.push_str(&rest[..start]);
+
+        match variable_at(&rest[start..]).and_then(|(name, len)| Some((env::var(name).ok()?, len))) {
+            Some((value, len)) => {
+                expanded.push_str(&value);
+                rest = &rest[start + len..];
+            },
+            None => {
+                expanded.push_str(&rest[start..=start]);
+                rest = &rest[start + 1..];
+            },
+        }
+    }
+    expanded.push_str(rest);
+
+    expanded
+}
+
+/// The name of the variable `text` starts with as `$NAME`, `${NAME}` or
+/// `%NAME%`, and the length of that reference.
+fn variable_at(text: &str) -> Option<(&str, usize)> {
+    let is_name = |c: char| c.is_ascii_alphanumeric() || c == '_';
+
+    if let Some(braced) = text.strip_prefix("${") {
+        let end = braced.find('}')?;
+        return Some((&braced[..end], end + 3));
+    }
+
+    if let Some(bare) = text.strip_prefix('$') {
+        let end = bare.find(|c| !is_name(c)).unwrap_or(bare.len());
+        return (end > 0).then(|| (&bare[..end], end + 1));
+    }
+
+    let percent = text.strip_prefix('%')?;
+    let end = percent.find('%')?;
+    (end > 0 && percent[..end].chars().all(is_name)).then(|| (&percent[..end], end + 2))
+}
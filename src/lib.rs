@@ -0,0 +1,417 @@
+//! MosaicMaker
+//!
+//! Creates a mosaic image: every pixel of the source image becomes a
+//! `tile_size` × `tile_size` thumbnail of the source, tinted with the color of
+//! that pixel. Given a [`TileLibrary`] it builds a true photomosaic instead,
+//! picking the best matching tile image for every pixel.
+//!
+//! Tiles are rendered in parallel on the global rayon thread pool, unless
+//! [`MosaicConfigBuilder::threads`] asks for a dedicated one.
+//!
+//! The `image` crate is re-exported so callers can build the `DynamicImage`
+//! passed to [`build_mosaic`] with the exact version this crate is built with.
+//!
+//! Downloading URL inputs needs `reqwest` and is behind the default `url`
+//! feature. Without it the crate only works on local files and memory, see
+//! [`mosaic_bytes`]. WebP output needs libwebp and the optional `webp`
+//! feature.
+//!
+//! The optional `wasm` feature exports `mosaic` through wasm-bindgen for
+//! builds for `wasm32-unknown-unknown` without the `url` feature, see
+//! `examples/browser`. There rayon finds no threads to spawn and renders on
+//! the calling thread, and [`MosaicConfigBuilder::threads`] is an error.
+mod animation;
+mod blend;
+mod color;
+mod config;
+mod depth;
+mod error;
+mod icc;
+mod input;
+mod orientation;
+mod output;
+mod palette;
+mod photomosaic;
+mod ramp;
+mod render;
+mod shape;
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use animation::build_gif_mosaic;
+pub use blend::BlendMode;
+pub use color::{grayscale, hsv_to_rgb, invert, linear_to_srgb, luminance, parse_color, rgb_to_hsv, srgb_to_lab, srgb_to_linear, GrayMethod};
+pub use config::{parse_filter, Crop, Fit, Grid, MosaicConfig, MosaicConfigBuilder, RenderMode, SampleMode};
+pub use depth::BitDepth;
+pub use error::MosaicError;
+pub use icc::icc_profile;
+pub use image;
+pub use image::{imageops::FilterType, DynamicImage, GenericImageView, ImageBuffer, ImageFormat, Rgba, RgbaImage};
+pub use input::{decode_downsampled, decode_frame, decode_image, expand_path, open_image};
+pub use orientation::{apply_orientation, exif_orientation};
+pub use output::{compare_images, default_output_name, encode_mosaic, flatten, flatten16, is_gray, output_format, parse_format, save_mosaic, sharpen, shift_colors, stream_mosaic_png, Difference, EncodeOptions, DEFAULT_BACKGROUND};
+pub use palette::Palette;
+pub use photomosaic::{MatchSpace, TileLibrary, TileMatcher};
+pub use ramp::ColorRamp;
+pub use shape::{Layout, TilePattern, TileShape};
+#[cfg(feature = "wasm")]
+pub use wasm::mosaic;
+
+use std::sync::atomic::AtomicBool;
+use depth::Channel;
+use image::imageops;
+use render::{oriented_source, sampled_grid, Hex, Renderer};
+
+/// Alpha of the color tile unless configured otherwise.
+pub const DEFAULT_ALPHA: u8 = 127;
+
+/// Gamma of the linear light conversion unless configured otherwise.
+pub const DEFAULT_GAMMA: f32 = 2.2;
+
+/// Color of the solid pattern and of the checkerboard squares at its corners
+/// unless configured otherwise.
+pub const DEFAULT_PATTERN_COLOR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Color of the other checkerboard squares unless configured otherwise.
+pub const DEFAULT_CHECKER_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// Side of the checkerboard squares unless configured otherwise.
+pub const DEFAULT_CHECKER_SIZE: u32 = 4;
+
+/// Color of the gaps between tiles unless configured otherwise.
+pub const DEFAULT_GAP_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// Color of the borders inside the tiles unless configured otherwise.
+pub const DEFAULT_BORDER_COLOR: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// Largest width or height of a mosaic unless told otherwise.
+pub const DEFAULT_MAX_SIDE: u32 = 30_000;
+
+/// Source alpha below which cells are skipped unless configured otherwise,
+/// see [`MosaicConfigBuilder::skip_transparent`].
+pub const DEFAULT_ALPHA_THRESHOLD: u8 = 128;
+
+/// Percentage of the darkest and of the lightest cells auto levels ignore
+/// unless configured otherwise, see [`MosaicConfigBuilder::auto_levels`].
+pub const DEFAULT_LEVELS_CLIP: f32 = 0.5;
+
+/// Longest side of the mosaic an automatic tile size aims at unless told
+/// otherwise, see [`MosaicConfigBuilder::auto_tile_size`].
+pub const DEFAULT_AUTO_SIDE: u32 = 4000;
+
+/// Largest anti-aliasing factor, see [`MosaicConfigBuilder::antialias`].
+pub const MAX_ANTIALIAS: u32 = 16;
+
+/// Largest pixel count of a mosaic unless told otherwise.
+pub const DEFAULT_MAX_PIXELS: u64 = 500_000_000;
+
+/// Standard deviation of the luminance of the source under an adaptive tile,
+/// on the 8 bit scale, above which it is split unless configured otherwise.
+pub const DEFAULT_ADAPTIVE_THRESHOLD: f32 = 12.0;
+
+/// Largest size in bytes of a single RGBA tile buffer, 64 MiB or 4096 ×
+/// 4096 pixels.
+pub const MAX_TILE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Builds a mosaic out of `source`, one `tile_size` × `tile_size` tile per
+/// source pixel (or grid cell), as described by `config`.
+///
+/// The returned image is `tile_size` times larger than `source` (or the grid)
+/// on each side, plus any configured gaps between the tiles,
+/// and is refused with [`MosaicError::OutputTooLarge`] if either side would
+/// exceed the configured maximum, or with [`MosaicError::TooManyPixels`] if
+/// the total pixel count would. With
+/// [`MosaicConfigBuilder::output_size`] it is exactly that size instead.
+///
+/// The mosaic is an [`DynamicImage::ImageRgba16`] when rendered in 16 bits,
+/// see [`MosaicConfigBuilder::bit_depth`], and an
+/// [`DynamicImage::ImageRgba8`] otherwise.
+pub fn build_mosaic(source: &DynamicImage, config: &MosaicConfig) -> Result<DynamicImage, MosaicError> {
+    on_pool(config, || render(source, config, None))
+}
+
+/// [`build_mosaic`], stopping early with [`MosaicError::Cancelled`] once
+/// `cancel` is set, such as when the client of a server goes away. Every
+/// worker checks it before each row of tiles, so the rows being rendered are
+/// finished first, and the unfinished mosaic is dropped.
+pub fn build_mosaic_cancellable(source: &DynamicImage, config: &MosaicConfig, cancel: &AtomicBool) -> Result<DynamicImage, MosaicError> {
+    on_pool(config, || render(source, config, Some(cancel)))
+}
+
+/// Builds the mosaic of the encoded image `input` and encodes it as `format`,
+/// entirely in memory.
+///
+/// The input is turned upright from its EXIF orientation first, and formats
+/// without alpha are flattened onto [`DEFAULT_BACKGROUND`]. An animated GIF
+/// input with [`ImageFormat::Gif`] output is processed frame by frame, see
+/// [`build_gif_mosaic`].
+pub fn mosaic_bytes(input: &[u8], config: &MosaicConfig, format: ImageFormat) -> Result<Vec<u8>, MosaicError> {
+    if format == ImageFormat::Gif && image::guess_format(input).ok() == Some(ImageFormat::Gif) {
+        return build_gif_mosaic(input, config);
+    }
+
+    let mut source = decode_image(input, "the input")?;
+
+    if let Some(orientation) = exif_orientation(input) {
+        source = apply_orientation(source, orientation);
+    }
+
+    encode_mosaic(&build_mosaic(&source, config)?, format, &EncodeOptions::default())
+}
+
+/// The colors the cells of the mosaic of `source` are sampled from, one
+/// pixel per cell, before any tile is drawn: the source turned, cropped,
+/// adjusted and downsampled to the grid as `config` asks, in its
+/// [`sample`](MosaicConfigBuilder::sample) mode. Levels, palettes and tints
+/// are left out.
+///
+/// Crops outside of the source are a [`MosaicError::CropOutOfBounds`].
+pub fn color_grid(source: &DynamicImage, config: &MosaicConfig) -> Result<DynamicImage, MosaicError> {
+    unchecked_dimensions(source.dimensions(), config)?;
+    let source = oriented_source(source, config);
+
+    Ok(match config.bit_depth.bits(&source) {
+        16 => u16::dynamic(sampled_grid::<u16>(&source, config)),
+        _ => u8::dynamic(sampled_grid::<u8>(&source, config)),
+    })
+}
+
+/// Runs `job` on the dedicated thread pool asked for by `config`, if any.
+pub(crate) fn on_pool<T: Send>(config: &MosaicConfig, job: impl FnOnce() -> Result<T, MosaicError> + Send) -> Result<T, MosaicError> {
+    match config.threads {
+        Some(threads) => rayon::ThreadPoolBuilder::new().num_threads(threads).build()?.install(job),
+        None => job(),
+    }
+}
+
+fn render(source: &DynamicImage, config: &MosaicConfig, cancel: Option<&AtomicBool>) -> Result<DynamicImage, MosaicError> {
+    match config.bit_depth.bits(source) {
+        16 => render_as::<u16>(source, config, cancel),
+        _ => render_as::<u8>(source, config, cancel),
+    }
+}
+
+fn render_as<T: Channel>(source: &DynamicImage, config: &MosaicConfig, cancel: Option<&AtomicBool>) -> Result<DynamicImage, MosaicError> {
+    let mut renderer = Renderer::<T>::new(source, config)?;
+    if let Some(cancel) = cancel {
+        renderer.cancel_on(cancel);
+    }
+    let (width_new, height_new) = renderer.dimensions();
+
+    check_buffer((width_new, height_new), "buffer")?;
+
+    // create new image
+    let mut img_new = ImageBuffer::new(width_new, height_new);
+    match config.adaptive {
+        Some(tile_sizes) => renderer.render_adaptive(&mut img_new, tile_sizes),
+        None => {
+            let (top, bands) = img_new.split_at_mut(renderer.top_len());
+            renderer.fill_background(top);
+            renderer.render_bands(bands, 0);
+        },
+    }
+
+    if renderer.cancelled() {
+        return Err(MosaicError::Cancelled);
+    }
+
+    // the tiles rarely divide a fixed output size evenly
+    let img_new = match config.output_size {
+        Some(size) if size != (width_new, height_new) => fit_to(&img_new, size, config.fit, config.filter.unwrap_or(FilterType::Triangle)),
+        _ => img_new,
+    };
+
+    Ok(T::dynamic(img_new))
+}
+
+/// Resizes `img` to exactly `width` × `height` with `filter`, keeping its
+/// aspect ratio as `fit` asks.
+fn fit_to<T: Channel>(img: &ImageBuffer<Rgba<T>, Vec<T>>, (width, height): (u32, u32), fit: Fit, filter: FilterType) -> ImageBuffer<Rgba<T>, Vec<T>> {
+    let scales = (f64::from(width) / f64::from(img.width()), f64::from(height) / f64::from(img.height()));
+    let scaled = |scale: f64| ((f64::from(img.width()) * scale).round() as u32, (f64::from(img.height()) * scale).round() as u32);
+
+    match fit {
+        Fit::Stretch => imageops::resize(img, width, height, filter),
+        Fit::Contain => {
+            let (scaled_width, scaled_height) = scaled(scales.0.min(scales.1));
+            let (scaled_width, scaled_height) = (scaled_width.clamp(1, width), scaled_height.clamp(1, height));
+
+            let mut fitted = ImageBuffer::new(width, height);
+            imageops::replace(&mut fitted, &imageops::resize(img, scaled_width, scaled_height, filter), (width - scaled_width) / 2, (height - scaled_height) / 2);
+            fitted
+        },
+        Fit::Cover => {
+            let (scaled_width, scaled_height) = scaled(scales.0.max(scales.1));
+            let (scaled_width, scaled_height) = (scaled_width.max(width), scaled_height.max(height));
+
+            let scaled = imageops::resize(img, scaled_width, scaled_height, filter);
+            imageops::crop_imm(&scaled, (scaled_width - width) / 2, (scaled_height - height) / 2, width, height).to_image()
+        },
+    }
+}
+
+/// Thumbnails `img` to exactly `tile_size` × `tile_size`, center-cropping
+/// non-square images first so the tile fills its whole cell.
+pub fn square_thumbnail(img: &DynamicImage, tile_size: u32) -> RgbaImage {
+    square_tile(img, tile_size).to_rgba8()
+}
+
+/// [`square_thumbnail`], keeping the color type of `img`.
+pub(crate) fn square_tile(img: &DynamicImage, tile_size: u32) -> DynamicImage {
+    let (width, height) = img.dimensions();
+    let side = width.min(height);
+
+    img.crop_imm((width - side) / 2, (height - side) / 2, side, side)
+        .thumbnail_exact(tile_size, tile_size)
+}
+
+/// Computes the size of the mosaic `config` builds out of a `width` × `height`
+/// source, without rendering anything, or the [`MosaicError::EmptyImage`],
+/// [`MosaicError::CropOutOfBounds`], [`MosaicError::OutputTooLarge`],
+/// [`MosaicError::TooManyPixels`] or [`MosaicError::Overflow`] that
+/// [`build_mosaic`] would fail with.
+pub fn mosaic_dimensions((width, height): (u32, u32), config: &MosaicConfig) -> Result<(u32, u32), MosaicError> {
+    let (_, _, dimensions) = rendered_dimensions((width, height), config)?;
+
+    Ok(config.output_size.unwrap_or(dimensions))
+}
+
+/// The tile size the mosaic of a `width` × `height` source is rendered with,
+/// which only differs from [`MosaicConfig::tile_size`] with an output size or
+/// an automatic tile size, or the error [`mosaic_dimensions`] would fail with.
+pub fn mosaic_tile_size((width, height): (u32, u32), config: &MosaicConfig) -> Result<u32, MosaicError> {
+    let (tile_size, _, _) = rendered_dimensions((width, height), config)?;
+
+    Ok(tile_size)
+}
+
+/// Estimates the bytes of the RGBA mosaic `config` builds out of a `width` ×
+/// `height` source, without rendering anything, so an embedder can tell up
+/// front whether it fits in memory. The grid, crop, rotation, gaps, layout,
+/// tile aspect and output size are all accounted for, and so are the limits
+/// of `config`: a mosaic over them is estimated all the same, even though
+/// [`build_mosaic`] refuses it.
+///
+/// Channels count 2 bytes with [`BitDepth::Sixteen`] and 1 byte otherwise;
+/// with [`BitDepth::Auto`] a source with 16 bit channels takes twice the
+/// estimate. Sources [`build_mosaic`] refuses outright, empty ones or ones
+/// the crop lies outside of, are estimated at 0, and mosaics too large to
+/// lay out at all at `u64::MAX`.
+pub fn estimate_output_bytes((width, height): (u32, u32), config: &MosaicConfig) -> u64 {
+    let (width, height) = match (unchecked_dimensions((width, height), config), config.output_size) {
+        (Err(MosaicError::Overflow(_)), _) => return u64::MAX,
+        (Err(_), _) => return 0,
+        (Ok(_), Some((width, height))) => (u64::from(width), u64::from(height)),
+        (Ok((_, _, dimensions)), None) => dimensions,
+    };
+    let channel_bytes = if config.bit_depth == BitDepth::Sixteen { 2 } else { 1 };
+
+    width.saturating_mul(height).saturating_mul(4 * channel_bytes)
+}
+
+/// The tile size the mosaic of a `width` × `height` source is rendered with,
+/// the height of its cells and its size before any resize to the output size.
+pub(crate) fn rendered_dimensions((width, height): (u32, u32), config: &MosaicConfig) -> Result<(u32, u32, (u32, u32)), MosaicError> {
+    let (tile_size, tile_height, (width_new, height_new)) = unchecked_dimensions((width, height), config)?;
+
+    check_tile_size(tile_size.max(tile_height), config.max_pixels)?;
+    let (width_new, height_new) = check_max_side(width_new, height_new, config.max_side)?;
+    check_pixels(width_new, height_new, config.max_pixels)?;
+
+    Ok((tile_size, tile_height, (width_new, height_new)))
+}
+
+/// [`rendered_dimensions`] before the limits of `config` are checked, only
+/// refusing empty sources and crops outside of them.
+fn unchecked_dimensions((width, height): (u32, u32), config: &MosaicConfig) -> Result<(u32, u32, (u64, u64)), MosaicError> {
+    if width == 0 || height == 0 {
+        return Err(MosaicError::EmptyImage {
+            input: "The source".to_string(),
+            width,
+            height,
+        });
+    }
+
+    // a quarter turn swaps the sides
+    let (width, height) = match config.rotation {
+        90 | 270 => (height, width),
+        _ => (width, height),
+    };
+
+    let (width, height) = match config.crop {
+        Some(crop) => crop.dimensions((width, height))?,
+        None => (width, height),
+    };
+
+    let cells = match config.grid {
+        Some(grid) => grid.dimensions((width, height)),
+        None => (width, height),
+    };
+
+    let tile_size = config.tile_size_for(cells);
+    let tile_height = config.tile_height(tile_size, (width, height));
+    let dimensions = match config.layout {
+        Layout::Square => (side(cells.0, tile_size, config.gap, "width")?, side(cells.1, tile_height, config.gap, "height")?),
+        Layout::Hex => Hex::new(tile_size).dimensions(cells),
+    };
+
+    Ok((tile_size, tile_height, dimensions))
+}
+
+/// Computes the size of the mosaic of a `width` × `height` source with `gap`
+/// pixels around every tile, refusing anything wider or higher than
+/// `max_side` (or than `u32` can hold).
+pub fn output_dimensions((width, height): (u32, u32), tile_size: u32, gap: u32, max_side: u32) -> Result<(u32, u32), MosaicError> {
+    check_max_side(side(width, tile_size, gap, "width")?, side(height, tile_size, gap, "height")?, max_side)
+}
+
+/// Pixels spanned by `cells` tiles `tile_size` long with `gap` pixels around each.
+fn side(cells: u32, tile_size: u32, gap: u32, what: &'static str) -> Result<u64, MosaicError> {
+    let pitch = u64::from(tile_size) + u64::from(gap);
+    checked(u64::from(cells).checked_mul(pitch).and_then(|side| side.checked_add(gap.into())), what)
+}
+
+/// Refuses RGBA buffers of `width` × `height` pixels whose channels cannot
+/// be counted in a `usize`, within the limits on 64 bit targets but maybe not
+/// on 32 bit ones, as a [`MosaicError::Overflow`] of `what`.
+pub(crate) fn check_buffer((width, height): (u32, u32), what: &'static str) -> Result<(), MosaicError> {
+    let channels = (u64::from(width) * u64::from(height)).checked_mul(4);
+    checked(channels.and_then(|channels| usize::try_from(channels).ok()), what).map(|_| ())
+}
+
+/// The result of checked arithmetic, or a [`MosaicError::Overflow`] of
+/// `what` if it wrapped.
+pub(crate) fn checked<T>(value: Option<T>, what: &'static str) -> Result<T, MosaicError> {
+    value.ok_or(MosaicError::Overflow(what))
+}
+
+pub(crate) fn check_max_side(width: u64, height: u64, max_side: u32) -> Result<(u32, u32), MosaicError> {
+    if width > u64::from(max_side) || height > u64::from(max_side) {
+        return Err(MosaicError::OutputTooLarge { width, height, max_side });
+    }
+
+    Ok((width as u32, height as u32))
+}
+
+/// Refuses tiles whose RGBA buffer takes more than [`MAX_TILE_BYTES`], or
+/// more pixels than the whole mosaic may have.
+pub(crate) fn check_tile_size(tile_size: u32, max_pixels: u64) -> Result<(), MosaicError> {
+    let bytes = checked(u64::from(tile_size).checked_mul(tile_size.into()).and_then(|pixels| pixels.checked_mul(4)), "tile size")?;
+    let max_bytes = MAX_TILE_BYTES.min(max_pixels.saturating_mul(4));
+
+    if bytes > max_bytes {
+        return Err(MosaicError::TileTooLarge { tile_size, bytes, max_bytes });
+    }
+
+    Ok(())
+}
+
+pub(crate) fn check_pixels(width: u32, height: u32, max_pixels: u64) -> Result<(), MosaicError> {
+    let pixels = u64::from(width) * u64::from(height);
+
+    if pixels > max_pixels {
+        return Err(MosaicError::TooManyPixels { pixels, max_pixels });
+    }
+
+    Ok(())
+}
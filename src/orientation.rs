@@ -0,0 +1,24 @@
+use std::io::Cursor;
+use image::DynamicImage;
+
+/// The EXIF orientation (1-8) stored in encoded image `bytes`, if any.
+pub fn exif_orientation(bytes: &[u8]) -> Option<u32> {
+    let exif = exif::Reader::new().read_from_container(&mut Cursor::new(bytes)).ok()?;
+
+    exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?.value.get_uint(0)
+}
+
+/// Rotates and flips `img` so it displays upright for EXIF `orientation`,
+/// leaving it as is for 1 and unknown values.
+pub fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
@@ -0,0 +1,506 @@
+use std::{
+    fs,
+    io::{Cursor, Write},
+    path::Path,
+};
+use image::{
+    codecs::{bmp::BmpEncoder, jpeg::JpegEncoder, png::PngEncoder, tiff::TiffEncoder},
+    imageops, ColorType, DynamicImage, GenericImageView, ImageBuffer, ImageEncoder, ImageFormat, Pixel, Rgb, Rgba, RgbImage,
+};
+use png::text_metadata::{EncodableTextChunk, ITXtChunk, TEXtChunk};
+use crate::{
+    color,
+    depth::{is_sixteen_bit, Channel},
+    icc,
+    on_pool,
+    render::Renderer,
+    MosaicConfig, MosaicError,
+};
+
+/// Background that formats without an alpha channel are flattened onto.
+pub const DEFAULT_BACKGROUND: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+/// Quality of lossy WebP output when none is given, the libwebp default.
+#[cfg(feature = "webp")]
+const DEFAULT_WEBP_QUALITY: f32 = 75.0;
+
+/// How [`encode_mosaic`] and [`save_mosaic`] encode a mosaic, see
+/// [`encode_mosaic`] for where each option applies.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// Color formats without an alpha channel are flattened onto. Defaults
+    /// to [`DEFAULT_BACKGROUND`].
+    pub background: Rgba<u8>,
+    /// Lossy quality from 1 to 100, None for the encoder default, which is
+    /// also the default.
+    pub quality: Option<u8>,
+    /// Whether WebP is lossless. Defaults to `false`.
+    pub lossless: bool,
+    /// Whether PNG is written gray, colors reduced to their luminance.
+    /// Defaults to `false`.
+    pub gray: bool,
+    /// Keyword and text pairs written into PNG as text chunks, such as the
+    /// settings the mosaic was made with. Defaults to none.
+    pub text: Vec<(String, String)>,
+    /// ICC color profile embedded into PNG and JPEG, such as the one of the
+    /// source read with [`icc_profile`](crate::icc_profile). Defaults to
+    /// none.
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> EncodeOptions {
+        EncodeOptions {
+            background: DEFAULT_BACKGROUND,
+            quality: None,
+            lossless: false,
+            gray: false,
+            text: Vec::new(),
+            icc_profile: None,
+        }
+    }
+}
+
+/// Picks the encoding for `path` from its extension.
+///
+/// `.png`, `.jpg`/`.jpeg`, `.bmp`, `.tif`/`.tiff` and, with the `webp`
+/// feature, `.webp` are supported; anything else, including no extension at
+/// all, is a [`MosaicError::UnsupportedOutputFormat`].
+pub fn output_format(path: &Path) -> Result<ImageFormat, MosaicError> {
+    let unsupported = || MosaicError::UnsupportedOutputFormat(path.display().to_string());
+
+    match ImageFormat::from_path(path).map_err(|_| unsupported())? {
+        format @ (ImageFormat::Png | ImageFormat::Jpeg | ImageFormat::Bmp | ImageFormat::Tiff) => Ok(format),
+        ImageFormat::WebP if cfg!(feature = "webp") => Ok(ImageFormat::WebP),
+        ImageFormat::WebP => Err(MosaicError::WebpSupportDisabled(path.display().to_string())),
+        _ => Err(unsupported()),
+    }
+}
+
+/// The file name of the mosaic of `input` when none is given,
+/// `<input stem>_mosaic_t<tile_size>.<extension>`, such as
+/// `photo_mosaic_t20.png` for `photos/photo.jpg` in PNG. URLs are named
+/// without their query and stdin, `-`, as `stdin`.
+pub fn default_output_name(input: &str, tile_size: u32, format: ImageFormat) -> String {
+    // the query and fragment of a URL are no part of its file name
+    let input = if input.contains("://") { input.split(['?', '#']).next().unwrap_or_default() } else { input };
+    let stem = match input {
+        "-" => "stdin".into(),
+        _ => Path::new(input).file_stem().map_or("mosaic".into(), |stem| stem.to_string_lossy()),
+    };
+    let extension = match format {
+        ImageFormat::Jpeg => "jpg",
+        ImageFormat::Bmp => "bmp",
+        ImageFormat::Tiff => "tif",
+        ImageFormat::WebP => "webp",
+        ImageFormat::Gif => "gif",
+        _ => "png",
+    };
+
+    format!("{}_mosaic_t{}.{}", stem, tile_size, extension)
+}
+
+/// Parses an output format name: `png`, `jpeg`/`jpg`, `bmp`, `tiff`/`tif` or,
+/// with the `webp` feature, `webp`.
+pub fn parse_format(value: &str) -> Result<ImageFormat, MosaicError> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "png" => Ok(ImageFormat::Png),
+        "jpeg" | "jpg" => Ok(ImageFormat::Jpeg),
+        "bmp" => Ok(ImageFormat::Bmp),
+        "tiff" | "tif" => Ok(ImageFormat::Tiff),
+        "webp" if cfg!(feature = "webp") => Ok(ImageFormat::WebP),
+        "webp" => Err(MosaicError::WebpSupportDisabled("WebP".to_string())),
+        _ => Err(MosaicError::InvalidValue {
+            kind: "output format",
+            value: value.to_string(),
+            expected: "png, jpeg, bmp, tiff or webp",
+        }),
+    }
+}
+
+/// Encodes `img` as `format`.
+///
+/// JPEG has no alpha channel and few programs read the alpha channel of a BMP,
+/// so for both the mosaic is flattened onto the background first; PNG and
+/// TIFF keep the alpha channel as is, and the 16 bits of a 16 bit mosaic,
+/// which the other formats round to 8. The quality only applies to JPEG and
+/// lossy WebP.
+///
+/// With gray set PNG is written as Luma, or as LumaA if any pixel is not
+/// opaque, at the bit depth of the mosaic. Use [`is_gray`] to check that no
+/// color is lost.
+///
+/// The text is only written to PNG, as tEXt chunks, or iTXt for text that is
+/// not Latin-1. The other formats skip it.
+///
+/// The ICC profile is only embedded into PNG, as an iCCP chunk, and JPEG, as
+/// APP2 segments. The other formats skip it.
+///
+/// WebP, with the `webp` feature, is lossy unless lossless is set. Lossy
+/// WebP is flattened like JPEG, lossless WebP keeps the alpha channel.
+///
+/// The bytes are returned, nothing is written, so every format can be
+/// checked in memory. A mosaic too large for the format is a
+/// [`MosaicError::TooLargeForFormat`].
+pub fn encode_mosaic(img: &DynamicImage, format: ImageFormat, options: &EncodeOptions) -> Result<Vec<u8>, MosaicError> {
+    let EncodeOptions { background, quality, gray, ref text, ref icc_profile, .. } = *options;
+    let (width, height) = img.dimensions();
+    let sixteen_bit = is_sixteen_bit(img.color());
+    check_format_limit(format, width, height, sixteen_bit)?;
+
+    let mut bytes = Cursor::new(Vec::new());
+    let (rgba, color) = if gray && format == ImageFormat::Png {
+        gray_of(img, sixteen_bit)
+    } else if sixteen_bit {
+        (DynamicImage::ImageRgba16(img.to_rgba16()), ColorType::Rgba16)
+    } else {
+        (DynamicImage::ImageRgba8(img.to_rgba8()), ColorType::Rgba8)
+    };
+
+    let encoded = match (format, icc_profile) {
+        (ImageFormat::Png, Some(profile)) => {
+            *bytes.get_mut() = icc::png_with_icc(rgba.as_bytes(), width, height, color, profile)?;
+            Ok(())
+        },
+        (ImageFormat::Png, None) => PngEncoder::new(&mut bytes).write_image(rgba.as_bytes(), width, height, color),
+        (ImageFormat::Jpeg, _) => {
+            let rgb = flatten(img, background);
+            let encoder = match quality {
+                Some(quality) => JpegEncoder::new_with_quality(&mut bytes, quality),
+                None => JpegEncoder::new(&mut bytes),
+            };
+            encoder.write_image(&rgb, width, height, ColorType::Rgb8)
+        },
+        (ImageFormat::Bmp, _) => BmpEncoder::new(&mut bytes).write_image(&flatten(img, background), width, height, ColorType::Rgb8),
+        (ImageFormat::Tiff, _) => TiffEncoder::new(&mut bytes).write_image(rgba.as_bytes(), width, height, color),
+        #[cfg(feature = "webp")]
+        (ImageFormat::WebP, _) => return encode_webp(img, options),
+        _ => return Err(MosaicError::UnsupportedOutputFormat(format!("{:?}", format))),
+    };
+    encoded.map_err(MosaicError::Encode)?;
+
+    match (format, icc_profile) {
+        (ImageFormat::Png, _) if !text.is_empty() => with_text(&bytes.into_inner(), text),
+        (ImageFormat::Jpeg, Some(profile)) => Ok(icc::with_jpeg_icc(&bytes.into_inner(), profile)),
+        _ => Ok(bytes.into_inner()),
+    }
+}
+
+/// The PNG `png` with a text chunk for every keyword and text of `text`
+/// right after its header.
+fn with_text(png: &[u8], text: &[(String, String)]) -> Result<Vec<u8>, MosaicError> {
+    // the signature and the IHDR chunk, which must come first
+    let (header, chunks) = png.split_at(8 + 25);
+    let mut bytes = header.to_vec();
+
+    for (keyword, text) in text {
+        if text.chars().all(|c| u32::from(c) <= 0xff) {
+            TEXtChunk::new(keyword, text).encode(&mut bytes)?;
+        } else {
+            ITXtChunk::new(keyword, text).encode(&mut bytes)?;
+        }
+    }
+    bytes.extend_from_slice(chunks);
+
+    Ok(bytes)
+}
+
+#[cfg(feature = "webp")]
+fn encode_webp(img: &DynamicImage, &EncodeOptions { background, quality, lossless, .. }: &EncodeOptions) -> Result<Vec<u8>, MosaicError> {
+    let (width, height) = img.dimensions();
+
+    let encoded = if lossless {
+        webp::Encoder::from_rgba(&img.to_rgba8(), width, height).encode_simple(true, 100.0)
+    } else {
+        let quality = quality.map_or(DEFAULT_WEBP_QUALITY, f32::from);
+        webp::Encoder::from_rgb(&flatten(img, background), width, height).encode_simple(false, quality)
+    };
+
+    match encoded {
+        Ok(bytes) => Ok(bytes.to_vec()),
+        Err(err) => Err(MosaicError::Encode(image::ImageError::Encoding(image::error::EncodingError::new(
+            image::error::ImageFormatHint::Exact(ImageFormat::WebP),
+            format!("{:?}", err),
+        )))),
+    }
+}
+
+/// `img` as Luma, or LumaA if any of its pixels is not opaque, with 16 bit
+/// channels if `sixteen_bit`.
+fn gray_of(img: &DynamicImage, sixteen_bit: bool) -> (DynamicImage, ColorType) {
+    fn buffer<P: Pixel + 'static>(img: &DynamicImage, channels: Vec<P::Subpixel>) -> ImageBuffer<P, Vec<P::Subpixel>> {
+        ImageBuffer::from_raw(img.width(), img.height(), channels).expect("one or two channels per pixel")
+    }
+
+    if sixteen_bit {
+        match gray_channels(&img.to_rgba16()) {
+            (channels, true) => (DynamicImage::ImageLuma16(buffer(img, channels)), ColorType::L16),
+            (channels, false) => (DynamicImage::ImageLumaA16(buffer(img, channels)), ColorType::La16),
+        }
+    } else {
+        match gray_channels(&img.to_rgba8()) {
+            (channels, true) => (DynamicImage::ImageLuma8(buffer(img, channels)), ColorType::L8),
+            (channels, false) => (DynamicImage::ImageLumaA8(buffer(img, channels)), ColorType::La8),
+        }
+    }
+}
+
+/// The luminance of every pixel of `rgba`, followed by its alpha unless all
+/// pixels are opaque, and whether they are.
+fn gray_channels<T: Channel>(rgba: &ImageBuffer<Rgba<T>, Vec<T>>) -> (Vec<T>, bool) {
+    let opaque = rgba.pixels().all(|pixel| pixel.0[3] == T::from_u8(255));
+    let channels = rgba
+        .pixels()
+        .flat_map(|&pixel| [color::luminance_of(pixel), pixel.0[3]].into_iter().take(if opaque { 1 } else { 2 }))
+        .collect();
+
+    (channels, opaque)
+}
+
+/// Whether every pixel of `img` is gray, its color channels all equal, so
+/// writing it gray loses nothing.
+pub fn is_gray(img: &DynamicImage) -> bool {
+    match img {
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_) | DynamicImage::ImageLuma16(_) | DynamicImage::ImageLumaA16(_) => true,
+        _ if is_sixteen_bit(img.color()) => img.to_rgba16().pixels().all(|&Rgba([r, g, b, _])| r == g && g == b),
+        _ => img.pixels().all(|(_, _, Rgba([r, g, b, _]))| r == g && g == b),
+    }
+}
+
+/// How far two images of the same size are apart, such as a mosaic and the
+/// one it is expected to reproduce.
+///
+/// Every pixel differs by its largest channel difference, alpha included,
+/// on the 8 bit scale; images with 16 bit channels are compared at full
+/// precision.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Difference {
+    /// Mean difference of the pixels.
+    pub mean: f32,
+    /// Largest difference of any pixel.
+    pub max: f32,
+}
+
+/// The [`Difference`] of `a` and `b`, None if their dimensions differ.
+pub fn compare_images(a: &DynamicImage, b: &DynamicImage) -> Option<Difference> {
+    if a.dimensions() != b.dimensions() {
+        return None;
+    }
+
+    if is_sixteen_bit(a.color()) || is_sixteen_bit(b.color()) {
+        Some(difference::<u16>(a, b))
+    } else {
+        Some(difference::<u8>(a, b))
+    }
+}
+
+fn difference<T: Channel>(a: &DynamicImage, b: &DynamicImage) -> Difference {
+    let (a, b) = (T::rgba(a), T::rgba(b));
+    let (sum, max) = a.pixels().zip(b.pixels()).fold((0.0f64, 0.0f32), |(sum, max), (a, b)| {
+        let difference = a.0.iter().zip(b.0).map(|(&a, b)| (a.to_f32() - b.to_f32()).abs()).fold(0.0, f32::max);
+        (sum + f64::from(difference), max.max(difference))
+    });
+
+    let pixels = f64::from(a.width()) * f64::from(a.height());
+    let scale = 255.0 / T::MAX;
+
+    Difference {
+        mean: if pixels == 0.0 { 0.0 } else { (sum / pixels) as f32 * scale },
+        max: max * scale,
+    }
+}
+
+/// Fails with [`MosaicError::TooLargeForFormat`] if `format` cannot hold a
+/// `width` × `height` image, with 16 bit channels if `sixteen_bit`.
+fn check_format_limit(format: ImageFormat, width: u32, height: u32, sixteen_bit: bool) -> Result<(), MosaicError> {
+    let pixels = u64::from(width) * u64::from(height);
+    let pixel_len = if sixteen_bit { 8 } else { 4 };
+
+    let (fits, limit) = match format {
+        ImageFormat::Png => (width <= i32::MAX as u32 && height <= i32::MAX as u32, "at most 2147483647px per side"),
+        ImageFormat::Jpeg => (width <= u32::from(u16::MAX) && height <= u32::from(u16::MAX), "at most 65535px per side"),
+        // the file size is a 32 bit field, rows of 24 bit pixels are padded to 4 bytes
+        ImageFormat::Bmp => ((u64::from(width) * 3).div_ceil(4) * 4 * u64::from(height) + 54 <= u64::from(u32::MAX), "at most 4 GiB"),
+        // classic TIFF addresses its data with 32 bit offsets
+        ImageFormat::Tiff => (pixels * pixel_len <= u64::from(u32::MAX), "at most 4 GiB"),
+        ImageFormat::WebP => (width <= 16383 && height <= 16383, "at most 16383px per side"),
+        _ => (true, ""),
+    };
+
+    if fits {
+        Ok(())
+    } else {
+        Err(MosaicError::TooLargeForFormat {
+            format: format!("{:?}", format).to_uppercase(),
+            width,
+            height,
+            limit,
+        })
+    }
+}
+
+/// Encodes `img` in the format matching the extension of `path` and writes it
+/// there. A directory `path` is in that does not exist is a
+/// [`MosaicError::OutputDirMissing`], found before encoding.
+pub fn save_mosaic(img: &DynamicImage, path: &Path, options: &EncodeOptions) -> Result<(), MosaicError> {
+    let format = output_format(path)?;
+
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty() && !dir.is_dir()) {
+        return Err(MosaicError::OutputDirMissing(dir.display().to_string()));
+    }
+
+    let bytes = encode_mosaic(img, format, options)?;
+
+    fs::write(path, bytes).map_err(|source| MosaicError::Save {
+        path: path.display().to_string(),
+        source,
+    })
+}
+
+/// Composites `img` over a solid `background`, dropping the alpha channel.
+pub fn flatten(img: &DynamicImage, background: Rgba<u8>) -> RgbImage {
+    RgbImage::from_fn(img.width(), img.height(), |x, y| {
+        let mut pixel = background;
+        pixel.blend(&img.get_pixel(x, y));
+
+        pixel.to_rgb()
+    })
+}
+
+/// Like [`flatten`], keeping the 16 bits of a 16 bit `img`.
+pub fn flatten16(img: &DynamicImage, background: Rgba<u8>) -> ImageBuffer<Rgb<u16>, Vec<u16>> {
+    let rgba = img.to_rgba16();
+
+    ImageBuffer::from_fn(img.width(), img.height(), |x, y| {
+        let mut pixel = u16::widen(background);
+        pixel.blend(rgba.get_pixel(x, y));
+
+        pixel.to_rgb()
+    })
+}
+
+/// Sharpens `img` with an unsharp mask: every color channel is pushed away
+/// from a Gaussian blur of it with a standard deviation of `sigma` pixels, by
+/// as much as it differs from the blur. Alpha is left alone, so a mosaic with
+/// transparent edges is best flattened first.
+///
+/// Flat areas stay as they are, edges gain contrast.
+pub fn sharpen(img: &DynamicImage, sigma: f32) -> DynamicImage {
+    match img {
+        DynamicImage::ImageRgb8(img) => DynamicImage::ImageRgb8(unsharp_mask(img, sigma)),
+        DynamicImage::ImageRgb16(img) => DynamicImage::ImageRgb16(unsharp_mask(img, sigma)),
+        DynamicImage::ImageRgba16(img) => DynamicImage::ImageRgba16(unsharp_mask(img, sigma)),
+        img => DynamicImage::ImageRgba8(unsharp_mask(&img.to_rgba8(), sigma)),
+    }
+}
+
+// imageops::unsharpen of image 0.23 adds the absolute difference to the blur,
+// brightening both sides of an edge instead of pulling them apart
+fn unsharp_mask<P>(img: &ImageBuffer<P, Vec<P::Subpixel>>, sigma: f32) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel + 'static,
+    P::Subpixel: Channel,
+{
+    let blurred = imageops::blur(img, sigma);
+    let colors = if P::COLOR_TYPE.has_alpha() { P::CHANNEL_COUNT - 1 } else { P::CHANNEL_COUNT };
+
+    let mut sharpened = img.clone();
+    for (pixel, blurred) in sharpened.pixels_mut().zip(blurred.pixels()) {
+        for (channel, blurred) in pixel.channels_mut().iter_mut().zip(blurred.channels()).take(usize::from(colors)) {
+            *channel = P::Subpixel::from_f32(2.0 * channel.to_f32() - blurred.to_f32());
+        }
+    }
+
+    sharpened
+}
+
+/// Turns the hue of every pixel of `img` by `hue_shift` degrees and scales
+/// its saturation by `saturation`, in HSV. A `saturation` of `0.0` leaves
+/// the mosaic gray, above `1.0` the colors grow more vivid until they
+/// saturate. Alpha is left alone.
+pub fn shift_colors(img: &DynamicImage, hue_shift: f32, saturation: f32) -> DynamicImage {
+    match img {
+        DynamicImage::ImageRgb8(img) => DynamicImage::ImageRgb8(shift_hsv(img, hue_shift, saturation)),
+        DynamicImage::ImageRgb16(img) => DynamicImage::ImageRgb16(shift_hsv(img, hue_shift, saturation)),
+        DynamicImage::ImageRgba16(img) => DynamicImage::ImageRgba16(shift_hsv(img, hue_shift, saturation)),
+        img => DynamicImage::ImageRgba8(shift_hsv(&img.to_rgba8(), hue_shift, saturation)),
+    }
+}
+
+fn shift_hsv<P>(img: &ImageBuffer<P, Vec<P::Subpixel>>, hue_shift: f32, saturation: f32) -> ImageBuffer<P, Vec<P::Subpixel>>
+where
+    P: Pixel + 'static,
+    P::Subpixel: Channel,
+{
+    let mut shifted = img.clone();
+    for pixel in shifted.pixels_mut() {
+        let channels = pixel.channels_mut();
+        let [hue, s, value] = color::to_hsv([0, 1, 2].map(|channel| channels[channel].to_unit()));
+        let rgb = color::from_hsv([hue + hue_shift, (s * saturation).min(1.0), value]);
+
+        for (channel, value) in channels.iter_mut().zip(rgb) {
+            *channel = P::Subpixel::from_unit(value);
+        }
+    }
+
+    shifted
+}
+
+/// Builds the mosaic of `source` and writes it to `writer` as a PNG, a few
+/// rows of tiles at a time, so the whole mosaic is never held in memory.
+///
+/// Only PNG can be written this way, always keeping the alpha channel, with
+/// the bit depth [`build_mosaic`](crate::build_mosaic) would render in. The
+/// output is written as it is rendered, so an error leaves it incomplete.
+///
+/// An output size cannot be streamed, resizing needs the whole mosaic, and
+/// neither can adaptive tile sizes; both are a
+/// [`MosaicError::UnsupportedWhenStreaming`].
+pub fn stream_mosaic_png<W: Write + Send>(source: &DynamicImage, config: &MosaicConfig, writer: W) -> Result<(), MosaicError> {
+    if config.output_size.is_some() {
+        return Err(MosaicError::UnsupportedWhenStreaming("an output size"));
+    }
+    if config.adaptive.is_some() {
+        return Err(MosaicError::UnsupportedWhenStreaming("adaptive tile sizes"));
+    }
+
+    on_pool(config, || match config.bit_depth.bits(source) {
+        16 => stream_as::<u16, W>(source, config, writer),
+        _ => stream_as::<u8, W>(source, config, writer),
+    })
+}
+
+fn stream_as<T: Channel, W: Write>(source: &DynamicImage, config: &MosaicConfig, writer: W) -> Result<(), MosaicError> {
+    let renderer = Renderer::<T>::new(source, config)?;
+    let (width, height) = renderer.dimensions();
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(match T::BITS {
+        16 => png::BitDepth::Sixteen,
+        _ => png::BitDepth::Eight,
+    });
+
+    let mut png_writer = encoder.write_header()?;
+    let mut stream = png_writer.stream_writer()?;
+
+    let mut top = vec![T::zero(); renderer.top_len()];
+    renderer.fill_background(&mut top);
+    stream.write_all(&T::png_bytes(&top))?;
+
+    // render as many bands at once as there are threads to keep them all busy
+    let batch = rayon::current_num_threads() as u32;
+    let mut buf = vec![T::zero(); renderer.band_len() * batch as usize];
+
+    for first in (0..renderer.bands()).step_by(batch as usize) {
+        let bands = batch.min(renderer.bands() - first);
+        let buf = &mut buf[..renderer.bands_len(first, bands)];
+
+        renderer.render_bands(buf, first);
+        stream.write_all(&T::png_bytes(buf))?;
+    }
+
+    stream.finish()?;
+
+    Ok(())
+}
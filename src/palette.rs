@@ -0,0 +1,162 @@
+use std::str::FromStr;
+use image::{ImageBuffer, Rgba};
+use crate::{depth::Channel, parse_color, MosaicError};
+
+/// A fixed set of opaque colors the cell colors are snapped to, for a
+/// poster-like mosaic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+impl Palette {
+    /// A palette of `colors`, their alpha ignored. Fewer than two colors are a
+    /// [`MosaicError::PaletteTooSmall`].
+    pub fn new(colors: impl IntoIterator<Item = Rgba<u8>>) -> Result<Palette, MosaicError> {
+        let colors: Vec<_> = colors.into_iter().map(|color| [color.0[0], color.0[1], color.0[2]]).collect();
+
+        if colors.len() < 2 {
+            return Err(MosaicError::PaletteTooSmall(colors.len()));
+        }
+
+        Ok(Palette { colors })
+    }
+
+    /// A palette of at most `size` colors that stand for `colors` as a whole,
+    /// picked by median cut: the colors are split in two at the median of
+    /// their widest channel, again and again, and every part gets its mean.
+    /// A `size` below two is a [`MosaicError::PaletteTooSmall`].
+    ///
+    /// The palette has fewer colors when `colors` has fewer distinct ones.
+    pub fn median_cut(colors: impl IntoIterator<Item = Rgba<u8>>, size: usize) -> Result<Palette, MosaicError> {
+        if size < 2 {
+            return Err(MosaicError::PaletteTooSmall(size));
+        }
+
+        let mut boxes = vec![colors.into_iter().map(|color| [color.0[0], color.0[1], color.0[2]]).collect::<Vec<_>>()];
+
+        while boxes.len() < size {
+            // the box spanning the widest range of any channel is split next
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .map(|(index, colors)| (index, widest_channel(colors)))
+                .max_by_key(|&(_, (_, range))| range);
+
+            let (index, channel) = match widest {
+                Some((index, (channel, range))) if range > 0 => (index, channel),
+                _ => break,
+            };
+
+            let mut colors = boxes.swap_remove(index);
+            colors.sort_unstable_by_key(|color| color[channel]);
+            let upper = colors.split_off(colors.len() / 2);
+            boxes.push(colors);
+            boxes.push(upper);
+        }
+
+        let colors: Vec<_> = boxes.iter().filter(|colors| !colors.is_empty()).map(|colors| mean(colors)).collect();
+
+        if colors.is_empty() {
+            return Err(MosaicError::PaletteTooSmall(0));
+        }
+
+        Ok(Palette { colors })
+    }
+
+    /// The colors of the palette, opaque.
+    pub fn colors(&self) -> Vec<Rgba<u8>> {
+        self.colors.iter().map(|&[r, g, b]| Rgba([r, g, b, 255])).collect()
+    }
+
+    /// The palette color nearest to `color` (Euclidean in RGB, on the 8 bit
+    /// scale).
+    fn nearest(&self, color: [f32; 3]) -> [u8; 3] {
+        let distance = |entry: &[u8; 3]| -> f32 {
+            entry.iter().zip(color).map(|(&a, b)| (f32::from(a) - b) * (f32::from(a) - b)).sum()
+        };
+
+        *self
+            .colors
+            .iter()
+            .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+            .expect("a palette is never empty")
+    }
+
+    /// Snaps the color of every pixel of `grid` to the palette, alpha kept.
+    ///
+    /// With `dither` the error of every pixel is diffused over the pixels to
+    /// its right and below (Floyd–Steinberg), so areas of the grid average
+    /// out to their original color instead of banding.
+    pub(crate) fn quantize<T: Channel>(&self, grid: &mut ImageBuffer<Rgba<T>, Vec<T>>, dither: bool) {
+        let width = grid.width() as usize;
+
+        // errors of the current and the next row, offset by one so the
+        // neighbors left of the first and right of the last pixel exist
+        let mut errors = vec![[0.0f32; 3]; width + 2];
+        let mut next = vec![[0.0f32; 3]; width + 2];
+
+        for y in 0..grid.height() {
+            for x in 0..grid.width() {
+                let pixel = grid.get_pixel_mut(x, y);
+                let i = x as usize + 1;
+                let color = [0, 1, 2].map(|channel| pixel.0[channel].to_unit() * 255.0 + errors[i][channel]);
+                let snapped = self.nearest(color);
+
+                for (channel, &snapped) in pixel.0.iter_mut().zip(&snapped) {
+                    *channel = T::from_u8(snapped);
+                }
+
+                if dither {
+                    for channel in 0..3 {
+                        let error = color[channel] - f32::from(snapped[channel]);
+
+                        errors[i + 1][channel] += error * 7.0 / 16.0;
+                        next[i - 1][channel] += error * 3.0 / 16.0;
+                        next[i][channel] += error * 5.0 / 16.0;
+                        next[i + 1][channel] += error / 16.0;
+                    }
+                }
+            }
+
+            errors = std::mem::replace(&mut next, vec![[0.0; 3]; width + 2]);
+        }
+    }
+}
+
+impl FromStr for Palette {
+    type Err = MosaicError;
+
+    /// Parses `#RRGGBB` colors separated by commas or whitespace, such as
+    /// one per line.
+    fn from_str(value: &str) -> Result<Palette, MosaicError> {
+        let colors = value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|color| !color.is_empty())
+            .map(parse_color)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Palette::new(colors)
+    }
+}
+
+/// The channel whose values spread the most over `colors`, with that spread.
+fn widest_channel(colors: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|channel| {
+            let values = colors.iter().map(|color| color[channel]);
+            let range = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+            (channel, range)
+        })
+        .max_by_key(|&(_, range)| range)
+        .expect("there are three channels")
+}
+
+fn mean(colors: &[[u8; 3]]) -> [u8; 3] {
+    let count = colors.len() as u64;
+
+    [0, 1, 2].map(|channel| {
+        let sum: u64 = colors.iter().map(|color| u64::from(color[channel])).sum();
+        ((sum + count / 2) / count) as u8
+    })
+}
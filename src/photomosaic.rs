@@ -0,0 +1,391 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashSet},
+    fs,
+    hash::{Hash, Hasher},
+    io::{Read, Seek},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::UNIX_EPOCH,
+};
+use image::{imageops::FilterType, DynamicImage, ImageFormat, Rgba, RgbaImage};
+use zip::ZipArchive;
+use crate::{check_tile_size, color, decode_image, open_image, MosaicError};
+
+/// A set of tile images for true photomosaics, resized once and indexed by
+/// average color.
+///
+/// Instead of tinting one repeated tile, every cell of the mosaic gets the
+/// library tile whose average color is nearest to the source pixel.
+#[derive(Clone, Debug)]
+pub struct TileLibrary {
+    tile_size: u32,
+    tiles: Vec<LibraryTile>,
+}
+
+/// The color space photomosaic tiles are matched to their cell in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MatchSpace {
+    /// Euclidean distance in RGB, in linear light with a linear blend. The
+    /// fastest, but a dark tile of the wrong hue may beat a slightly brighter
+    /// one of the right hue.
+    #[default]
+    Rgb,
+    /// Euclidean distance in CIELAB (ΔE, CIE76), so the tiles that look
+    /// closest win. The tile averages are converted once, but the color of
+    /// every cell is converted too, which takes a few cube roots per cell.
+    Lab,
+}
+
+impl FromStr for MatchSpace {
+    type Err = MosaicError;
+
+    fn from_str(value: &str) -> Result<MatchSpace, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "rgb" => Ok(MatchSpace::Rgb),
+            "lab" => Ok(MatchSpace::Lab),
+            _ => Err(MosaicError::InvalidValue {
+                kind: "match space",
+                value: value.to_string(),
+                expected: "rgb or lab",
+            }),
+        }
+    }
+}
+
+// the pixel limit of the mosaic is checked when its config is built
+fn check_library_tile_size(tile_size: u32) -> Result<(), MosaicError> {
+    check_tile_size(tile_size, u64::MAX)
+}
+
+#[derive(Clone, Debug)]
+struct LibraryTile {
+    image: RgbaImage,
+    average: [u8; 3],
+    lab: [f32; 3],
+}
+
+impl LibraryTile {
+    /// The average color as a point of the space tiles are matched in.
+    fn point(&self, gamma: Option<f32>, space: MatchSpace) -> [f32; 3] {
+        match (space, gamma) {
+            (MatchSpace::Lab, _) => self.lab,
+            (MatchSpace::Rgb, Some(gamma)) => self.average.map(|channel| color::srgb_to_linear(channel, gamma)),
+            (MatchSpace::Rgb, None) => self.average.map(f32::from),
+        }
+    }
+}
+
+/// The tiles of a [`TileLibrary`] in a k-d tree of their average colors, to
+/// find the nearest one to every cell without comparing them all.
+///
+/// It picks the very tile [`TileLibrary::nearest_index`] does, ties
+/// included, but takes a logarithmic number of comparisons instead of a
+/// linear one, which adds up with thousands of tiles over thousands of
+/// cells.
+#[derive(Clone, Debug)]
+pub struct TileMatcher {
+    /// Points and tile indices, every range split at its median along the
+    /// axis of its depth, its halves before and after it.
+    points: Vec<([f32; 3], usize)>,
+    gamma: Option<f32>,
+    space: MatchSpace,
+}
+
+impl TileMatcher {
+    /// Index of the tile nearest to `color`.
+    pub fn nearest(&self, color: Rgba<u8>) -> usize {
+        self.nearest_allowed(color, |_| true).expect("tile library is never empty")
+    }
+
+    /// Index of the tile nearest to `color` among the tiles whose index is
+    /// `allowed`, if any is.
+    pub(crate) fn nearest_allowed(&self, color: Rgba<u8>, allowed: impl Fn(usize) -> bool) -> Option<usize> {
+        let mut best = None;
+        search_tree(&self.points, 0, match_point(color, self.gamma, self.space), &allowed, &mut best);
+
+        best.map(|(_, index)| index)
+    }
+}
+
+/// Orders `points` into a k-d tree, splitting along `axis` first.
+fn build_tree(points: &mut [([f32; 3], usize)], axis: usize) {
+    if points.len() <= 1 {
+        return;
+    }
+
+    let median = points.len() / 2;
+    points.select_nth_unstable_by(median, |(a, _), (b, _)| a[axis].total_cmp(&b[axis]));
+
+    let (before, after) = points.split_at_mut(median);
+    build_tree(before, (axis + 1) % 3);
+    build_tree(&mut after[1..], (axis + 1) % 3);
+}
+
+/// Updates `best` with the nearest allowed point of the tree `points` to
+/// `target`, if it is nearer or as near with a lower index.
+fn search_tree(points: &[([f32; 3], usize)], axis: usize, target: [f32; 3], allowed: &impl Fn(usize) -> bool, best: &mut Option<(f32, usize)>) {
+    if points.is_empty() {
+        return;
+    }
+
+    let median = points.len() / 2;
+    let (point, index) = points[median];
+
+    if allowed(index) {
+        let distance = distance_squared(point, target);
+        if best.is_none_or(|(nearest, first)| distance < nearest || (distance == nearest && index < first)) {
+            *best = Some((distance, index));
+        }
+    }
+
+    // the side of the split the target is on first, the other only if the
+    // split is no farther than the nearest point yet, an equal one may come first
+    let offset = target[axis] - point[axis];
+    let (near, far) = match offset < 0.0 {
+        true => (&points[..median], &points[median + 1..]),
+        false => (&points[median + 1..], &points[..median]),
+    };
+
+    search_tree(near, (axis + 1) % 3, target, allowed, best);
+    if best.is_none_or(|(nearest, _)| offset * offset <= nearest) {
+        search_tree(far, (axis + 1) % 3, target, allowed, best);
+    }
+}
+
+/// `color` as a point of the space tiles are matched in.
+fn match_point(color: Rgba<u8>, gamma: Option<f32>, space: MatchSpace) -> [f32; 3] {
+    let [r, g, b, _] = color.0;
+
+    match (space, gamma) {
+        (MatchSpace::Lab, _) => color::srgb_to_lab(color),
+        (MatchSpace::Rgb, Some(gamma)) => [r, g, b].map(|channel| color::srgb_to_linear(channel, gamma)),
+        (MatchSpace::Rgb, None) => [r, g, b].map(f32::from),
+    }
+}
+
+impl TileLibrary {
+    /// Resizes (center-cropping to a square) every image to `tile_size` ×
+    /// `tile_size` with `filter` and computes its average color.
+    /// [`FilterType::Triangle`] is a good tradeoff between speed and sharpness.
+    ///
+    /// Tile sizes beyond [`MAX_TILE_BYTES`](crate::MAX_TILE_BYTES) are a
+    /// [`MosaicError::TileTooLarge`], before anything is resized.
+    pub fn new(images: impl IntoIterator<Item = DynamicImage>, tile_size: u32, filter: FilterType) -> Result<TileLibrary, MosaicError> {
+        check_library_tile_size(tile_size)?;
+        TileLibrary::from_tiles(images.into_iter().map(|img| resize_tile(&img, tile_size, filter)).collect(), tile_size)
+    }
+
+    /// Loads every image file directly inside `dir`, skipping files whose
+    /// extension is not an image format.
+    pub fn from_dir(dir: &Path, tile_size: u32, filter: FilterType) -> Result<TileLibrary, MosaicError> {
+        check_library_tile_size(tile_size)?;
+        let tiles = image_paths(dir)?
+            .iter()
+            .map(|path| Ok(resize_tile(&open_image(path)?, tile_size, filter)))
+            .collect::<Result<Vec<_>, MosaicError>>()?;
+
+        TileLibrary::from_tiles(tiles, tile_size)
+    }
+
+    /// Like [`from_dir`](TileLibrary::from_dir), but keeps the resized tiles
+    /// in `cache_dir` so the next run only decodes and resizes the images that
+    /// are new or were modified since.
+    ///
+    /// Entries are keyed by image path, modification time, tile size and
+    /// filter, and replaced when the image changes. The average color is recomputed from
+    /// the cached tile, which costs next to nothing. Failing to write the cache
+    /// is not an error, the tiles are just computed again next time.
+    pub fn from_dir_cached(dir: &Path, tile_size: u32, filter: FilterType, cache_dir: &Path) -> Result<TileLibrary, MosaicError> {
+        check_library_tile_size(tile_size)?;
+        fs::create_dir_all(cache_dir)?;
+
+        let cached: HashSet<String> = fs::read_dir(cache_dir)?
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect();
+
+        let tiles = image_paths(dir)?
+            .iter()
+            .map(|path| cached_tile(path, tile_size, filter, cache_dir, &cached))
+            .collect::<Result<Vec<_>, MosaicError>>()?;
+
+        TileLibrary::from_tiles(tiles, tile_size)
+    }
+
+    /// Loads every image inside the ZIP `archive`, in its subdirectories too,
+    /// decoding each in memory instead of unpacking it to disk. Entries whose
+    /// extension is not an image format are skipped, and counted in the log.
+    pub fn from_zip(archive: impl Read + Seek, tile_size: u32, filter: FilterType) -> Result<TileLibrary, MosaicError> {
+        check_library_tile_size(tile_size)?;
+        let mut archive = ZipArchive::new(archive)?;
+
+        // archive order is up to the tool that packed it, keep tile matching reproducible
+        let mut names: Vec<_> = archive.file_names().filter(|name| !name.ends_with('/')).map(String::from).collect();
+        names.sort();
+
+        // macOS packs a resource fork named ._<file> next to every file, images included
+        let (names, skipped): (Vec<_>, Vec<_>) = names.into_iter().partition(|name| {
+            let is_fork = name.starts_with("__MACOSX/") || Path::new(name).file_name().is_some_and(|file| file.to_string_lossy().starts_with("._"));
+            !is_fork && ImageFormat::from_path(name).is_ok()
+        });
+
+        if !skipped.is_empty() {
+            log::info!("Skipped {} entries of the ZIP archive that are not images", skipped.len());
+        }
+
+        let tiles = names
+            .iter()
+            .map(|name| {
+                let mut bytes = Vec::new();
+                archive.by_name(name)?.read_to_end(&mut bytes)?;
+                Ok(resize_tile(&decode_image(&bytes, name)?, tile_size, filter))
+            })
+            .collect::<Result<Vec<_>, MosaicError>>()?;
+
+        TileLibrary::from_tiles(tiles, tile_size)
+    }
+
+    fn from_tiles(tiles: Vec<RgbaImage>, tile_size: u32) -> Result<TileLibrary, MosaicError> {
+        if tiles.is_empty() {
+            return Err(MosaicError::EmptyTileLibrary);
+        }
+
+        let tiles = tiles
+            .into_iter()
+            .map(|image| {
+                let average = average_color(&image);
+                let [r, g, b] = average;
+
+                LibraryTile {
+                    average,
+                    lab: color::srgb_to_lab(Rgba([r, g, b, 255])),
+                    image,
+                }
+            })
+            .collect();
+
+        Ok(TileLibrary { tile_size, tiles })
+    }
+
+    /// Side length the tiles were resized to.
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    /// Number of tiles in the library.
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// Always false, an empty library cannot be built.
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// Index of the tile whose average color is nearest to `color` in
+    /// `space`, RGB being compared in linear light when a `gamma` is given.
+    /// Ties go to the first of the tiles, so the pick never depends on chance.
+    ///
+    /// Every tile is compared, which is fine for a few lookups. For a cell
+    /// after cell, build a [`TileMatcher`] once instead, it picks the same
+    /// tiles.
+    pub fn nearest_index(&self, color: Rgba<u8>, gamma: Option<f32>, space: MatchSpace) -> usize {
+        let target = match_point(color, gamma, space);
+
+        self.tiles
+            .iter()
+            .enumerate()
+            .map(|(index, tile)| (index, distance_squared(tile.point(gamma, space), target)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(index, _)| index)
+            .expect("tile library is never empty")
+    }
+
+    /// A [`TileMatcher`] over the tiles, comparing them in `space` and, for
+    /// RGB, linear light with a `gamma`.
+    pub fn matcher(&self, gamma: Option<f32>, space: MatchSpace) -> TileMatcher {
+        let mut points: Vec<_> = self.tiles.iter().enumerate().map(|(index, tile)| (tile.point(gamma, space), index)).collect();
+        build_tree(&mut points, 0);
+
+        TileMatcher { points, gamma, space }
+    }
+
+    /// The tile at `index`.
+    pub(crate) fn tile(&self, index: usize) -> &RgbaImage {
+        &self.tiles[index].image
+    }
+}
+
+fn resize_tile(img: &DynamicImage, tile_size: u32, filter: FilterType) -> RgbaImage {
+    img.resize_to_fill(tile_size, tile_size, filter).to_rgba8()
+}
+
+/// The image files directly inside `dir`, sorted.
+fn image_paths(dir: &Path) -> Result<Vec<PathBuf>, MosaicError> {
+    let mut paths: Vec<_> = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<_, _>>()?;
+
+    // read_dir order is platform dependent, keep tile matching reproducible
+    paths.sort();
+    paths.retain(|path| path.is_file() && ImageFormat::from_path(path).is_ok());
+
+    Ok(paths)
+}
+
+/// The tile for the image at `path`, from the cache if it holds an entry for
+/// the current version of the image, otherwise computed and cached.
+fn cached_tile(path: &Path, tile_size: u32, filter: FilterType, cache_dir: &Path, cached: &HashSet<String>) -> Result<RgbaImage, MosaicError> {
+    let modified = fs::metadata(path)?.modified()?;
+    let modified = modified.duration_since(UNIX_EPOCH).map_or(0, |modified| modified.as_nanos());
+
+    // DefaultHasher may change between Rust releases, which only costs a cache miss
+    let mut hasher = DefaultHasher::new();
+    fs::canonicalize(path)?.hash(&mut hasher);
+
+    let prefix = format!("{:016x}-{}-{}-", hasher.finish(), tile_size, filter_name(filter));
+    let name = format!("{}{}.png", prefix, modified);
+
+    if cached.contains(&name) {
+        if let Ok(tile) = image::open(cache_dir.join(&name)) {
+            return Ok(tile.to_rgba8());
+        }
+    }
+
+    let tile = resize_tile(&open_image(path)?, tile_size, filter);
+
+    // entries for older versions of the image are stale now
+    for stale in cached.iter().filter(|entry| entry.starts_with(&prefix) && **entry != name) {
+        let _ = fs::remove_file(cache_dir.join(stale));
+    }
+    let _ = tile.save(cache_dir.join(&name));
+
+    Ok(tile)
+}
+
+fn filter_name(filter: FilterType) -> &'static str {
+    match filter {
+        FilterType::Nearest => "nearest",
+        FilterType::Triangle => "triangle",
+        FilterType::CatmullRom => "catmullrom",
+        FilterType::Gaussian => "gaussian",
+        FilterType::Lanczos3 => "lanczos3",
+    }
+}
+
+fn average_color(img: &RgbaImage) -> [u8; 3] {
+    let mut sum = [0u64; 3];
+
+    for pixel in img.pixels() {
+        for (channel, sum) in sum.iter_mut().enumerate() {
+            *sum += u64::from(pixel.0[channel]);
+        }
+    }
+
+    let count = u64::from(img.width()) * u64::from(img.height());
+
+    [(sum[0] / count) as u8, (sum[1] / count) as u8, (sum[2] / count) as u8]
+}
+
+fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
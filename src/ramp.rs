@@ -0,0 +1,96 @@
+use std::str::FromStr;
+use image::Rgba;
+use crate::{depth::Channel, parse_color, MosaicError};
+
+/// A gradient the cell colors are mapped onto by their luminance, from its
+/// first color for black to its last for white, for a heatmap-like mosaic.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ColorRamp {
+    colors: Vec<[u8; 3]>,
+}
+
+impl ColorRamp {
+    /// A ramp through `colors`, evenly spaced, their alpha ignored. Fewer
+    /// than two colors are a [`MosaicError::InvalidValue`].
+    pub fn new(colors: impl IntoIterator<Item = Rgba<u8>>) -> Result<ColorRamp, MosaicError> {
+        let colors: Vec<_> = colors.into_iter().map(|color| [color.0[0], color.0[1], color.0[2]]).collect();
+
+        if colors.len() < 2 {
+            return Err(MosaicError::InvalidValue {
+                kind: "color ramp",
+                value: format!("{} colors", colors.len()),
+                expected: "at least 2 colors",
+            });
+        }
+
+        Ok(ColorRamp { colors })
+    }
+
+    /// The perceptually uniform ramp from dark purple over teal to yellow.
+    pub fn viridis() -> ColorRamp {
+        ColorRamp::from_hex(&[0x440154, 0x482878, 0x3e4a89, 0x31688e, 0x26828e, 0x1f9e89, 0x35b779, 0x6dcd59, 0xb4de2c, 0xfde725])
+    }
+
+    /// Dark brown over tan to cream, like an old photograph.
+    pub fn sepia() -> ColorRamp {
+        ColorRamp::from_hex(&[0x1e1209, 0x704214, 0xc09a6b, 0xfff8e7])
+    }
+
+    /// Black to white.
+    pub fn grayscale() -> ColorRamp {
+        ColorRamp::from_hex(&[0x000000, 0xffffff])
+    }
+
+    fn from_hex(colors: &[u32]) -> ColorRamp {
+        ColorRamp {
+            colors: colors.iter().map(|&color| [(color >> 16) as u8, (color >> 8) as u8, color as u8]).collect(),
+        }
+    }
+
+    /// The colors of the ramp, opaque.
+    pub fn colors(&self) -> Vec<Rgba<u8>> {
+        self.colors.iter().map(|&[r, g, b]| Rgba([r, g, b, 255])).collect()
+    }
+
+    /// The color of the ramp at `position`, from 0.0 for the first color to
+    /// 1.0 for the last, blended between the two nearest colors.
+    pub(crate) fn at<T: Channel>(&self, position: f32) -> [T; 3] {
+        let scaled = position.clamp(0.0, 1.0) * (self.colors.len() - 1) as f32;
+        let index = (scaled as usize).min(self.colors.len() - 2);
+        let (from, to, along) = (self.colors[index], self.colors[index + 1], scaled - index as f32);
+
+        [0, 1, 2].map(|channel| {
+            let (from, to) = (f32::from(from[channel]), f32::from(to[channel]));
+            T::from_unit((from + (to - from) * along) / 255.0)
+        })
+    }
+}
+
+impl FromStr for ColorRamp {
+    type Err = MosaicError;
+
+    /// Parses `viridis`, `sepia` or `grayscale`, or `#RRGGBB` colors
+    /// separated by commas or whitespace, such as one per line, darkest
+    /// first.
+    fn from_str(value: &str) -> Result<ColorRamp, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "viridis" => return Ok(ColorRamp::viridis()),
+            "sepia" => return Ok(ColorRamp::sepia()),
+            "grayscale" => return Ok(ColorRamp::grayscale()),
+            _ => {},
+        }
+
+        let colors = value
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|color| !color.is_empty())
+            .map(parse_color)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| MosaicError::InvalidValue {
+                kind: "color ramp",
+                value: value.to_string(),
+                expected: "viridis, sepia, grayscale or #RRGGBB colors",
+            })?;
+
+        ColorRamp::new(colors)
+    }
+}
@@ -0,0 +1,928 @@
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    sync::{atomic::{AtomicBool, Ordering}, Mutex},
+};
+use image::{
+    imageops::{self, FilterType},
+    DynamicImage, GenericImageView, ImageBuffer, Rgba, RgbaImage,
+};
+use rayon::prelude::*;
+use crate::{check_buffer, color, depth::Channel, rendered_dimensions, square_tile, MosaicConfig, MosaicError, Palette, Layout, RenderMode, SampleMode, TileLibrary, TileMatcher, TilePattern, TileShape};
+
+/// Everything the bands of one mosaic share, prepared once.
+///
+/// Every row of source pixels fills one band of the mosaic: its row of tiles
+/// and the gap below it. Tiles never overlap, so bands can be rendered in
+/// parallel and in any order.
+///
+/// Pixels have channels of type `T`, whatever the depth of the source.
+pub(crate) struct Renderer<'a, T: Channel> {
+    config: &'a MosaicConfig,
+    source: ImageBuffer<Rgba<T>, Vec<T>>,
+    img_cell: Option<ImageBuffer<Rgba<T>, Vec<T>>>,
+    /// Share of every cell pixel inside the tile shape.
+    mask: Option<Vec<f32>>,
+    shades: Option<Vec<f32>>,
+    assignment: Option<Vec<Option<usize>>>,
+    tile_size: u32,
+    /// Height of the cells, the tile size unless the tile aspect is preserved.
+    tile_height: u32,
+    width: u32,
+    height: u32,
+    /// Rows of tiles rendered so far and in total, for the progress callback.
+    rows_done: Mutex<u32>,
+    rows_total: u32,
+    /// Set to stop rendering, the rows of tiles not started yet are skipped.
+    cancel: Option<&'a AtomicBool>,
+}
+
+impl<'a, T: Channel> Renderer<'a, T> {
+    pub(crate) fn new(source: &DynamicImage, config: &'a MosaicConfig) -> Result<Renderer<'a, T>, MosaicError> {
+        // get width/height of new image
+        let (tile_size, tile_height, (width, height)) = rendered_dimensions(source.dimensions(), config)?;
+
+        if let Some(tile_library) = &config.tile_library {
+            if tile_library.tile_size() != tile_size {
+                return Err(MosaicError::TileLibrarySize {
+                    library: tile_library.tile_size(),
+                    mosaic: tile_size,
+                });
+            }
+        }
+
+        // only the cropped region is tiled, and makes the default tile
+        let source = oriented_source(source, config);
+        check_buffer(config.grid.map_or(source.dimensions(), |grid| grid.dimensions(source.dimensions())), "grid")?;
+
+        // create image tile, pixelation has none
+        let img_cell = (config.mode == RenderMode::Tiled).then(|| {
+            let (side, side_height) = (tile_size.div_ceil(config.tile_repeat), tile_height.div_ceil(config.tile_repeat));
+            let mut img_tile = match config.pattern {
+                TilePattern::Image => {
+                    let thumbnail = |tile_source: &DynamicImage| {
+                        T::rgba(&match config.filter {
+                            Some(filter) => tile_source.resize_to_fill(side, side_height, filter),
+                            None if side_height != side => tile_source.resize_to_fill(side, side_height, FilterType::Triangle),
+                            None => square_tile(tile_source, side),
+                        })
+                    };
+
+                    match config.tile_images.as_slice() {
+                        // only the source is adjusted, not a tile image of its own
+                        [] => {
+                            let mut img_tile = thumbnail(&source);
+                            img_tile.pixels_mut().for_each(|pixel| *pixel = config.adjust(*pixel));
+                            img_tile
+                        },
+                        [tile_image] => thumbnail(tile_image),
+                        tile_images => mean_tile(&tile_images.iter().map(thumbnail).collect::<Vec<_>>()),
+                    }
+                },
+                TilePattern::Solid => ImageBuffer::from_pixel(side, side, T::widen(config.pattern_color)),
+                TilePattern::Checker => ImageBuffer::from_fn(side, side, |x, y| {
+                    let square = x / config.checker_size + y / config.checker_size;
+                    T::widen(if square.is_multiple_of(2) { config.pattern_color } else { config.checker_color })
+                }),
+            };
+
+            if config.grayscale_tile {
+                img_tile.pixels_mut().for_each(|pixel| *pixel = color::grayscale_of(*pixel, config.gray_method));
+            }
+
+            if config.tile_opacity < 255 {
+                img_tile.pixels_mut().for_each(|pixel| pixel.0[3] = T::scale_alpha(config.tile_opacity, pixel.0[3]));
+            }
+
+            // the repeated tile is identical in every cell, so lay it out once
+            let mut img_cell = ImageBuffer::new(tile_size, tile_height);
+            for y in (0..tile_height).step_by(side_height as usize) {
+                for x in (0..tile_size).step_by(side as usize) {
+                    imageops::overlay(&mut img_cell, &img_tile, x, y);
+                }
+            }
+            img_cell
+        });
+
+        // the tile keeps the full resolution source
+        let mut source = sampled_grid(&source, config);
+        if let Some(clip) = config.auto_levels {
+            stretch_levels(&mut source, clip);
+        }
+
+        // edges of the grid itself, before dithering adds edges of its own
+        let shades = (config.edges > 0.0).then(|| edge_shades(&source, config.edges));
+
+        // snap the cells to the palette, recolored first so the tints stay in it
+        let palette = match (&config.palette, config.palette_size) {
+            (Some(palette), _) => Some(Cow::Borrowed(palette)),
+            (None, Some(size)) => Some(Cow::Owned(Palette::median_cut(source.pixels().map(|pixel| T::narrow(config.recolor(*pixel))), size)?)),
+            (None, None) => None,
+        };
+        if let Some(palette) = palette {
+            source.pixels_mut().for_each(|pixel| *pixel = config.recolor(*pixel));
+            palette.quantize(&mut source, config.dither);
+        }
+
+        // cell pixels outside the tile shape are skipped, None when it is the whole cell
+        let mask = (config.tile_shape != TileShape::Square).then(|| {
+            (0..tile_size * tile_size).map(|i| config.tile_shape.coverage(i % tile_size, i / tile_size, tile_size, config.antialias)).collect()
+        });
+
+        // the tiles are picked up front, every cell on its own in parallel, but
+        // in order with a repeat limit, as every tile depends on those placed before it
+        let assignment = match &config.tile_library {
+            Some(tile_library) if config.mode == RenderMode::Tiled => {
+                let matcher = tile_library.matcher(config.gamma, config.match_space);
+                match config.repeat_limit {
+                    0 => Some(match_tiles(&source, config, &matcher)),
+                    _ => Some(assign_tiles(&source, config, tile_library, &matcher)?),
+                }
+            },
+            _ => None,
+        };
+
+        let mut renderer = Renderer {
+            config,
+            source,
+            img_cell,
+            mask,
+            shades,
+            assignment,
+            tile_size,
+            tile_height,
+            width,
+            height,
+            rows_done: Mutex::new(0),
+            rows_total: 0,
+            cancel: None,
+        };
+
+        renderer.rows_total = match config.adaptive {
+            Some((_, max_tile)) => height.div_ceil(max_tile),
+            None => renderer.bands(),
+        };
+        if let Some(progress) = &config.progress {
+            progress.report(0, renderer.rows_total);
+        }
+
+        Ok(renderer)
+    }
+
+    /// Size of the whole mosaic.
+    pub(crate) fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Number of bands, one per source row, and in the hex layout one more
+    /// for the bottom points of the last row.
+    pub(crate) fn bands(&self) -> u32 {
+        let rows = self.source.height();
+        match self.config.layout {
+            Layout::Hex if rows > 0 && self.band_rows() * rows < self.height => rows + 1,
+            _ => rows,
+        }
+    }
+
+    /// Channels of the gap rows above the first band.
+    pub(crate) fn top_len(&self) -> usize {
+        self.row_len() * self.config.gap as usize
+    }
+
+    /// Channels of a single band, the last one possibly being shorter.
+    pub(crate) fn band_len(&self) -> usize {
+        self.row_len() * self.band_rows() as usize
+    }
+
+    /// Channels of `count` bands from band `first` on.
+    pub(crate) fn bands_len(&self, first: u32, count: u32) -> usize {
+        let top = self.top_len() / self.row_len();
+        let rows = |bands: u32| u64::from(bands) * u64::from(self.band_rows());
+        let end = rows(first + count).min(u64::from(self.height - top as u32));
+
+        self.row_len() * (end - rows(first)) as usize
+    }
+
+    fn band_rows(&self) -> u32 {
+        match self.config.layout {
+            Layout::Square => self.tile_height + self.config.gap,
+            Layout::Hex => Hex::new(self.tile_size).pitch,
+        }
+    }
+
+    fn row_len(&self) -> usize {
+        self.width as usize * 4
+    }
+
+    /// Fills `buf` with the gap color, or transparency without gaps, which is
+    /// what shows wherever no tile is drawn.
+    pub(crate) fn fill_background(&self, buf: &mut [T]) {
+        let background = match self.config.gap {
+            0 => Rgba([0, 0, 0, 0]),
+            _ => self.config.gap_color,
+        };
+        let background = T::widen(background);
+
+        buf.chunks_exact_mut(4).for_each(|pixel| pixel.copy_from_slice(&background.0));
+    }
+
+    /// Renders consecutive bands, starting at band `first`, in parallel into
+    /// `buf`, which holds a whole number of bands, see
+    /// [`bands_len`](Self::bands_len).
+    pub(crate) fn render_bands(&self, buf: &mut [T], first: u32) {
+        buf.par_chunks_mut(self.band_len()).enumerate().for_each(|(y, band)| {
+            if self.cancelled() {
+                return;
+            }
+            self.render_band(first + y as u32, band);
+            self.advance();
+        });
+    }
+
+    /// Stops rendering once `cancel` is set, see [`cancelled`](Self::cancelled).
+    pub(crate) fn cancel_on(&mut self, cancel: &'a AtomicBool) {
+        self.cancel = Some(cancel);
+    }
+
+    /// Whether rendering was cancelled, which leaves the mosaic unfinished.
+    /// Every worker checks before each row of tiles it renders.
+    pub(crate) fn cancelled(&self) -> bool {
+        self.cancel.is_some_and(|cancel| cancel.load(Ordering::Relaxed))
+    }
+
+    /// Reports one more finished row of tiles to the progress callback.
+    fn advance(&self) {
+        if let Some(progress) = &self.config.progress {
+            // held while calling back, so the calls come in order
+            let mut done = self.rows_done.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            *done += 1;
+            progress.report(*done, self.rows_total);
+        }
+    }
+
+    fn render_band(&self, y: u32, band: &mut [T]) {
+        self.fill_background(band);
+
+        let rows = (band.len() / self.row_len()) as u32;
+        let mut band = ImageBuffer::<Rgba<T>, &mut [T]>::from_raw(self.width, rows, band)
+            .expect("band length matches its dimensions");
+
+        match self.config.layout {
+            Layout::Square => self.render_square_band(y, &mut band),
+            Layout::Hex => self.render_hex_band(y, &mut band),
+        }
+    }
+
+    fn render_square_band(&self, y: u32, band: &mut ImageBuffer<Rgba<T>, &mut [T]>) {
+        let tile_size = self.tile_size;
+        let gap = self.config.gap;
+        let pitch = tile_size + gap;
+        let coverage = |tile_x: u32, tile_y: u32| self.mask.as_ref().map_or(1.0, |mask| mask[(tile_y * tile_size + tile_x) as usize]);
+
+        let border = self.config.border;
+        let border_color = T::widen(self.config.border_color);
+        let on_border = |side: u32, length: u32| side < border || side + border >= length;
+
+        for x in 0..self.source.width() {
+            let cell = self.cell(x, y);
+            let tile_x0 = gap + x * pitch;
+
+            if let Cell::Empty = cell {
+                continue;
+            }
+
+            for tile_y in 0..self.tile_height {
+                for tile_x in 0..tile_size {
+                    let pixel = match coverage(tile_x, tile_y) {
+                        0.0 => continue,
+                        1.0 => self.cell_pixel(&cell, tile_x, tile_y),
+                        // an anti-aliased edge, part tile and part background
+                        coverage => {
+                            let background = *band.get_pixel(tile_x0 + tile_x, tile_y);
+                            mix(&[(self.cell_pixel(&cell, tile_x, tile_y), coverage), (background, 1.0 - coverage)])
+                        },
+                    };
+                    band.put_pixel(tile_x0 + tile_x, tile_y, pixel);
+                }
+            }
+
+            // the border goes over the finished tile
+            if border > 0 {
+                for tile_y in 0..self.tile_height {
+                    for tile_x in (0..tile_size).filter(|&tile_x| on_border(tile_x, tile_size) || on_border(tile_y, self.tile_height)) {
+                        band.put_pixel(tile_x0 + tile_x, tile_y, border_color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Renders band `y` of the hex layout, the rows from the top of hex row
+    /// `y` to the top of the next one, which the bottom points of row `y - 1`
+    /// reach into. Every pixel is drawn from the hex it lies in.
+    fn render_hex_band(&self, y: u32, band: &mut ImageBuffer<Rgba<T>, &mut [T]>) {
+        let hex = Hex::new(self.tile_size);
+        let (columns, rows) = self.source.dimensions();
+        let hex_rows: Vec<(u32, Vec<Cell<T>>)> = [Some(y), y.checked_sub(1)]
+            .into_iter()
+            .flatten()
+            .filter(|&row| row < rows)
+            .map(|row| (row, (0..columns).map(|x| self.cell(x, row)).collect()))
+            .collect();
+
+        // the tile pixel at a point of the mosaic, None where no tile is drawn
+        let sample = |px: f32, py: f32| {
+            for (row, cells) in &hex_rows {
+                let Some((column, tile_x, tile_y)) = hex.locate(px, py, *row) else {
+                    continue;
+                };
+                let Some(cell) = cells.get(column as usize) else {
+                    continue;
+                };
+
+                return (!matches!(cell, Cell::Empty)).then(|| self.cell_pixel(cell, tile_x, tile_y));
+            }
+            None
+        };
+
+        let samples = self.config.antialias;
+        let step = 1.0 / samples as f32;
+        let weight = step * step;
+
+        for (x, band_y, pixel) in band.enumerate_pixels_mut() {
+            let (x, y) = (x as f32, (y * hex.pitch + band_y) as f32);
+
+            if samples == 1 {
+                if let Some(color) = sample(x + 0.5, y + 0.5) {
+                    *pixel = color;
+                }
+                continue;
+            }
+
+            let colors: Vec<_> = (0..samples * samples)
+                .map(|i| {
+                    let (sx, sy) = ((i % samples) as f32 + 0.5, (i / samples) as f32 + 0.5);
+                    (sample(x + sx * step, y + sy * step).unwrap_or(*pixel), weight)
+                })
+                .collect();
+            *pixel = mix(&colors);
+        }
+    }
+
+    /// What fills the cell of source pixel (`x`, `y`).
+    fn cell(&self, x: u32, y: u32) -> Cell<'_, T> {
+        let config = self.config;
+
+        // get pixel color from original image
+        let pixel = *self.source.get_pixel(x, y);
+
+        // transparent source pixels leave their cell empty
+        if config.skips(pixel) {
+            return Cell::Empty;
+        }
+
+        // pixelation: a solid block of the pixel color
+        if self.img_cell.is_none() {
+            return Cell::Solid(self.shade(config.block(pixel), x, y));
+        }
+
+        // photomosaic: paste the best matching library tile as is
+        if let (Some(tile_library), Some(assignment)) = (&config.tile_library, &self.assignment) {
+            let tile = match assignment[y as usize * self.source.width() as usize + x as usize] {
+                Some(index) => tile_library.tile(index),
+                None => return Cell::Empty,
+            };
+
+            let target = (config.tile_tint > 0.0).then(|| config.block(pixel));
+
+            if config.rotate_tiles {
+                return Cell::Tile(Cow::Owned(rotate(tile, cell_random(config.seed, x, y))), target);
+            }
+            return Cell::Tile(Cow::Borrowed(tile), target);
+        }
+
+        // a self-mosaic pulls its miniatures toward the cell color instead
+        if config.self_tile && config.tile_tint > 0.0 {
+            return Cell::Pulled(self.fade(self.shade(config.block(pixel), x, y), x, y));
+        }
+
+        Cell::Tinted(self.fade(self.shade(config.tint(pixel), x, y), x, y))
+    }
+
+    /// `color` darkened by the edge strength of cell (`x`, `y`), alpha kept.
+    fn shade(&self, mut color: Rgba<T>, x: u32, y: u32) -> Rgba<T> {
+        if let Some(shades) = &self.shades {
+            let shade = shades[y as usize * self.source.width() as usize + x as usize];
+
+            for channel in &mut color.0[..3] {
+                *channel = T::from_f32(channel.to_f32() * shade);
+            }
+        }
+        color
+    }
+
+    /// `color` with its alpha faded by the vignette at cell (`x`, `y`).
+    fn fade(&self, mut color: Rgba<T>, x: u32, y: u32) -> Rgba<T> {
+        let strength = self.config.vignette;
+        if strength == 0.0 {
+            return color;
+        }
+
+        // -1.0 to 1.0 from the first to the last cell of a row or column
+        let offset = |position: u32, cells: u32| {
+            if cells > 1 { 2.0 * position as f32 / (cells - 1) as f32 - 1.0 } else { 0.0 }
+        };
+        let (dx, dy) = (offset(x, self.source.width()), offset(y, self.source.height()));
+
+        // 0.0 in the center, 1.0 in the corners
+        let distance = ((dx * dx + dy * dy) / 2.0).sqrt();
+        let factor = if strength > 0.0 { 1.0 - strength * (1.0 - distance) } else { 1.0 + strength * distance };
+
+        color.0[3] = T::from_f32(color.0[3].to_f32() * factor);
+        color
+    }
+
+    /// Pixel (`tile_x`, `tile_y`) of a filled `cell`.
+    fn cell_pixel(&self, cell: &Cell<T>, tile_x: u32, tile_y: u32) -> Rgba<T> {
+        match cell {
+            Cell::Solid(color) => *color,
+            // library tiles are 8 bit
+            Cell::Tile(tile, target) => {
+                let pixel = T::widen(*tile.get_pixel(tile_x, tile_y));
+                match target {
+                    Some(target) => self.pulled(pixel, *target, self.config.tile_tint),
+                    None => pixel,
+                }
+            },
+            // tint the tile straight into the new image
+            Cell::Tinted(color) => {
+                let img_cell = self.img_cell.as_ref().expect("tinted cells have a tile");
+                self.tinted(*img_cell.get_pixel(tile_x, tile_y), *color)
+            },
+            // the faded alpha of the color weakens the pull
+            Cell::Pulled(color) => {
+                let img_cell = self.img_cell.as_ref().expect("pulled cells have a tile");
+                let strength = self.config.tile_tint * color.0[3].to_unit();
+                self.pulled(*img_cell.get_pixel(tile_x, tile_y), *color, strength)
+            },
+            Cell::Empty => unreachable!("empty cells are skipped"),
+        }
+    }
+
+    /// The tile pixel `pixel` pulled toward `target` by `strength`, its
+    /// alpha kept.
+    fn pulled(&self, mut pixel: Rgba<T>, target: Rgba<T>, strength: f32) -> Rgba<T> {
+        for (channel, &target) in pixel.0.iter_mut().zip(&target.0).take(3) {
+            *channel = T::from_f32(channel.to_f32() + (target.to_f32() - channel.to_f32()) * strength);
+        }
+        pixel
+    }
+
+    /// The tile pixel `pixel` with the tint `color` blended over it.
+    fn tinted(&self, mut pixel: Rgba<T>, color: Rgba<T>) -> Rgba<T> {
+        match self.config.gamma {
+            Some(gamma) => self.config.blend.mix_linear(&mut pixel, color, gamma),
+            None => self.config.blend.mix(&mut pixel, color),
+        }
+        pixel
+    }
+
+    /// Renders the whole mosaic into `buf` with tiles from `min_tile` to
+    /// `max_tile` pixels, see [`adaptive`](crate::MosaicConfigBuilder::adaptive),
+    /// in parallel per row of the largest tiles.
+    pub(crate) fn render_adaptive(&self, buf: &mut [T], (min_tile, max_tile): (u32, u32)) {
+        self.fill_background(buf);
+        let rows = self.height.div_ceil(max_tile);
+
+        let blocks: Vec<Vec<Block<T>>> = (0..rows)
+            .into_par_iter()
+            .map(|row| {
+                let (y, mut blocks) = (row * max_tile, Vec::new());
+                for x in (0..self.width).step_by(max_tile as usize) {
+                    self.split(x, y, max_tile.min(self.width - x), max_tile.min(self.height - y), min_tile, &mut blocks);
+                }
+                blocks
+            })
+            .collect();
+
+        // the tile is resized once to every block size there is
+        let tiles: HashMap<(u32, u32), ImageBuffer<Rgba<T>, Vec<T>>> = match &self.img_cell {
+            Some(img_cell) => {
+                let sizes: HashSet<(u32, u32)> = blocks.iter().flatten().map(|block| (block.width, block.height)).collect();
+                sizes
+                    .into_par_iter()
+                    .map(|(width, height)| ((width, height), imageops::resize(img_cell, width, height, FilterType::Triangle)))
+                    .collect()
+            },
+            None => HashMap::new(),
+        };
+
+        buf.par_chunks_mut(self.row_len() * max_tile as usize).zip(blocks).enumerate().for_each(|(row, (band, blocks))| {
+            if self.cancelled() {
+                return;
+            }
+            let rows = (band.len() / self.row_len()) as u32;
+            let mut band = ImageBuffer::<Rgba<T>, &mut [T]>::from_raw(self.width, rows, band)
+                .expect("band length matches its dimensions");
+
+            for block in &blocks {
+                self.render_block(block, tiles.get(&(block.width, block.height)), &mut band, row as u32 * max_tile);
+            }
+            self.advance();
+        });
+    }
+
+    /// Adds the block of `width` × `height` mosaic pixels at (`x`, `y`) to
+    /// `blocks`, or its quarters if the source under it is too detailed and
+    /// they are no smaller than `min_tile`.
+    fn split(&self, x: u32, y: u32, width: u32, height: u32, min_tile: u32, blocks: &mut Vec<Block<T>>) {
+        let (color, deviation) = self.source_under(x, y, width, height);
+        let (left, top) = (width / 2, height / 2);
+
+        if deviation <= self.config.adaptive_threshold || left < min_tile || top < min_tile {
+            blocks.push(Block { x, y, width, height, color });
+            return;
+        }
+
+        for (dx, width) in [(0, left), (left, width - left)] {
+            for (dy, height) in [(0, top), (top, height - top)] {
+                self.split(x + dx, y + dy, width, height, min_tile, blocks);
+            }
+        }
+    }
+
+    /// The mean color of the source cells under the `width` × `height`
+    /// mosaic pixels at (`x`, `y`), and the standard deviation of their
+    /// luminance on the 8 bit scale.
+    fn source_under(&self, x: u32, y: u32, width: u32, height: u32) -> (Rgba<T>, f32) {
+        let tile_size = self.tile_size;
+        let columns = x / tile_size..(x + width).div_ceil(tile_size).min(self.source.width());
+        let rows = y / tile_size..(y + height).div_ceil(tile_size).min(self.source.height());
+
+        let (mut sums, mut luminance, mut squares, mut count) = ([0.0f64; 4], 0.0f64, 0.0f64, 0.0f64);
+        for source_y in rows {
+            for source_x in columns.clone() {
+                let pixel = *self.source.get_pixel(source_x, source_y);
+                for (total, channel) in sums.iter_mut().zip(pixel.0) {
+                    *total += f64::from(channel.to_f32());
+                }
+
+                let l = f64::from(color::luminance_of(pixel).to_unit()) * 255.0;
+                luminance += l;
+                squares += l * l;
+                count += 1.0;
+            }
+        }
+
+        let mean = Rgba(sums.map(|total| T::from_f32((total / count) as f32)));
+        let deviation = (squares / count - (luminance / count).powi(2)).max(0.0).sqrt();
+
+        (mean, deviation as f32)
+    }
+
+    /// Draws `block` into `band`, whose first row is row `top` of the mosaic,
+    /// with `tile` resized to it or, when pixelating, as a solid block.
+    fn render_block(&self, block: &Block<T>, tile: Option<&ImageBuffer<Rgba<T>, Vec<T>>>, band: &mut ImageBuffer<Rgba<T>, &mut [T]>, top: u32) {
+        // transparent source under the block leaves it empty
+        if self.config.skips(block.color) {
+            return;
+        }
+
+        let (tint, solid) = (self.config.tint(block.color), self.config.block(block.color));
+
+        for tile_y in 0..block.height {
+            for tile_x in 0..block.width {
+                let pixel = match tile {
+                    Some(tile) if self.config.self_tile && self.config.tile_tint > 0.0 => self.pulled(*tile.get_pixel(tile_x, tile_y), solid, self.config.tile_tint),
+                    Some(tile) => self.tinted(*tile.get_pixel(tile_x, tile_y), tint),
+                    None => solid,
+                };
+                band.put_pixel(block.x + tile_x, block.y - top + tile_y, pixel);
+            }
+        }
+    }
+}
+
+/// A block of an adaptive mosaic, in mosaic pixels, and the mean color of
+/// the source under it.
+struct Block<T: Channel> {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    color: Rgba<T>,
+}
+
+/// What fills one cell of the mosaic.
+enum Cell<'a, T: Channel> {
+    /// Nothing, the background shows.
+    Empty,
+    /// A solid color.
+    Solid(Rgba<T>),
+    /// A photomosaic tile, and the color it is pulled toward if any.
+    Tile(Cow<'a, RgbaImage>, Option<Rgba<T>>),
+    /// The repeated tile with this tint.
+    Tinted(Rgba<T>),
+    /// The repeated tile pulled toward this color, in a self-mosaic.
+    Pulled(Rgba<T>),
+}
+
+/// The mean of `colors` by their weights, which add up to 1.0, with the
+/// colors weighted by their alpha too so transparent ones add no color.
+fn mix<T: Channel>(colors: &[(Rgba<T>, f32)]) -> Rgba<T> {
+    let alpha: f32 = colors.iter().map(|(color, weight)| color.0[3].to_f32() * weight).sum();
+    let mut mixed = Rgba([T::from_f32(0.0); 4]);
+
+    if alpha > 0.0 {
+        for channel in 0..3 {
+            let sum: f32 = colors.iter().map(|(color, weight)| color.0[channel].to_f32() * color.0[3].to_f32() * weight).sum();
+            mixed.0[channel] = T::from_f32(sum / alpha);
+        }
+    }
+    mixed.0[3] = T::from_f32(alpha);
+    mixed
+}
+
+/// Geometry of the hex layout: `tile_size` wide hexagons with their points
+/// up, as high as they are wide, every odd row shifted right by half a tile
+/// and every row overlapping the one above by a quarter of a tile, so they
+/// tessellate.
+pub(crate) struct Hex {
+    tile_size: u32,
+    /// Rows from the top of one hex row to the top of the next.
+    pitch: u32,
+}
+
+impl Hex {
+    pub(crate) fn new(tile_size: u32) -> Hex {
+        Hex {
+            tile_size,
+            pitch: tile_size - tile_size / 4,
+        }
+    }
+
+    /// Size of the mosaic of `columns` × `rows` hexes.
+    pub(crate) fn dimensions(&self, (columns, rows): (u32, u32)) -> (u64, u64) {
+        let (tile_size, pitch) = (u64::from(self.tile_size), u64::from(self.pitch));
+        let width = u64::from(columns) * tile_size + if rows > 1 { tile_size.div_ceil(2) } else { 0 };
+        let height = if rows > 0 { u64::from(rows - 1) * pitch + tile_size } else { 0 };
+
+        (width, height)
+    }
+
+    /// How far right hex row `row` is shifted.
+    fn offset(&self, row: u32) -> f32 {
+        if row % 2 == 1 { self.tile_size as f32 / 2.0 } else { 0.0 }
+    }
+
+    /// The column and tile pixel of the hex in row `row` the point
+    /// (`px`, `py`) of the mosaic lies in, if any.
+    fn locate(&self, px: f32, py: f32, row: u32) -> Option<(u32, u32, u32)> {
+        let size = self.tile_size as f32;
+        let x = px - self.offset(row);
+        let y = py - (row * self.pitch) as f32;
+
+        if x < 0.0 || !(0.0..size).contains(&y) {
+            return None;
+        }
+
+        let column = (x / size) as u32;
+        let x = x - column as f32 * size;
+
+        // the slanted edges rise a quarter of a tile from the sides to the points
+        let slant = (size - self.pitch as f32) * (x - size / 2.0).abs() / (size / 2.0);
+        if y < slant || y > size - slant {
+            return None;
+        }
+
+        let last = self.tile_size - 1;
+        Some((column, (x as u32).min(last), (y as u32).min(last)))
+    }
+}
+
+/// Thresholds of ordered dithering, 0 to 15 over 4 × 4 cells.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Range of the offsets tile dithering adds to the cell colors, on the 8 bit
+/// scale.
+const TILE_DITHER_SPREAD: f32 = 32.0;
+
+/// The color the library tile of source pixel (`x`, `y`) is matched with,
+/// the pixel offset by the Bayer threshold of the cell with tile dithering.
+fn match_color<T: Channel>(config: &MosaicConfig, pixel: Rgba<T>, x: u32, y: u32) -> Rgba<u8> {
+    let mut color = T::narrow(pixel);
+
+    if config.tile_dither {
+        let threshold = f32::from(BAYER[(y % 4) as usize][(x % 4) as usize]);
+        let offset = ((threshold + 0.5) / 16.0 - 0.5) * TILE_DITHER_SPREAD;
+
+        for channel in &mut color.0[..3] {
+            *channel = (f32::from(*channel) + offset).round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    color
+}
+
+/// `source` turned, cropped and mirrored as `config` asks.
+pub(crate) fn oriented_source<'a>(source: &'a DynamicImage, config: &MosaicConfig) -> Cow<'a, DynamicImage> {
+    // turned before cropping, the crop is measured on the turned source
+    let source = match config.rotation {
+        90 => Cow::Owned(source.rotate90()),
+        180 => Cow::Owned(source.rotate180()),
+        270 => Cow::Owned(source.rotate270()),
+        _ => Cow::Borrowed(source),
+    };
+
+    let mut source = match config.crop {
+        Some(crop) => Cow::Owned(source.crop_imm(crop.x, crop.y, crop.width, crop.height)),
+        None => source,
+    };
+
+    // mirrored after cropping, the crop is measured on the source as given
+    if config.flip_horizontal {
+        source = Cow::Owned(source.fliph());
+    }
+    if config.flip_vertical {
+        source = Cow::Owned(source.flipv());
+    }
+    source
+}
+
+/// The color of every cell of the oriented `source`, adjusted and
+/// downsampled to the grid of `config`, one pixel per cell.
+pub(crate) fn sampled_grid<T: Channel>(source: &DynamicImage, config: &MosaicConfig) -> ImageBuffer<Rgba<T>, Vec<T>> {
+    let mut source = T::rgba(source);
+    source.pixels_mut().for_each(|pixel| *pixel = config.adjust(*pixel));
+
+    match config.grid {
+        Some(grid) => {
+            let (width_grid, height_grid) = grid.dimensions(source.dimensions());
+            match config.sample {
+                SampleMode::Point => imageops::resize(&source, width_grid, height_grid, FilterType::Triangle),
+                SampleMode::Average => average_cells(&source, width_grid, height_grid),
+            }
+        },
+        None => source,
+    }
+}
+
+/// Picks the nearest library tile of every cell, the cells in parallel.
+/// Cells left empty for transparency get `None`.
+fn match_tiles<T: Channel>(source: &ImageBuffer<Rgba<T>, Vec<T>>, config: &MosaicConfig, matcher: &TileMatcher) -> Vec<Option<usize>> {
+    (0..source.height())
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            (0..source.width()).map(move |x| {
+                let pixel = *source.get_pixel(x, y);
+                (!config.skips(pixel)).then(|| matcher.nearest(match_color(config, pixel, x, y)))
+            })
+        })
+        .collect()
+}
+
+/// Picks the library tile of every cell in reading order, the nearest one
+/// not already used within the repeat limit to the left or above. Cells left
+/// empty for transparency get `None`.
+fn assign_tiles<T: Channel>(source: &ImageBuffer<Rgba<T>, Vec<T>>, config: &MosaicConfig, tile_library: &TileLibrary, matcher: &TileMatcher) -> Result<Vec<Option<usize>>, MosaicError> {
+    let (width, height) = source.dimensions();
+    let limit = config.repeat_limit as usize;
+    let mut assignment: Vec<Option<usize>> = Vec::with_capacity(width as usize * height as usize);
+
+    for (x, y, &pixel) in source.enumerate_pixels() {
+        if config.skips(pixel) {
+            assignment.push(None);
+            continue;
+        }
+
+        let color = match_color(config, pixel, x, y);
+        let (x, y, width) = (x as usize, y as usize, width as usize);
+        let left = (x.saturating_sub(limit)..x).map(|x| y * width + x);
+        let above = (y.saturating_sub(limit)..y).map(|y| y * width + x);
+        let blocked: Vec<usize> = left.chain(above).filter_map(|cell| assignment[cell]).collect();
+
+        let index = matcher
+            .nearest_allowed(color, |index| !blocked.contains(&index))
+            .ok_or(MosaicError::InsufficientTiles {
+                tiles: tile_library.len(),
+                repeat_limit: config.repeat_limit,
+            })?;
+        assignment.push(Some(index));
+    }
+
+    Ok(assignment)
+}
+
+/// The mean of every pixel over `tiles`, which all have the same size.
+fn mean_tile<T: Channel>(tiles: &[ImageBuffer<Rgba<T>, Vec<T>>]) -> ImageBuffer<Rgba<T>, Vec<T>> {
+    let count = tiles.len() as u64;
+    let (width, height) = tiles[0].dimensions();
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        Rgba([0, 1, 2, 3].map(|channel| {
+            let sum: u64 = tiles.iter().map(|tile| tile.get_pixel(x, y).0[channel].into()).sum();
+            T::from_f32(((sum + count / 2) / count) as f32)
+        }))
+    })
+}
+
+/// Downsamples `source` to `width` × `height` cells, each the mean of the
+/// source pixels it covers, in a single pass over the source.
+pub(crate) fn average_cells<T: Channel>(source: &ImageBuffer<Rgba<T>, Vec<T>>, width: u32, height: u32) -> ImageBuffer<Rgba<T>, Vec<T>> {
+    let (width_orig, height_orig) = source.dimensions();
+    let cell = |x: u32, size_orig: u32, size: u32| (u64::from(x) * u64::from(size) / u64::from(size_orig)) as usize;
+    let mut sums = vec![[0u64; 5]; width as usize * height as usize];
+
+    for (x, y, pixel) in source.enumerate_pixels() {
+        let sum = &mut sums[cell(y, height_orig, height) * width as usize + cell(x, width_orig, width)];
+
+        for (total, channel) in sum.iter_mut().zip(pixel.0) {
+            *total += channel.into();
+        }
+        sum[4] += 1;
+    }
+
+    ImageBuffer::from_fn(width, height, |x, y| {
+        let sum = sums[y as usize * width as usize + x as usize];
+
+        // a grid finer than the source leaves cells without a pixel of their own
+        if sum[4] == 0 {
+            let at = |x: u32, size: u32, size_orig: u32| (u64::from(x) * u64::from(size_orig) / u64::from(size)) as u32;
+            return *source.get_pixel(at(x, width, width_orig), at(y, height, height_orig));
+        }
+
+        Rgba([0, 1, 2, 3].map(|channel| T::from_f32(((sum[channel] + sum[4] / 2) / sum[4]) as f32)))
+    })
+}
+
+/// Stretches every color channel of `grid` linearly so the cells `clip`
+/// percent in from the darkest and the lightest become black and white.
+/// Channels with a single value left are kept as they are.
+fn stretch_levels<T: Channel>(grid: &mut ImageBuffer<Rgba<T>, Vec<T>>, clip: f32) {
+    let count = grid.pixels().len();
+    if count == 0 {
+        return;
+    }
+    let skip = ((count as f32 * clip / 100.0) as usize).min((count - 1) / 2);
+
+    for channel in 0..3 {
+        let mut values: Vec<f32> = grid.pixels().map(|pixel| pixel.0[channel].to_f32()).collect();
+        values.sort_unstable_by(f32::total_cmp);
+
+        let (low, high) = (values[skip], values[count - 1 - skip]);
+        if high <= low {
+            continue;
+        }
+
+        for pixel in grid.pixels_mut() {
+            let value = (pixel.0[channel].to_f32() - low) / (high - low) * T::MAX;
+            pixel.0[channel] = T::from_f32(value.clamp(0.0, T::MAX));
+        }
+    }
+}
+
+/// How much of its color every cell of `grid` keeps, `1.0` in flat areas
+/// down to `1.0 - strength` on the hardest edges, by the Sobel gradient of
+/// the luminance. Cells past the border repeat the border cells.
+fn edge_shades<T: Channel>(grid: &ImageBuffer<Rgba<T>, Vec<T>>, strength: f32) -> Vec<f32> {
+    let (width, height) = grid.dimensions();
+    let luminance = |x: i64, y: i64| {
+        let (x, y) = (x.clamp(0, i64::from(width) - 1) as u32, y.clamp(0, i64::from(height) - 1) as u32);
+        color::luminance_of(*grid.get_pixel(x, y)).to_unit()
+    };
+
+    grid
+        .enumerate_pixels()
+        .map(|(x, y, _)| {
+            let (x, y) = (i64::from(x), i64::from(y));
+            let gx = luminance(x + 1, y - 1) + 2.0 * luminance(x + 1, y) + luminance(x + 1, y + 1)
+                - luminance(x - 1, y - 1) - 2.0 * luminance(x - 1, y) - luminance(x - 1, y + 1);
+            let gy = luminance(x - 1, y + 1) + 2.0 * luminance(x, y + 1) + luminance(x + 1, y + 1)
+                - luminance(x - 1, y - 1) - 2.0 * luminance(x, y - 1) - luminance(x + 1, y - 1);
+
+            // a step from black to white has a gradient of 4
+            let edge = ((gx * gx + gy * gy).sqrt() / 4.0).min(1.0);
+            1.0 - strength * edge
+        })
+        .collect()
+}
+
+/// `tile` turned by a multiple of 90° picked by `random`.
+fn rotate(tile: &RgbaImage, random: u64) -> RgbaImage {
+    match random % 4 {
+        0 => tile.clone(),
+        1 => imageops::rotate90(tile),
+        2 => imageops::rotate180(tile),
+        _ => imageops::rotate270(tile),
+    }
+}
+
+/// A random number for cell (`x`, `y`) that only depends on `seed` and the
+/// cell, so bands can be rendered in any order and still be reproducible.
+fn cell_random(seed: u64, x: u32, y: u32) -> u64 {
+    // splitmix64
+    let mut z = seed ^ (u64::from(y) << 32 | u64::from(x)).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+
+    z ^ (z >> 31)
+}
@@ -0,0 +1,125 @@
+use std::str::FromStr;
+use crate::MosaicError;
+
+/// Outline of every tile, pixels outside of it are left out of the mosaic.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TileShape {
+    /// The whole cell.
+    #[default]
+    Square,
+    /// A circle of radius `tile_size / 2` centered in the cell.
+    Circle,
+    /// A square with corners rounded to a quarter of the tile size.
+    Rounded,
+}
+
+impl TileShape {
+    /// Whether pixel (`x`, `y`) of a `tile_size` × `tile_size` cell lies
+    /// inside the shape, judged at the pixel center.
+    pub fn covers(self, x: u32, y: u32, tile_size: u32) -> bool {
+        self.contains(x as f32 + 0.5, y as f32 + 0.5, tile_size as f32)
+    }
+
+    /// The share of pixel (`x`, `y`) of a `tile_size` × `tile_size` cell
+    /// inside the shape, judged at `samples` × `samples` points spread over
+    /// the pixel, from 0.0 to 1.0. One sample judges the pixel center only,
+    /// like [`covers`](Self::covers).
+    pub fn coverage(self, x: u32, y: u32, tile_size: u32, samples: u32) -> f32 {
+        let step = 1.0 / samples as f32;
+        let points = (0..samples * samples).filter(|i| {
+            let (sx, sy) = ((i % samples) as f32 + 0.5, (i / samples) as f32 + 0.5);
+            self.contains(x as f32 + sx * step, y as f32 + sy * step, tile_size as f32)
+        });
+
+        points.count() as f32 / (samples * samples) as f32
+    }
+
+    /// Whether the point (`px`, `py`) of a `size` × `size` cell lies inside the shape.
+    fn contains(self, px: f32, py: f32, size: f32) -> bool {
+        // distance to the nearest point of the inner square the corners are
+        // rounded around, a circle being fully rounded
+        let radius = match self {
+            TileShape::Square => return true,
+            TileShape::Circle => size / 2.0,
+            TileShape::Rounded => size / 4.0,
+        };
+        let dx = px - px.clamp(radius, size - radius);
+        let dy = py - py.clamp(radius, size - radius);
+
+        dx * dx + dy * dy <= radius * radius
+    }
+}
+
+impl FromStr for TileShape {
+    type Err = MosaicError;
+
+    fn from_str(value: &str) -> Result<TileShape, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "square" => Ok(TileShape::Square),
+            "circle" => Ok(TileShape::Circle),
+            "rounded" => Ok(TileShape::Rounded),
+            _ => Err(MosaicError::InvalidValue {
+                kind: "tile shape",
+                value: value.to_string(),
+                expected: "square, circle or rounded",
+            }),
+        }
+    }
+}
+
+/// How the tiles are laid out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// A grid of square cells.
+    #[default]
+    Square,
+    /// A honeycomb of hexagons with their points up, every odd row shifted
+    /// right by half a tile and the rows closer together by a quarter of a
+    /// tile, so they tessellate.
+    Hex,
+}
+
+impl FromStr for Layout {
+    type Err = MosaicError;
+
+    fn from_str(value: &str) -> Result<Layout, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "square" => Ok(Layout::Square),
+            "hex" => Ok(Layout::Hex),
+            _ => Err(MosaicError::InvalidValue {
+                kind: "layout",
+                value: value.to_string(),
+                expected: "square or hex",
+            }),
+        }
+    }
+}
+
+/// What the repeated tile under the tints shows.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TilePattern {
+    /// A thumbnail of the source, or of the tile image.
+    #[default]
+    Image,
+    /// The pattern color all over.
+    Solid,
+    /// A checkerboard of the pattern and the checker color.
+    Checker,
+}
+
+impl FromStr for TilePattern {
+    type Err = MosaicError;
+
+    fn from_str(value: &str) -> Result<TilePattern, MosaicError> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "image" => Ok(TilePattern::Image),
+            "solid" => Ok(TilePattern::Solid),
+            "checker" => Ok(TilePattern::Checker),
+            _ => Err(MosaicError::InvalidValue {
+                kind: "pattern",
+                value: value.to_string(),
+                expected: "image, solid or checker",
+            }),
+        }
+    }
+}
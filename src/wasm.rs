@@ -0,0 +1,15 @@
+use wasm_bindgen::prelude::*;
+use crate::{mosaic_bytes, ImageFormat, MosaicConfig};
+
+/// Builds the mosaic of the encoded image `input` with `tile_size` pixel
+/// tiles tinted by `alpha`, returned as PNG bytes.
+///
+/// Everything runs on the calling thread, so it can be called without
+/// threads in the browser. Errors are thrown as JavaScript errors carrying
+/// the error message.
+#[wasm_bindgen]
+pub fn mosaic(input: &[u8], tile_size: u32, alpha: u8) -> Result<Vec<u8>, JsError> {
+    let config = MosaicConfig::new(tile_size).alpha(alpha).build()?;
+
+    Ok(mosaic_bytes(input, &config, ImageFormat::Png)?)
+}
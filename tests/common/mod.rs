@@ -0,0 +1,23 @@
+// every test binary compiles its own copy and uses only some helpers
+#![allow(dead_code)]
+
+use std::path::PathBuf;
+use mosaic_maker::{open_image, DynamicImage, Rgba};
+
+pub fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures").join(name)
+}
+
+pub fn open_fixture(name: &str) -> DynamicImage {
+    open_image(&fixture(name)).unwrap()
+}
+
+/// Whether every channel of `a` and `b` is within `tolerance`.
+pub fn close(a: Rgba<u8>, b: Rgba<u8>, tolerance: u8) -> bool {
+    a.0.iter().zip(b.0).all(|(&a, b)| a.abs_diff(b) <= tolerance)
+}
+
+/// A path in the temp directory unique to this test process.
+pub fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("mosaic-maker-{}-{}", std::process::id(), name))
+}
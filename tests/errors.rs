@@ -0,0 +1,100 @@
+mod common;
+
+use std::error::Error;
+use common::{fixture, open_fixture};
+use mosaic_maker::{build_mosaic, decode_image, estimate_output_bytes, mosaic_dimensions, open_image, output_dimensions, DynamicImage, FilterType, GenericImageView, Grid, MosaicConfig, MosaicError, Palette, RgbaImage, TileLibrary, DEFAULT_MAX_SIDE};
+
+#[test]
+fn tile_size_1_is_rejected() {
+    assert!(matches!(MosaicConfig::new(1).build(), Err(MosaicError::InvalidTileSize(1))));
+}
+
+#[test]
+fn absurd_tile_size_is_rejected_before_allocating() {
+    assert!(matches!(MosaicConfig::new(100_000).build(), Err(MosaicError::TileTooLarge { tile_size: 100_000, .. })));
+    assert!(matches!(MosaicConfig::new(64).max_pixels(1000).build(), Err(MosaicError::TileTooLarge { max_bytes: 4000, .. })));
+    assert!(matches!(TileLibrary::new([DynamicImage::new_rgba8(1, 1)], 100_000, FilterType::Triangle), Err(MosaicError::TileTooLarge { .. })));
+}
+
+#[test]
+fn rotations_other_than_quarter_turns_are_rejected() {
+    assert!(matches!(MosaicConfig::new(2).rotate(45).build(), Err(MosaicError::InvalidValue { kind: "rotation", .. })));
+    assert!(MosaicConfig::new(2).rotate(270).build().is_ok());
+}
+
+#[test]
+fn nonexistent_file_is_rejected() {
+    assert!(matches!(open_image(&fixture("missing.png")), Err(MosaicError::Read { .. })));
+}
+
+#[test]
+fn corrupt_bytes_are_rejected() {
+    assert!(matches!(open_image(&fixture("corrupt.png")), Err(MosaicError::Decode { .. })));
+    assert!(matches!(decode_image(&[0x89, b'P', b'N', b'G', 0, 0], "corrupt.png"), Err(MosaicError::Decode { .. })));
+}
+
+#[test]
+fn decode_errors_keep_the_codec_error_as_their_source() {
+    let err = open_image(&fixture("corrupt.png")).unwrap_err();
+    let source = err.source().expect("a decode error has a source");
+
+    assert!(source.downcast_ref::<image::ImageError>().is_some());
+    assert!(err.to_string().ends_with(&source.to_string()));
+}
+
+#[test]
+fn crop_outside_the_source_is_rejected() {
+    let config = MosaicConfig::new(4).crop("2,0,2,2".parse().unwrap()).build().unwrap();
+
+    assert!(matches!(build_mosaic(&open_fixture("red.png"), &config), Err(MosaicError::CropOutOfBounds { width: 3, height: 2, .. })));
+}
+
+#[test]
+fn palette_of_fewer_than_2_colors_is_rejected() {
+    assert!(matches!(MosaicConfig::new(4).adaptive_palette(1).build(), Err(MosaicError::PaletteTooSmall(1))));
+    assert!(matches!("#FF0000".parse::<Palette>(), Err(MosaicError::PaletteTooSmall(1))));
+}
+
+#[test]
+fn output_size_beyond_the_limits_is_rejected() {
+    assert!(matches!(MosaicConfig::new(4).output_size(0, 10).build(), Err(MosaicError::InvalidValue { kind: "output size", .. })));
+    assert!(matches!(MosaicConfig::new(4).max_side(100).output_size(101, 10).build(), Err(MosaicError::OutputTooLarge { .. })));
+    assert!(matches!(MosaicConfig::new(4).max_pixels(99).output_size(10, 10).build(), Err(MosaicError::TooManyPixels { pixels: 100, .. })));
+}
+
+#[test]
+fn sizes_near_the_integer_limits_are_rejected_instead_of_wrapping() {
+    assert!(matches!(MosaicConfig::new(u32::MAX).build(), Err(MosaicError::Overflow("tile size"))));
+    assert!(matches!(output_dimensions((u32::MAX, 1), u32::MAX, u32::MAX, u32::MAX), Err(MosaicError::Overflow("width"))));
+
+    // every cell and gap is u32::MAX wide, so the width does not even fit a u64
+    let source = DynamicImage::new_rgba8(4, 4);
+    let gaps = MosaicConfig::new(2).gap(u32::MAX).grid(Grid::Exact(u32::MAX, 1)).max_side(u32::MAX).max_pixels(u64::MAX).build().unwrap();
+    assert!(matches!(mosaic_dimensions(source.dimensions(), &gaps), Err(MosaicError::Overflow("width"))));
+    assert!(matches!(build_mosaic(&source, &gaps), Err(MosaicError::Overflow("width"))));
+    assert_eq!(estimate_output_bytes(source.dimensions(), &gaps), u64::MAX);
+
+    // without the gaps it fits, and the real width is reported
+    let cells = MosaicConfig::new(2).grid(Grid::Exact(u32::MAX, 1)).max_side(u32::MAX).build().unwrap();
+    assert!(matches!(mosaic_dimensions(source.dimensions(), &cells), Err(MosaicError::OutputTooLarge { width: 8_589_934_590, .. })));
+}
+
+#[test]
+fn empty_source_is_rejected() {
+    let config = MosaicConfig::new(4).build().unwrap();
+
+    assert!(matches!(build_mosaic(&DynamicImage::new_rgba8(0, 3), &config), Err(MosaicError::EmptyImage { width: 0, height: 3, .. })));
+}
+
+#[test]
+fn outputs_overflowing_u32_are_refused() {
+    // 100000 * 50000 pixels wide would wrap around a u32
+    let result = output_dimensions((100_000, 10), 50_000, 0, DEFAULT_MAX_SIDE);
+    assert!(matches!(result, Err(MosaicError::OutputTooLarge { width: 5_000_000_000, height: 500_000, .. })));
+
+    // a 4000px wide photo with 20px tiles asks for 80000px
+    let photo = DynamicImage::ImageRgba8(RgbaImage::new(4000, 1));
+    let err = build_mosaic(&photo, &MosaicConfig::new(20).build().unwrap()).unwrap_err();
+    assert!(matches!(err, MosaicError::OutputTooLarge { width: 80_000, height: 20, .. }));
+    assert!(err.to_string().starts_with("Output of 80000x20 is too large"), "{}", err);
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::{env, io::Cursor};
+use tiff::encoder::{colortype, TiffEncoder};
+use common::{close, fixture};
+use mosaic_maker::{apply_orientation, build_mosaic, decode_downsampled, decode_frame, decode_image, encode_mosaic, exif_orientation, expand_path, image, EncodeOptions, DynamicImage, GenericImageView, Grid, ImageFormat, MosaicConfig, MosaicError, RenderMode, Rgba, RgbaImage, SampleMode};
+
+#[test]
+#[cfg(unix)]
+fn tilde_expands_to_the_home_directory() {
+    env::set_var("HOME", "/home/mosaic");
+
+    assert_eq!(expand_path("~/Pictures/photo.jpg"), "/home/mosaic/Pictures/photo.jpg");
+    assert_eq!(expand_path("~"), "/home/mosaic");
+    assert_eq!(expand_path("~other/photo.jpg"), "~other/photo.jpg");
+    assert_eq!(expand_path("./~/photo.jpg"), "./~/photo.jpg");
+}
+
+#[test]
+fn environment_variables_expand_when_set() {
+    env::set_var("MOSAIC_TEST_DIR", "pictures");
+    env::remove_var("MOSAIC_TEST_UNSET");
+
+    assert_eq!(expand_path("$MOSAIC_TEST_DIR/photo.jpg"), "pictures/photo.jpg");
+    assert_eq!(expand_path("${MOSAIC_TEST_DIR}2/photo.jpg"), "pictures2/photo.jpg");
+    assert_eq!(expand_path("%MOSAIC_TEST_DIR%\\photo.jpg"), "pictures\\photo.jpg");
+    assert_eq!(expand_path("$MOSAIC_TEST_UNSET/photo.jpg"), "$MOSAIC_TEST_UNSET/photo.jpg");
+    assert_eq!(expand_path("50% off, 100%.png"), "50% off, 100%.png");
+    assert_eq!(expand_path("https://example.com/$MOSAIC_TEST_DIR.jpg"), "https://example.com/$MOSAIC_TEST_DIR.jpg");
+}
+
+#[test]
+fn downsampled_decoding_matches_the_full_decode() {
+    let gradient = RgbaImage::from_fn(160, 120, |x, y| Rgba([(x * 255 / 159) as u8, (y * 255 / 119) as u8, ((x + y) * 255 / 278) as u8, 255]));
+    let gradient = DynamicImage::ImageRgba8(gradient);
+    let config = MosaicConfig::new(2).mode(RenderMode::Pixelate).grid(Grid::Cells(10)).sample(SampleMode::Average).build().unwrap();
+
+    // PNG sums up the very same pixels, the JPEG decoded at a smaller scale only comes close
+    for (format, tolerance) in [(ImageFormat::Png, 0), (ImageFormat::Jpeg, 8)] {
+        let bytes = encode_mosaic(&gradient, format, &EncodeOptions::default()).unwrap();
+        let downsampled = decode_downsampled(&bytes, "gradient", Grid::Cells(10)).unwrap();
+        assert_eq!(downsampled.dimensions(), (10, 8));
+
+        let full = build_mosaic(&decode_image(&bytes, "gradient").unwrap(), &config).unwrap();
+        let reduced = build_mosaic(&downsampled, &config).unwrap();
+        assert_eq!(full.dimensions(), reduced.dimensions());
+        assert!(full.pixels().zip(reduced.pixels()).all(|((_, _, a), (_, _, b))| close(a, b, tolerance)), "{:?}", format);
+    }
+}
+
+#[test]
+fn a_single_frame_of_an_animation_is_decoded() {
+    let bytes = std::fs::read(fixture("frames.gif")).unwrap();
+
+    // red, green and blue frames
+    let frame = decode_frame(&bytes, "frames.gif", 2).unwrap();
+    assert_eq!(frame.get_pixel(1, 1), Rgba([0, 255, 0, 255]));
+
+    assert!(matches!(decode_frame(&bytes, "frames.gif", 4), Err(MosaicError::FrameOutOfRange { frame: 4, frames: 3, .. })));
+    assert!(matches!(decode_frame(&std::fs::read(fixture("red.png")).unwrap(), "red.png", 2), Err(MosaicError::FrameOutOfRange { frames: 1, .. })));
+}
+
+#[test]
+fn a_single_page_of_a_tiff_is_decoded() {
+    let mut bytes = Cursor::new(Vec::new());
+    {
+        let mut encoder = TiffEncoder::new(&mut bytes).unwrap();
+        encoder.write_image::<colortype::RGB8>(2, 1, &[255, 0, 0, 255, 0, 0]).unwrap();
+        encoder.write_image::<colortype::RGB8>(2, 1, &[0, 0, 255, 0, 0, 255]).unwrap();
+    }
+    let bytes = bytes.into_inner();
+
+    assert_eq!(decode_frame(&bytes, "pages.tiff", 1).unwrap().get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+    assert_eq!(decode_frame(&bytes, "pages.tiff", 2).unwrap().get_pixel(1, 0), Rgba([0, 0, 255, 255]));
+    assert!(matches!(decode_frame(&bytes, "pages.tiff", 3), Err(MosaicError::FrameOutOfRange { frames: 2, .. })));
+}
+
+#[test]
+fn exif_orientation_turns_the_photo_upright() {
+    // a 6x4 JPEG tagged to be turned a quarter clockwise
+    let bytes = std::fs::read(fixture("exif6.jpg")).unwrap();
+    assert_eq!(exif_orientation(&bytes), Some(6));
+
+    let upright = apply_orientation(image::load_from_memory(&bytes).unwrap(), 6);
+    assert_eq!(upright.dimensions(), (4, 6));
+}
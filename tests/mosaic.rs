@@ -0,0 +1,947 @@
+mod common;
+
+use std::{io::{Cursor, Write}, sync::{atomic::{AtomicBool, AtomicU32, Ordering}, Arc, Mutex}};
+use zip::write::SimpleFileOptions;
+use common::{close, fixture, open_fixture};
+use mosaic_maker::{build_mosaic, build_mosaic_cancellable, color_grid, ColorRamp, estimate_output_bytes, grayscale, invert, linear_to_srgb, luminance, mosaic_bytes, square_thumbnail, srgb_to_lab, mosaic_dimensions, mosaic_tile_size, parse_filter, srgb_to_linear, BitDepth, DynamicImage, FilterType, Fit, GenericImageView, ImageBuffer, ImageFormat, Layout, GrayMethod, Grid, MatchSpace, MosaicConfig, DEFAULT_ALPHA_THRESHOLD, DEFAULT_AUTO_SIDE, DEFAULT_GAMMA, DEFAULT_MAX_SIDE, MosaicConfigBuilder, MosaicError, Palette, RenderMode, Rgba, RgbaImage, SampleMode, TileLibrary, TilePattern, TileShape};
+
+#[test]
+fn output_is_tile_size_times_the_source() {
+    let source = open_fixture("red.png");
+    let mosaic = build_mosaic(&source, &MosaicConfig::new(5).build().unwrap()).unwrap();
+
+    assert_eq!(mosaic.dimensions(), (15, 10));
+}
+
+#[test]
+fn solid_red_input_gives_red_tiles() {
+    let source = open_fixture("red.png");
+    let mosaic = build_mosaic(&source, &MosaicConfig::new(4).build().unwrap()).unwrap();
+
+    for y in 0..2 {
+        for x in 0..3 {
+            let center = mosaic.get_pixel(x * 4 + 2, y * 4 + 2);
+            assert!(close(center, Rgba([255, 0, 0, 255]), 2), "tile {},{} is {:?}", x, y, center);
+        }
+    }
+}
+
+#[test]
+fn every_source_pixel_gets_its_own_tile() {
+    let source = open_fixture("checker.png");
+    let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [255, 255, 255, 255]];
+    let tiles = colors.iter().map(|&color| DynamicImage::ImageRgba8(RgbaImage::from_pixel(3, 3, Rgba(color))));
+    let config = MosaicConfig::new(3).tile_library(TileLibrary::new(tiles, 3, FilterType::Triangle).unwrap()).build().unwrap();
+
+    let mosaic = build_mosaic(&source, &config).unwrap();
+
+    // a solid library tile per cell, matching the source pixel it stands for
+    let mut tiles = 0;
+    for (x, y, pixel) in source.pixels() {
+        let cell = mosaic.view(x * 3, y * 3, 3, 3);
+        assert!(cell.pixels().all(|(_, _, cell_pixel)| cell_pixel == pixel));
+        tiles += 1;
+    }
+    assert_eq!(tiles, source.width() * source.height());
+}
+
+#[test]
+fn rotated_tiles_are_reproducible_with_a_seed() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, Rgba([128, 128, 128, 255])));
+    let tile = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 4, |x, y| Rgba([x as u8 * 60, y as u8 * 60, 0, 255])));
+    let library = TileLibrary::new([tile], 4, FilterType::Triangle).unwrap();
+    let mosaic = |seed| {
+        let config = MosaicConfig::new(4).tile_library(library.clone()).rotate_tiles(true).seed(seed).build().unwrap();
+        build_mosaic(&source, &config).unwrap().to_rgba8()
+    };
+
+    let first = mosaic(7);
+    assert_eq!(first.as_raw(), mosaic(7).as_raw());
+
+    // the same single tile is turned differently from cell to cell
+    let corners: Vec<_> = (0..8).map(|x| *first.get_pixel(x * 4, 0)).collect();
+    assert!(corners.iter().any(|&corner| corner != corners[0]));
+}
+
+#[test]
+fn tile_dither_mixes_tiles_along_a_gradient() {
+    // a vertical gradient from just above one tile to just below the next
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 8, |_, y| {
+        let gray = 36 + y as u8 * 3;
+        Rgba([gray, gray, gray, 255])
+    }));
+    let tiles = [0, 32, 64, 96].map(|gray| DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([gray, gray, gray, 255]))));
+    let library = TileLibrary::new(tiles, 2, FilterType::Triangle).unwrap();
+
+    let tiles_per_row = |tile_dither| {
+        let config = MosaicConfig::new(2).tile_library(library.clone()).tile_dither(tile_dither).build().unwrap();
+        let mosaic = build_mosaic(&source, &config).unwrap();
+
+        // ordered, not random, dithering
+        assert_eq!(mosaic, build_mosaic(&source, &config).unwrap());
+
+        (0..8)
+            .map(|y| {
+                let mut row: Vec<_> = (0..8).map(|x| mosaic.get_pixel(x * 2, y * 2).0).collect();
+                row.sort_unstable();
+                row.dedup();
+                row.len()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    // bands of one tile per row, against rows mixing two tiles
+    assert!(tiles_per_row(false).iter().all(|&tiles| tiles == 1));
+    assert!(tiles_per_row(true).iter().filter(|&&tiles| tiles > 1).count() >= 4);
+}
+
+#[test]
+fn repeat_limit_keeps_tiles_apart() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 4, Rgba([200, 0, 0, 255])));
+    let tiles = [[200, 0, 0, 255], [180, 0, 0, 255], [150, 0, 0, 255]].map(|color| DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba(color))));
+    let library = TileLibrary::new(tiles, 2, FilterType::Triangle).unwrap();
+    let config = MosaicConfig::new(2).tile_library(library.clone()).repeat_limit(1).build().unwrap();
+
+    let mosaic = build_mosaic(&source, &config).unwrap();
+
+    for y in 0..4 {
+        for x in 0..4 {
+            let cell = mosaic.get_pixel(x * 2, y * 2);
+            assert!(x == 0 || cell != mosaic.get_pixel(x * 2 - 2, y * 2));
+            assert!(y == 0 || cell != mosaic.get_pixel(x * 2, y * 2 - 2));
+        }
+    }
+
+    let too_strict = MosaicConfig::new(2).tile_library(library).repeat_limit(2).build().unwrap();
+    assert!(matches!(build_mosaic(&source, &too_strict), Err(MosaicError::InsufficientTiles { tiles: 3, repeat_limit: 2 })));
+}
+
+#[test]
+fn tile_matcher_agrees_with_comparing_every_tile() {
+    // a fixed linear congruential sequence of colors, few enough values for ties
+    let mut state = 12345u32;
+    let mut random_color = move || {
+        Rgba([0, 1, 2, 3].map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8 & 0xf0
+        }))
+    };
+
+    let tiles: Vec<_> = (0..300).map(|_| DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, random_color()))).collect();
+    let library = TileLibrary::new(tiles, 2, FilterType::Triangle).unwrap();
+
+    for (gamma, space) in [(None, MatchSpace::Rgb), (Some(2.2), MatchSpace::Rgb), (None, MatchSpace::Lab)] {
+        let matcher = library.matcher(gamma, space);
+
+        for _ in 0..500 {
+            let color = random_color();
+            assert_eq!(matcher.nearest(color), library.nearest_index(color, gamma, space), "{:?} in {:?}", color, space);
+        }
+    }
+}
+
+#[test]
+fn cached_tile_library_matches_the_uncached_one() {
+    let dir = std::env::temp_dir().join(format!("mosaic-maker-cache-{}", std::process::id()));
+    let (tiles_dir, cache_dir) = (dir.join("tiles"), dir.join("cache"));
+    std::fs::create_dir_all(&tiles_dir).unwrap();
+    std::fs::copy(fixture("red.png"), tiles_dir.join("red.png")).unwrap();
+    std::fs::copy(fixture("checker.png"), tiles_dir.join("checker.png")).unwrap();
+
+    let source = open_fixture("checker.png");
+    let render = |library| build_mosaic(&source, &MosaicConfig::new(3).tile_library(library).build().unwrap()).unwrap().to_rgba8();
+    let uncached = render(TileLibrary::from_dir(&tiles_dir, 3, FilterType::Triangle).unwrap());
+
+    // the first run fills the cache, the second reads from it
+    for _ in 0..2 {
+        let cached = TileLibrary::from_dir_cached(&tiles_dir, 3, FilterType::Triangle, &cache_dir).unwrap();
+        assert_eq!(render(cached).as_raw(), uncached.as_raw());
+    }
+    assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 2);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn zipped_tile_library_matches_the_directory_one() {
+    let mut archive = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let entries = [
+        ("red.png", std::fs::read(fixture("red.png")).unwrap()),
+        ("nested/checker.png", std::fs::read(fixture("checker.png")).unwrap()),
+        ("nested/notes.txt", b"not a tile".to_vec()),
+        ("__MACOSX/._red.png", b"resource fork".to_vec()),
+    ];
+    for (name, bytes) in entries {
+        archive.start_file(name, SimpleFileOptions::default()).unwrap();
+        archive.write_all(&bytes).unwrap();
+    }
+    let archive = archive.finish().unwrap();
+
+    let tiles = vec![open_fixture("checker.png"), open_fixture("red.png")];
+    let zipped = TileLibrary::from_zip(archive, 3, FilterType::Triangle).unwrap();
+    assert_eq!(zipped.len(), 2);
+
+    let source = open_fixture("checker.png");
+    let render = |library| build_mosaic(&source, &MosaicConfig::new(3).tile_library(library).build().unwrap()).unwrap().to_rgba8();
+    assert_eq!(render(zipped).as_raw(), render(TileLibrary::new(tiles, 3, FilterType::Triangle).unwrap()).as_raw());
+}
+
+#[test]
+fn srgb_converts_to_the_reference_lab_values() {
+    let near = |lab: [f32; 3], expected: [f32; 3]| lab.iter().zip(expected).all(|(a, b)| (a - b).abs() < 0.05);
+
+    assert!(near(srgb_to_lab(Rgba([0, 0, 0, 255])), [0.0, 0.0, 0.0]));
+    assert!(near(srgb_to_lab(Rgba([255, 255, 255, 255])), [100.0, 0.0, 0.0]));
+    assert!(near(srgb_to_lab(Rgba([255, 0, 0, 255])), [53.24, 80.09, 67.20]));
+    assert!(near(srgb_to_lab(Rgba([0, 0, 255, 0])), [32.30, 79.19, -107.86]));
+}
+
+#[test]
+fn lab_matching_picks_the_tile_that_looks_closest() {
+    // in RGB the green tile is nearer to the gray, but it looks more off than the red one
+    let (red, green) = (Rgba([160, 128, 128, 255]), Rgba([128, 150, 128, 255]));
+    let tiles = [red, green].map(|color| DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, color)));
+    let library = TileLibrary::new(tiles, 2, FilterType::Triangle).unwrap();
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([128, 128, 128, 255])));
+
+    let pick = |space| {
+        let config = MosaicConfig::new(2).tile_library(library.clone()).match_space(space).build().unwrap();
+        build_mosaic(&source, &config).unwrap().get_pixel(0, 0)
+    };
+
+    assert_eq!(pick(MatchSpace::Rgb), green);
+    assert_eq!(pick(MatchSpace::Lab), red);
+}
+
+#[test]
+fn mosaic_bytes_decodes_builds_and_encodes_in_memory() {
+    let input = std::fs::read(fixture("red.png")).unwrap();
+    let output = mosaic_bytes(&input, &MosaicConfig::new(4).build().unwrap(), ImageFormat::Jpeg).unwrap();
+
+    let mosaic = mosaic_maker::image::load_from_memory_with_format(&output, ImageFormat::Jpeg).unwrap();
+    assert_eq!(mosaic.dimensions(), (12, 8));
+}
+
+#[test]
+fn crop_tiles_only_the_region() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::new(100, 100));
+    let config = MosaicConfig::new(4).crop("10,10,20,20".parse().unwrap()).build().unwrap();
+
+    assert_eq!(build_mosaic(&source, &config).unwrap().dimensions(), (20 * 4, 20 * 4));
+}
+
+#[test]
+fn flips_mirror_the_source_after_cropping() {
+    let source = open_fixture("checker.png");
+    let corner = |config: MosaicConfigBuilder| {
+        let mosaic = build_mosaic(&source, &config.mode(RenderMode::Pixelate).build().unwrap()).unwrap();
+        (mosaic.dimensions(), mosaic.get_pixel(0, 0))
+    };
+
+    let (red, green, blue, white) = (Rgba([255, 0, 0, 255]), Rgba([0, 255, 0, 255]), Rgba([0, 0, 255, 255]), Rgba([255, 255, 255, 255]));
+    assert_eq!(corner(MosaicConfig::new(2)), ((4, 4), red));
+    assert_eq!(corner(MosaicConfig::new(2).flip_horizontal(true)), ((4, 4), green));
+    assert_eq!(corner(MosaicConfig::new(2).flip_vertical(true)), ((4, 4), blue));
+    assert_eq!(corner(MosaicConfig::new(2).flip_horizontal(true).flip_vertical(true)), ((4, 4), white));
+
+    // the crop picks the bottom row as given, which is then mirrored
+    assert_eq!(corner(MosaicConfig::new(2).crop("0,1,2,1".parse().unwrap()).flip_horizontal(true)), ((4, 2), white));
+}
+
+#[test]
+fn quarter_turns_swap_the_sides_of_the_mosaic() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(20, 10, |x, _| if x == 0 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) }));
+    let config = MosaicConfig::new(3).mode(RenderMode::Pixelate).rotate(90).build().unwrap();
+    let mosaic = build_mosaic(&source, &config).unwrap();
+
+    assert_eq!(mosaic.dimensions(), (10 * 3, 20 * 3));
+    assert_eq!(mosaic_dimensions(source.dimensions(), &config).unwrap(), (10 * 3, 20 * 3));
+
+    // turned clockwise, the red left column becomes the top row
+    assert_eq!(mosaic.get_pixel(29, 0), Rgba([255, 0, 0, 255]));
+    assert_eq!(mosaic.get_pixel(29, 3), Rgba([0, 0, 255, 255]));
+}
+
+#[test]
+fn several_tile_images_blend_into_one_tile() {
+    let solid = |color| DynamicImage::ImageRgba8(RgbaImage::from_pixel(8, 8, color));
+    let config = MosaicConfig::new(4)
+        .alpha(0)
+        .tile_image(solid(Rgba([255, 0, 0, 255])))
+        .tile_image(solid(Rgba([0, 0, 255, 255])))
+        .build()
+        .unwrap();
+    let mosaic = build_mosaic(&open_fixture("checker.png"), &config).unwrap();
+
+    assert!(mosaic.pixels().all(|(_, _, pixel)| pixel == Rgba([128, 0, 128, 255])));
+}
+
+#[test]
+fn filter_names_are_parsed() {
+    assert_eq!(parse_filter("nearest").unwrap(), FilterType::Nearest);
+    assert_eq!(parse_filter("triangle").unwrap(), FilterType::Triangle);
+    assert_eq!(parse_filter("CatmullRom").unwrap(), FilterType::CatmullRom);
+    assert_eq!(parse_filter("lanczos3").unwrap(), FilterType::Lanczos3);
+    assert!(matches!(parse_filter("bicubic"), Err(MosaicError::InvalidValue { .. })));
+}
+
+#[test]
+fn pixelate_fills_cells_with_the_source_color() {
+    let source = open_fixture("checker.png");
+    let config = MosaicConfig::new(5).mode(RenderMode::Pixelate).build().unwrap();
+    let mosaic = build_mosaic(&source, &config).unwrap();
+
+    for (x, y, pixel) in source.pixels() {
+        assert_eq!(mosaic.get_pixel(x * 5 + 2, y * 5 + 2), pixel);
+    }
+}
+
+#[test]
+fn sixteen_bit_sources_keep_sixteen_bits() {
+    // 1000 and 1001 both round to 4 in 8 bits
+    let source = DynamicImage::ImageRgba16(ImageBuffer::from_fn(2, 1, |x, _| Rgba([1000 + x as u16, 0, 0, 65535])));
+    let config = MosaicConfig::new(2).alpha(255).build().unwrap();
+
+    let mosaic = build_mosaic(&source, &config).unwrap();
+    let mosaic = mosaic.as_rgba16().expect("a 16 bit mosaic");
+    assert_eq!(mosaic.get_pixel(0, 0).0[0], 1000);
+    assert_eq!(mosaic.get_pixel(2, 0).0[0], 1001);
+
+    let config = MosaicConfig::new(2).alpha(255).bit_depth(BitDepth::Eight).build().unwrap();
+    assert!(build_mosaic(&source, &config).unwrap().as_rgba8().is_some());
+}
+
+#[test]
+fn dithering_a_gradient_mixes_the_palette() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 8, |x, _| Rgba([x as u8 * 17, x as u8 * 17, x as u8 * 17, 255])));
+    let palette = Palette::new([Rgba([0, 0, 0, 255]), Rgba([255, 255, 255, 255])]).unwrap();
+
+    // every column of cells averages to one gray
+    let column_grays = |dither| {
+        let config = MosaicConfig::new(2).alpha(255).palette(palette.clone()).dither(dither).build().unwrap();
+        let mosaic = build_mosaic(&source, &config).unwrap().to_rgba8();
+        let mut sums: Vec<u32> = (0..16).map(|x| (0..8).map(|y| u32::from(mosaic.get_pixel(x * 2, y * 2).0[0])).sum()).collect();
+
+        assert!(mosaic.pixels().all(|pixel| pixel.0[0] == 0 || pixel.0[0] == 255));
+        sums.sort();
+        sums.dedup();
+        sums.len()
+    };
+
+    assert_eq!(column_grays(false), 2);
+    assert!(column_grays(true) > 2);
+}
+
+#[test]
+fn adaptive_palette_merges_the_nearest_colors() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 1, |x, _| Rgba([[0, 10, 240, 250][x as usize], 0, 0, 255])));
+    let config = MosaicConfig::new(2).alpha(255).adaptive_palette(2).build().unwrap();
+
+    let mosaic = build_mosaic(&source, &config).unwrap();
+    let reds: Vec<u8> = (0..4).map(|x| mosaic.get_pixel(x * 2, 0).0[0]).collect();
+
+    assert_eq!(reds, [5, 5, 245, 245]);
+}
+
+#[test]
+fn brightness_and_contrast_adjust_the_source() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(3, 1, |x, _| {
+        let gray = [0, 128, 255][x as usize];
+        Rgba([gray, gray, gray, 255])
+    }));
+    let reds = |config: MosaicConfig| {
+        let mosaic = build_mosaic(&source, &config).unwrap();
+        (0..3).map(|x| mosaic.get_pixel(x * 2, 0).0[0]).collect::<Vec<_>>()
+    };
+
+    assert_eq!(reds(MosaicConfig::new(2).alpha(255).brightness(20).build().unwrap()), [20, 148, 255]);
+    assert_eq!(reds(MosaicConfig::new(2).alpha(255).brightness(-255).build().unwrap()), [0, 0, 0]);
+    assert_eq!(reds(MosaicConfig::new(2).alpha(255).contrast(0.5).build().unwrap()), [64, 128, 191]);
+
+    // the repeated tile, the mid-gray center of the source, is adjusted too
+    assert_eq!(reds(MosaicConfig::new(2).alpha(0).brightness(20).build().unwrap()), [148, 148, 148]);
+}
+
+#[test]
+fn hex_layout_staggers_odd_rows() {
+    let source = open_fixture("checker.png");
+    let config = MosaicConfig::new(8).layout(Layout::Hex).mode(RenderMode::Pixelate).build().unwrap();
+
+    let mosaic = build_mosaic(&source, &config).unwrap();
+    assert_eq!(mosaic.dimensions(), (20, 14));
+
+    let color = |x, y| mosaic.get_pixel(x, y).0;
+    let (red, green, blue, white, none) = ([255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [255; 4], [0; 4]);
+
+    // across the middle of the first row, and of the second shifted by half a tile
+    assert_eq!([1, 9, 17].map(|x| color(x, 4)), [red, green, none]);
+    assert_eq!([1, 5, 13, 19].map(|x| color(x, 10)), [none, blue, white, white]);
+
+    // the rows interlock without holes, only the zigzag edges are empty
+    assert!((4..16).all(|x| (6..8).all(|y| color(x, y) != none)));
+}
+
+#[test]
+fn antialiased_circles_have_soft_edges() {
+    let source = open_fixture("red.png");
+    let circles = || MosaicConfig::new(8).mode(RenderMode::Pixelate).tile_shape(TileShape::Circle);
+    let alphas = |config| build_mosaic(&source, &config).unwrap().pixels().map(|(_, _, pixel)| pixel.0[3]).collect::<Vec<_>>();
+
+    // hard edges are all or nothing
+    assert!(alphas(circles().build().unwrap()).iter().all(|&alpha| alpha == 0 || alpha == 255));
+
+    let soft = build_mosaic(&source, &circles().antialias(4).build().unwrap()).unwrap();
+    let alpha = |x, y| soft.get_pixel(x, y).0[3];
+    assert_eq!((alpha(4, 4), alpha(0, 0)), (255, 0));
+
+    // the edge pixel between them is partly inside, and stays red
+    assert!(alpha(1, 1) > 0 && alpha(1, 1) < 255);
+    assert_eq!(soft.get_pixel(1, 1).0[..3], [255, 0, 0]);
+
+    assert!(matches!(circles().antialias(17).build(), Err(MosaicError::InvalidValue { .. })));
+}
+
+#[test]
+fn tile_opacity_fades_the_tile_independently_of_the_tint() {
+    let source = open_fixture("checker.png");
+    let mosaic = |alpha, tile_opacity| build_mosaic(&source, &MosaicConfig::new(2).alpha(alpha).tile_opacity(tile_opacity).build().unwrap()).unwrap();
+
+    // no tile at all under an opaque tint is pure pixelation
+    let pixelated = mosaic(255, 0);
+    for (x, y, pixel) in source.pixels() {
+        assert_eq!(pixelated.get_pixel(x * 2 + 1, y * 2 + 1), pixel);
+    }
+
+    // an untinted opaque tile is the raw repeated image, a faded one is translucent
+    assert_eq!(mosaic(0, 255).get_pixel(1, 1).0[3], 255);
+    assert_eq!(mosaic(0, 51).get_pixel(1, 1).0[3], 51);
+    assert_eq!(mosaic(0, 0).get_pixel(1, 1).0[3], 0);
+}
+
+#[test]
+fn output_size_picks_the_tile_size_and_resizes_to_it() {
+    let source = open_fixture("checker.png");
+
+    // 6px tiles fill 12x12 exactly, the same mosaic as asking for them
+    let fitted = build_mosaic(&source, &MosaicConfig::new(2).output_size(12, 12).build().unwrap()).unwrap();
+    assert_eq!(fitted.to_rgba8(), build_mosaic(&source, &MosaicConfig::new(6).build().unwrap()).unwrap().to_rgba8());
+
+    // anything else is rendered close by and resized
+    let config = MosaicConfig::new(2).output_size(10, 7).build().unwrap();
+    assert_eq!(mosaic_dimensions(source.dimensions(), &config).unwrap(), (10, 7));
+    assert_eq!(build_mosaic(&source, &config).unwrap().dimensions(), (10, 7));
+}
+
+#[test]
+fn fit_letterboxes_or_crops_to_the_output_size() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 2, Rgba([255, 0, 0, 255])));
+    let fitted = |fit| build_mosaic(&source, &MosaicConfig::new(2).output_size(40, 40).fit(fit).build().unwrap()).unwrap();
+
+    // the 40x20 mosaic centered between two transparent bars of 10 rows
+    let contained = fitted(Fit::Contain);
+    assert_eq!(contained.dimensions(), (40, 40));
+    for (_, y, pixel) in contained.pixels() {
+        assert_eq!(pixel.0[3], if (10..30).contains(&y) { 255 } else { 0 }, "row {}", y);
+    }
+
+    let covered = fitted(Fit::Cover);
+    assert_eq!(covered.dimensions(), (40, 40));
+    assert!(covered.pixels().all(|(_, _, pixel)| pixel.0[3] == 255));
+
+    assert!(matches!("fill".parse::<Fit>(), Err(MosaicError::InvalidValue { kind: "fit", .. })));
+}
+
+#[test]
+fn checker_pattern_alternates_its_colors() {
+    let (white, black) = (Rgba([255, 255, 255, 255]), Rgba([0, 0, 0, 255]));
+    let config = MosaicConfig::new(8).alpha(0).pattern(TilePattern::Checker).checker_size(2).build().unwrap();
+    let mosaic = build_mosaic(&open_fixture("red.png"), &config).unwrap();
+
+    // 2px squares starting over in every tile, white in the corner
+    for (x, y, pixel) in mosaic.pixels() {
+        let square = (x % 8) / 2 + (y % 8) / 2;
+        assert_eq!(pixel, if square.is_multiple_of(2) { white } else { black });
+    }
+
+    let solid = MosaicConfig::new(4).alpha(0).pattern(TilePattern::Solid).pattern_color(black).build().unwrap();
+    assert!(build_mosaic(&open_fixture("red.png"), &solid).unwrap().pixels().all(|(_, _, pixel)| pixel == black));
+}
+
+#[test]
+fn repeat_tile_scale_tiles_the_pattern_within_every_cell() {
+    let checker = open_fixture("checker.png");
+    let config = MosaicConfig::new(8).alpha(0).tile_image(checker.clone()).repeat_tile_scale(4).build().unwrap();
+    let mosaic = build_mosaic(&open_fixture("red.png"), &config).unwrap();
+
+    // 4×4 copies of the 2px checker in every cell, one red pixel each
+    for (x, y, pixel) in mosaic.pixels() {
+        assert_eq!(pixel, checker.get_pixel(x % 2, y % 2));
+    }
+    let cell = mosaic.view(0, 0, 8, 8);
+    assert_eq!(cell.pixels().filter(|&(_, _, pixel)| pixel == Rgba([255, 0, 0, 255])).count(), 16);
+
+    // a tile size the copies do not divide cuts off the last ones, leaving no gap
+    let config = MosaicConfig::new(7).alpha(0).pattern(TilePattern::Solid).repeat_tile_scale(3).build().unwrap();
+    assert!(build_mosaic(&open_fixture("red.png"), &config).unwrap().pixels().all(|(_, _, pixel)| pixel == Rgba([255, 255, 255, 255])));
+
+    assert!(matches!(MosaicConfig::new(4).repeat_tile_scale(5).build(), Err(MosaicError::InvalidValue { kind: "tile repeat", .. })));
+}
+
+#[test]
+fn gray_methods_give_distinct_grays_for_saturated_red() {
+    let gray = |method| {
+        let config = MosaicConfig::new(2).mode(RenderMode::Pixelate).grayscale(true).gray_method(method).build().unwrap();
+        build_mosaic(&open_fixture("red.png"), &config).unwrap().get_pixel(0, 0)
+    };
+
+    assert_eq!(gray(GrayMethod::Rec601), Rgba([76, 76, 76, 255]));
+    assert_eq!(gray(GrayMethod::Rec709), Rgba([54, 54, 54, 255]));
+    assert_eq!(gray(GrayMethod::Average), Rgba([85, 85, 85, 255]));
+    assert_eq!(gray(GrayMethod::Lightness), Rgba([128, 128, 128, 255]));
+}
+
+#[test]
+fn edges_darken_the_tiles_along_a_hard_edge() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(6, 3, |x, _| if x < 3 { Rgba([255, 255, 255, 255]) } else { Rgba([128, 128, 128, 255]) }));
+    let config = MosaicConfig::new(2).mode(RenderMode::Pixelate).edges(1.0).build().unwrap();
+    let mosaic = build_mosaic(&source, &config).unwrap();
+    let brightness = |column: u32| mosaic.get_pixel(column * 2, 2).0[0];
+
+    // the two columns either side of the edge, flat further away
+    assert_eq!(brightness(0), 255);
+    assert_eq!(brightness(5), 128);
+    assert!(brightness(2) < brightness(1));
+    assert!(brightness(3) < brightness(4));
+}
+
+#[test]
+fn auto_tile_size_aims_the_longest_side_at_the_target() {
+    let config = MosaicConfig::new(2).auto_tile_size(DEFAULT_AUTO_SIDE).build().unwrap();
+
+    // 4000 pixels over 100 columns
+    assert_eq!(mosaic_tile_size((100, 50), &config).unwrap(), 40);
+    assert_eq!(mosaic_dimensions((100, 50), &config).unwrap(), (4000, 2000));
+
+    // never below 2 pixels, nor beyond the maximum side
+    assert_eq!(mosaic_tile_size((5000, 10), &config).unwrap(), 2);
+    let config = MosaicConfig::new(2).auto_tile_size(u32::MAX).build().unwrap();
+    let tile_size = mosaic_tile_size((100, 50), &config).unwrap();
+    assert!(tile_size <= DEFAULT_MAX_SIDE / 100 && mosaic_dimensions((100, 50), &config).is_ok(), "{}", tile_size);
+}
+
+#[test]
+fn preserved_tile_aspect_makes_cells_as_wide_as_the_tile() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 2, |x, _| if x == 0 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) }));
+    let banner = DynamicImage::ImageRgba8(RgbaImage::from_pixel(4, 2, Rgba([0, 0, 0, 255])));
+    let config = MosaicConfig::new(8).alpha(255).tile_image(banner).preserve_tile_aspect(true).build().unwrap();
+
+    // 8 × 4 cells, the tint filling them all the way
+    let mosaic = build_mosaic(&source, &config).unwrap();
+    assert_eq!(mosaic.dimensions(), (16, 8));
+    assert_eq!(mosaic_dimensions((2, 2), &config).unwrap(), (16, 8));
+    assert_eq!(mosaic.get_pixel(7, 3), Rgba([255, 0, 0, 255]));
+    assert_eq!(mosaic.get_pixel(8, 4), Rgba([0, 0, 255, 255]));
+
+    let hex = MosaicConfig::new(8).preserve_tile_aspect(true).layout(Layout::Hex).build();
+    assert!(matches!(hex, Err(MosaicError::UnsupportedWithTileAspect(_))));
+}
+
+#[test]
+fn transparent_cells_are_skipped_below_the_threshold() {
+    // the top left quadrant is almost transparent
+    let sprite = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 4, |x, y| {
+        let alpha = if x < 2 && y < 2 { 40 } else { 255 };
+        Rgba([255, 0, 0, alpha])
+    }));
+    let tiled = || MosaicConfig::new(2).pattern(TilePattern::Solid);
+    let mosaic = build_mosaic(&sprite, &tiled().skip_transparent(DEFAULT_ALPHA_THRESHOLD).build().unwrap()).unwrap();
+
+    assert!((0..4).all(|y| (0..4).all(|x| mosaic.get_pixel(x, y).0[3] == 0)));
+    assert!((4..8).all(|y| (0..8).all(|x| mosaic.get_pixel(x, y).0[3] == 255)));
+    assert_eq!(mosaic.get_pixel(4, 0).0[3], 255);
+
+    // tinted like the rest without skipping
+    let mosaic = build_mosaic(&sprite, &tiled().build().unwrap()).unwrap();
+    assert_eq!(mosaic.get_pixel(0, 0).0[3], 255);
+}
+
+#[test]
+fn auto_levels_stretch_a_dull_source_to_the_full_range() {
+    let dull = DynamicImage::ImageRgba8(RgbaImage::from_fn(10, 1, |x, _| {
+        let level = 100 + x as u8 * 4;
+        Rgba([level, level, level, 255])
+    }));
+    let levels = |config: MosaicConfigBuilder| {
+        let mosaic = build_mosaic(&dull, &config.mode(RenderMode::Pixelate).build().unwrap()).unwrap();
+        (0..10).map(|x| mosaic.get_pixel(x * 2, 0).0[0]).collect::<Vec<_>>()
+    };
+
+    let plain = levels(MosaicConfig::new(2));
+    assert_eq!((plain[0], plain[9]), (100, 136));
+
+    let stretched = levels(MosaicConfig::new(2).auto_levels(0.0));
+    assert_eq!((stretched[0], stretched[9]), (0, 255));
+    assert!(stretched.windows(2).all(|pair| pair[0] < pair[1]));
+
+    // the darkest and the lightest tenth saturate
+    let clipped = levels(MosaicConfig::new(2).auto_levels(10.0));
+    assert_eq!((clipped[0], clipped[1], clipped[8], clipped[9]), (0, 0, 255, 255));
+}
+
+#[test]
+fn vignette_fades_the_tint_toward_the_middle() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_pixel(3, 3, Rgba([255, 0, 0, 255])));
+    let black = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 255])));
+    let red = |strength: f32, x: u32, y: u32| {
+        let config = MosaicConfig::new(2).alpha(200).tile_image(black.clone()).vignette(strength).build().unwrap();
+        build_mosaic(&source, &config).unwrap().get_pixel(x * 2, y * 2).0[0]
+    };
+
+    // the tint over black is as red as it is opaque
+    assert_eq!((red(0.5, 1, 1), red(0.5, 0, 0)), (100, 200));
+    assert_eq!((red(-0.5, 1, 1), red(-0.5, 2, 0)), (200, 100));
+    assert_eq!((red(0.0, 1, 1), red(0.0, 0, 2)), (200, 200));
+}
+
+#[test]
+fn adaptive_tiles_split_where_the_source_is_detailed() {
+    // flat gray on the left, noise on the right
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(16, 8, |x, y| match x {
+        0..=7 => Rgba([128, 128, 128, 255]),
+        _ => {
+            let noise = ((x * 7 + y * 13) % 5 * 60) as u8;
+            Rgba([noise, noise, noise, 255])
+        },
+    }));
+    let config = MosaicConfig::new(2).mode(RenderMode::Pixelate).adaptive(2, 16).build().unwrap();
+    let mosaic = build_mosaic(&source, &config).unwrap();
+    let colors = |x0: u32| {
+        let mut colors: Vec<_> = mosaic.view(x0, 0, 16, 16).pixels().map(|(_, _, pixel)| pixel).collect();
+        colors.sort_by_key(|pixel| pixel.0);
+        colors.dedup();
+        colors.len()
+    };
+
+    // one 16px block for the flat half, 2px blocks down to every source pixel for the noise
+    assert_eq!(mosaic.dimensions(), (32, 16));
+    assert_eq!(colors(0), 1);
+    assert_eq!(colors(16), 5);
+    assert_eq!(mosaic.get_pixel(16, 0), source.get_pixel(8, 0));
+
+    assert!(matches!(MosaicConfig::new(2).adaptive(2, 16).gap(1).build(), Err(MosaicError::UnsupportedWithAdaptive("gaps between tiles"))));
+}
+
+#[test]
+fn progress_is_reported_once_per_row_in_order() {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&calls);
+    let config = MosaicConfig::new(4).on_progress(move |done, total| recorded.lock().unwrap().push((done, total))).build().unwrap();
+
+    build_mosaic(&open_fixture("red.png"), &config).unwrap();
+    assert_eq!(*calls.lock().unwrap(), [(0, 2), (1, 2), (2, 2)]);
+}
+
+#[test]
+fn cancelling_stops_the_render_midway() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 512, Rgba([0, 128, 255, 255])));
+    let cancel = Arc::new(AtomicBool::new(false));
+    let rows = Arc::new(AtomicU32::new(0));
+
+    // cancelled from the progress of the first row, as a server would from another thread
+    let (flag, counter) = (Arc::clone(&cancel), Arc::clone(&rows));
+    let config = MosaicConfig::new(4)
+        .threads(2)
+        .on_progress(move |done, _total| {
+            counter.store(done, Ordering::Relaxed);
+            if done == 1 {
+                flag.store(true, Ordering::Relaxed);
+            }
+        })
+        .build()
+        .unwrap();
+
+    assert!(matches!(build_mosaic_cancellable(&source, &config, &cancel), Err(MosaicError::Cancelled)));
+    assert!(rows.load(Ordering::Relaxed) < 512 / 2, "{} of 512 rows rendered", rows.load(Ordering::Relaxed));
+
+    // never set, the same mosaic as without the flag
+    let config = MosaicConfig::new(4).build().unwrap();
+    assert_eq!(build_mosaic_cancellable(&source, &config, &AtomicBool::new(false)).unwrap(), build_mosaic(&source, &config).unwrap());
+}
+
+#[test]
+fn single_pixel_source_makes_a_single_tile() {
+    let source = DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba([0, 128, 255, 255])));
+    let mosaic = build_mosaic(&source, &MosaicConfig::new(5).build().unwrap()).unwrap();
+
+    // the tile is the pixel itself, tinted with itself
+    assert_eq!(mosaic.dimensions(), (5, 5));
+    assert!(mosaic.pixels().all(|(_, _, pixel)| pixel == Rgba([0, 128, 255, 255])));
+}
+
+#[test]
+fn border_frames_every_tile_inside_the_gap() {
+    let magenta = Rgba([255, 0, 255, 255]);
+    let config = MosaicConfig::new(6).alpha(255).gap(1).border(1).border_color(magenta).build().unwrap();
+    let mosaic = build_mosaic(&open_fixture("checker.png"), &config).unwrap();
+
+    // gap, border, tile, border and gap again across the first tile
+    assert_eq!(mosaic.get_pixel(0, 3), Rgba([0, 0, 0, 255]));
+    assert_eq!([1, 6].map(|x| mosaic.get_pixel(x, 3)), [magenta; 2]);
+    assert_eq!([2, 5].map(|x| mosaic.get_pixel(x, 3)), [Rgba([255, 0, 0, 255]); 2]);
+    assert_eq!(mosaic.get_pixel(7, 3), Rgba([0, 0, 0, 255]));
+
+    // and down the last one
+    assert_eq!([8, 13].map(|y| mosaic.get_pixel(10, y)), [magenta; 2]);
+    assert_eq!(mosaic.get_pixel(10, 10), Rgba([255, 255, 255, 255]));
+}
+
+#[test]
+fn tint_from_tile_pulls_library_tiles_toward_the_cell_color() {
+    let source = open_fixture("red.png");
+    let tile = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([0, 0, 200, 255])));
+    let library = TileLibrary::new([tile], 2, FilterType::Triangle).unwrap();
+    let corner = |strength| {
+        let config = MosaicConfig::new(2).tile_library(library.clone()).tint_from_tile(strength).build().unwrap();
+        build_mosaic(&source, &config).unwrap().get_pixel(0, 0)
+    };
+
+    // untouched, halfway and all the way to the red of the cell
+    assert_eq!(corner(0.0), Rgba([0, 0, 200, 255]));
+    assert_eq!(corner(0.5), Rgba([128, 0, 100, 255]));
+    assert_eq!(corner(1.0), Rgba([255, 0, 0, 255]));
+}
+
+#[test]
+fn self_tile_builds_the_picture_from_miniatures_of_itself() {
+    // quadrants of four colors, which a 4px tile shows pixel for pixel
+    let colors = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [255, 255, 255, 255]];
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 4, |x, y| Rgba(colors[(y / 2 * 2 + x / 2) as usize])));
+    let mosaic = |strength| {
+        let config = MosaicConfig::new(4).self_tile(true).alpha(0).tint_from_tile(strength).build().unwrap();
+        build_mosaic(&source, &config).unwrap().to_rgba8()
+    };
+
+    let copies = mosaic(0.0);
+    for (x, y, pixel) in copies.enumerate_pixels() {
+        assert_eq!(*pixel, source.get_pixel(x % 4, y % 4), "({}, {})", x, y);
+    }
+
+    // the white corner of the red cell pulled halfway to red, the red one unchanged
+    let pulled = mosaic(0.5);
+    assert_eq!(*pulled.get_pixel(3, 3), Rgba([255, 128, 128, 255]));
+    assert_eq!(*pulled.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+
+    let config = |config: MosaicConfigBuilder| config.self_tile(true).build();
+    assert!(matches!(config(MosaicConfig::new(4).pattern(TilePattern::Solid)), Err(MosaicError::UnsupportedWithSelfTile("tile patterns"))));
+    assert!(matches!(config(MosaicConfig::new(4).mode(RenderMode::Pixelate)), Err(MosaicError::UnsupportedWithSelfTile("pixelation"))));
+    assert!(matches!(config(MosaicConfig::new(4).tile_image(source.clone())), Err(MosaicError::UnsupportedWithSelfTile("a tile image"))));
+}
+
+#[test]
+fn output_bytes_are_estimated_without_rendering() {
+    let estimate = |config: MosaicConfigBuilder| estimate_output_bytes((30, 20), &config.build().unwrap());
+
+    assert_eq!(estimate(MosaicConfig::new(4)), 120 * 80 * 4);
+    assert_eq!(estimate(MosaicConfig::new(4).bit_depth(BitDepth::Sixteen)), 120 * 80 * 8);
+    assert_eq!(estimate(MosaicConfig::new(4).grid(Grid::Exact(10, 5)).gap(1)), 51 * 26 * 4);
+    assert_eq!(estimate(MosaicConfig::new(8).layout(Layout::Hex)), (30 * 8 + 4) * (19 * 6 + 8) * 4);
+    assert_eq!(estimate(MosaicConfig::new(4).crop("0,0,10,10".parse().unwrap()).rotate(90)), 40 * 40 * 4);
+    assert_eq!(estimate(MosaicConfig::new(4).output_size(1920, 1080)), 1920 * 1080 * 4);
+
+    // the estimates agree with what is built
+    let source = open_fixture("checker.png");
+    for config in [MosaicConfig::new(3).gap(2), MosaicConfig::new(8).layout(Layout::Hex), MosaicConfig::new(5).grid(Grid::Cells(1))] {
+        let config = config.build().unwrap();
+        let mosaic = build_mosaic(&source, &config).unwrap();
+        assert_eq!(estimate_output_bytes(source.dimensions(), &config), u64::from(mosaic.width() * mosaic.height() * 4));
+    }
+
+    // over the limits still estimated, refused sources not
+    assert_eq!(estimate(MosaicConfig::new(4).max_side(100)), 120 * 80 * 4);
+    assert_eq!(estimate_output_bytes((0, 20), &MosaicConfig::new(4).build().unwrap()), 0);
+}
+
+#[test]
+fn color_grid_has_a_pixel_per_cell() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(40, 20, |x, _| if x < 20 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) }));
+    let configs = [
+        MosaicConfig::new(4),
+        MosaicConfig::new(4).grid(Grid::Exact(10, 5)),
+        MosaicConfig::new(4).grid(Grid::Cells(8)).sample(SampleMode::Average),
+        MosaicConfig::new(4).crop("0,10,20,10".parse().unwrap()).grid(Grid::Exact(4, 2)).rotate(90),
+    ];
+
+    for config in configs {
+        let config = config.build().unwrap();
+        let grid = color_grid(&source, &config).unwrap();
+        assert_eq!((grid.width() * 4, grid.height() * 4), mosaic_dimensions(source.dimensions(), &config).unwrap());
+    }
+
+    // averaged cells of one color each keep it exactly
+    let halves = MosaicConfig::new(4).grid(Grid::Exact(2, 1)).sample(SampleMode::Average).build().unwrap();
+    let grid = color_grid(&source, &halves).unwrap();
+    assert_eq!(grid.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+    assert_eq!(grid.get_pixel(1, 0), Rgba([0, 0, 255, 255]));
+}
+
+#[test]
+fn color_ramps_run_from_the_darkest_to_the_brightest_cell() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(3, 1, |x, _| {
+        let gray = [0, 128, 255][x as usize];
+        Rgba([gray, gray, gray, 255])
+    }));
+    let recolored = |ramp: ColorRamp| {
+        let mosaic = build_mosaic(&source, &MosaicConfig::new(2).mode(RenderMode::Pixelate).color_ramp(ramp).build().unwrap()).unwrap();
+        (0..3).map(|x| mosaic.get_pixel(x * 2, 0)).collect::<Vec<_>>()
+    };
+
+    for ramp in [ColorRamp::viridis(), ColorRamp::sepia(), "#102030, #FF0000, #F0E0D0".parse().unwrap()] {
+        let colors = ramp.colors();
+        let cells = recolored(ramp);
+
+        assert_eq!((cells[0], cells[2]), (colors[0], colors[colors.len() - 1]));
+    }
+
+    // mid-gray lands halfway along
+    assert_eq!(recolored(ColorRamp::grayscale())[1], Rgba([128, 128, 128, 255]));
+
+    assert!("plasma".parse::<ColorRamp>().is_err());
+    assert!("#FF0000".parse::<ColorRamp>().is_err());
+}
+
+#[test]
+fn alpha_runs_from_the_bare_tile_to_a_solid_color_grid() {
+    // red and blue cells, each unlike the tile made of both
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 2, |x, y| {
+        if (x + y) % 2 == 0 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) }
+    }));
+    let mosaic = |alpha| build_mosaic(&source, &MosaicConfig::new(4).alpha(alpha).build().unwrap()).unwrap().to_rgba8();
+
+    // at 0 every cell is the same untinted tile
+    let bare = mosaic(0);
+    assert!((0..8).all(|x| (0..8).all(|y| bare.get_pixel(x, y) == bare.get_pixel(x % 4, y % 4))));
+
+    // at 255 every cell is the solid color of its source pixel
+    let solid = mosaic(255);
+    assert!((0..8).all(|x| (0..8).all(|y| *solid.get_pixel(x, y) == source.get_pixel(x / 4, y / 4))));
+}
+
+#[test]
+fn tiles_of_non_square_sources_fill_the_whole_cell() {
+    for (width, height) in [(20, 13), (10, 25)] {
+        let source = DynamicImage::ImageRgba8(RgbaImage::from_pixel(width, height, Rgba([0, 128, 0, 255])));
+        assert_eq!(square_thumbnail(&source, 5).dimensions(), (5, 5));
+
+        // the bare tile covers every one of the tile_size x tile_size pixels of a cell
+        let mosaic = build_mosaic(&source, &MosaicConfig::new(5).alpha(0).build().unwrap()).unwrap();
+        let cell = mosaic.view(0, 0, 5, 5);
+        assert_eq!(cell.pixels().filter(|(_, _, pixel)| pixel.0[3] == 255).count(), 5 * 5);
+        assert_eq!(mosaic.pixels().count() as u32, width * height * 5 * 5);
+    }
+}
+
+#[test]
+fn grayscale_turns_pure_red_into_its_luminance() {
+    // Rec. 601 weighs red at 0.299
+    let red = Rgba([255, 0, 0, 255]);
+    assert_eq!(luminance(red), 76);
+    assert_eq!(grayscale(red), Rgba([76, 76, 76, 255]));
+
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, red));
+    let mosaic = build_mosaic(&source, &MosaicConfig::new(2).alpha(255).grayscale(true).build().unwrap()).unwrap();
+    assert_eq!(mosaic.get_pixel(1, 1), Rgba([76, 76, 76, 255]));
+}
+
+#[test]
+fn invert_turns_the_color_tile_negative() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([10, 20, 30, 255])));
+
+    // at full alpha the color tile hides the repeated tile
+    let mosaic = build_mosaic(&source, &MosaicConfig::new(2).alpha(255).invert(true).build().unwrap()).unwrap();
+    assert_eq!(mosaic.get_pixel(1, 1), Rgba([245, 235, 225, 255]));
+
+    // alpha is left as it is
+    assert_eq!(invert(Rgba([10, 20, 30, 40])), Rgba([245, 235, 225, 40]));
+}
+
+#[test]
+fn gaps_between_tiles_are_filled_with_the_gap_color() {
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(2, 1, |x, _| {
+        if x == 0 { Rgba([255, 0, 0, 255]) } else { Rgba([0, 0, 255, 255]) }
+    }));
+    let magenta = Rgba([255, 0, 255, 255]);
+    let config = MosaicConfig::new(4).alpha(255).gap(2).gap_color(magenta).build().unwrap();
+    let mosaic = build_mosaic(&source, &config).unwrap();
+
+    // 2 * (4 + 2) + 2 by 1 * (4 + 2) + 2, every tile starting after a gap
+    assert_eq!(mosaic.dimensions(), (14, 8));
+    for (x, y, pixel) in mosaic.pixels() {
+        let expected = match (x, y) {
+            (2..=5, 2..=5) => Rgba([255, 0, 0, 255]),
+            (8..=11, 2..=5) => Rgba([0, 0, 255, 255]),
+            _ => magenta,
+        };
+        assert_eq!(pixel, expected, "pixel {},{}", x, y);
+    }
+}
+
+#[test]
+fn circle_tiles_mask_out_their_corners() {
+    let source = open_fixture("red.png");
+    let mosaic = build_mosaic(&source, &MosaicConfig::new(8).tile_shape(TileShape::Circle).build().unwrap()).unwrap();
+
+    // both the repeated tile and the color tile are cut to the circle
+    for (x, y) in [(0, 0), (7, 0), (0, 7), (7, 7)] {
+        assert_eq!(mosaic.get_pixel(8 + x, y), Rgba([0, 0, 0, 0]), "corner {},{}", x, y);
+    }
+    assert_eq!(mosaic.get_pixel(12, 4).0[3], 255);
+}
+
+#[test]
+fn srgb_round_trips_through_linear_light() {
+    for gamma in [DEFAULT_GAMMA, 1.0, 2.4] {
+        for channel in 0..=255 {
+            let linear = srgb_to_linear(channel, gamma);
+            assert!((0.0..=1.0).contains(&linear));
+
+            // back to the same channel, well within rounding
+            let back = linear.powf(1.0 / gamma) * 255.0;
+            assert!((back - f32::from(channel)).abs() < 1e-3, "{} at gamma {}", channel, gamma);
+            assert_eq!(linear_to_srgb(linear, gamma), channel);
+        }
+    }
+}
+
+#[test]
+fn respected_alpha_keeps_transparent_corners_free_of_color() {
+    // blue, with a fully transparent magenta top left quadrant
+    let source = open_fixture("transparent_corner.png");
+
+    for mode in [RenderMode::Tiled, RenderMode::Pixelate] {
+        let config = MosaicConfig::new(4).mode(mode).alpha(255).respect_alpha(true).build().unwrap();
+        let mosaic = build_mosaic(&source, &config).unwrap();
+
+        assert!((0..8).all(|y| (0..8).all(|x| mosaic.get_pixel(x, y) == Rgba([0, 0, 0, 0]))), "{:?}", mode);
+        assert_eq!(mosaic.get_pixel(10, 10), Rgba([0, 0, 255, 255]), "{:?}", mode);
+    }
+}
+
+#[test]
+fn averaged_cells_match_the_mean_of_a_gradient() {
+    // red climbs 32 per column, green 64 per row
+    let source = DynamicImage::ImageRgba8(RgbaImage::from_fn(8, 4, |x, y| Rgba([x as u8 * 32, y as u8 * 64, 100, 255])));
+    let mosaic = |config: MosaicConfigBuilder| {
+        build_mosaic(&source, &config.grid(Grid::Exact(2, 2)).alpha(255).build().unwrap()).unwrap()
+    };
+    let averaged = mosaic(MosaicConfig::new(4).sample(SampleMode::Average));
+
+    // the mean of the 4x2 block of a cell is the gradient at its middle, 1.5 columns and 0.5 rows in
+    for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+        let mean = [32.0 * (4.0 * x as f32 + 1.5), 64.0 * (2.0 * y as f32 + 0.5)];
+        assert_eq!(averaged.get_pixel(x * 4, y * 4), Rgba([mean[0] as u8, mean[1] as u8, 100, 255]), "cell {},{}", x, y);
+    }
+
+    // a point sample takes a single pixel of the block instead
+    let point = mosaic(MosaicConfig::new(4));
+    assert_ne!(point.get_pixel(0, 0), averaged.get_pixel(0, 0));
+}
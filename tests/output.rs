@@ -0,0 +1,264 @@
+mod common;
+
+use std::io::Cursor;
+use common::{fixture, open_fixture, temp_path};
+use mosaic_maker::{build_mosaic, compare_images, default_output_name, encode_mosaic, icc_profile, image, is_gray, parse_format, save_mosaic, sharpen, shift_colors, DynamicImage, FilterType, GenericImageView, ImageBuffer, ImageFormat, MosaicConfig, MosaicError, Rgba, RgbaImage, TileLibrary, Difference, EncodeOptions};
+
+// the mosaic of the 3x2 red fixture with 4px tiles, written to `name` and decoded again
+fn round_trip(name: &str) -> DynamicImage {
+    let mosaic = build_mosaic(&open_fixture("red.png"), &MosaicConfig::new(4).build().unwrap()).unwrap();
+    let path = temp_path(name);
+
+    save_mosaic(&mosaic, &path, &EncodeOptions::default()).unwrap();
+    let written = image::open(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    written
+}
+
+#[test]
+fn tiff_output_round_trips() {
+    for name in ["mosaic.tiff", "mosaic.tif"] {
+        let written = round_trip(name);
+
+        assert_eq!(written.dimensions(), (12, 8));
+        assert!(written.color().has_alpha());
+    }
+}
+
+#[test]
+fn bmp_output_round_trips_flattened() {
+    let written = round_trip("mosaic.bmp");
+
+    assert_eq!(written.dimensions(), (12, 8));
+    assert!(!written.color().has_alpha());
+}
+
+#[test]
+fn bmp_output_is_flattened_onto_the_background() {
+    let transparent = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([0, 0, 0, 0])));
+    let bytes = encode_mosaic(&transparent, ImageFormat::Bmp, &EncodeOptions { background: Rgba([0, 0, 255, 255]), ..EncodeOptions::default() }).unwrap();
+
+    let decoded = image::load_from_memory_with_format(&bytes, ImageFormat::Bmp).unwrap();
+    assert_eq!(decoded.get_pixel(1, 1), Rgba([0, 0, 255, 255]));
+}
+
+#[test]
+fn jpeg_wider_than_65535_pixels_is_rejected() {
+    let wide = DynamicImage::ImageRgba8(RgbaImage::new(70_000, 1));
+
+    assert!(matches!(
+        encode_mosaic(&wide, ImageFormat::Jpeg, &EncodeOptions::default()),
+        Err(MosaicError::TooLargeForFormat { width: 70_000, height: 1, .. })
+    ));
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn webp_output_round_trips() {
+    let written = round_trip("mosaic.webp");
+
+    assert_eq!(written.dimensions(), (12, 8));
+}
+
+#[cfg(feature = "webp")]
+#[test]
+fn lossless_webp_output_is_vp8l() {
+    let mosaic = build_mosaic(&open_fixture("red.png"), &MosaicConfig::new(4).build().unwrap()).unwrap();
+    let bytes = encode_mosaic(&mosaic, ImageFormat::WebP, &EncodeOptions { lossless: true, ..EncodeOptions::default() }).unwrap();
+
+    assert_eq!(&bytes[8..16], b"WEBPVP8L");
+}
+
+#[test]
+fn format_names_are_parsed() {
+    assert_eq!(parse_format("png").unwrap(), ImageFormat::Png);
+    assert_eq!(parse_format("JPG").unwrap(), ImageFormat::Jpeg);
+    assert_eq!(parse_format("tif").unwrap(), ImageFormat::Tiff);
+    assert!(matches!(parse_format("gif"), Err(MosaicError::InvalidValue { .. })));
+}
+
+#[test]
+fn sixteen_bit_png_output_round_trips() {
+    let source = DynamicImage::ImageRgba16(ImageBuffer::from_pixel(2, 2, Rgba([1000, 20000, 40000, 65535])));
+    let mosaic = build_mosaic(&source, &MosaicConfig::new(3).alpha(255).build().unwrap()).unwrap();
+
+    let bytes = encode_mosaic(&mosaic, ImageFormat::Png, &EncodeOptions::default()).unwrap();
+    let written = image::load_from_memory(&bytes).unwrap();
+
+    assert_eq!(written.as_rgba16().unwrap().get_pixel(4, 4), &Rgba([1000, 20000, 40000, 65535]));
+}
+
+#[test]
+fn gray_png_output_decodes_as_luma() {
+    let mosaic = build_mosaic(&open_fixture("checker.png"), &MosaicConfig::new(4).grayscale(true).grayscale_tile(true).build().unwrap()).unwrap();
+    assert!(is_gray(&mosaic));
+
+    let bytes = encode_mosaic(&mosaic, ImageFormat::Png, &EncodeOptions { gray: true, ..EncodeOptions::default() }).unwrap();
+    let written = image::load_from_memory(&bytes).unwrap();
+
+    assert_eq!(written.color(), image::ColorType::L8);
+    assert_eq!(written.to_rgba8(), mosaic.to_rgba8());
+}
+
+#[test]
+fn same_seed_writes_identical_files() {
+    let tile = DynamicImage::ImageRgba8(RgbaImage::from_fn(4, 4, |x, y| Rgba([x as u8 * 60, y as u8 * 60, 0, 255])));
+    let library = TileLibrary::new([tile], 4, FilterType::Triangle).unwrap();
+    let source = open_fixture("checker.png");
+
+    // a different thread count renders the cells in a different order
+    let write = |name: &str, threads: usize| {
+        let config = MosaicConfig::new(4).tile_library(library.clone()).rotate_tiles(true).seed(42).threads(threads).build().unwrap();
+        let path = temp_path(name);
+
+        save_mosaic(&build_mosaic(&source, &config).unwrap(), &path, &EncodeOptions::default()).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        bytes
+    };
+
+    assert_eq!(write("seeded-1.png", 1), write("seeded-4.png", 4));
+}
+
+#[test]
+fn sharpening_keeps_flat_areas_and_steepens_edges() {
+    let flat = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(8, 8, image::Rgb([100, 150, 200])));
+    let sharpened = sharpen(&flat, 1.5).to_rgb8();
+    assert!(sharpened.pixels().all(|pixel| pixel.0.iter().zip([100, 150, 200]).all(|(&a, b)| a.abs_diff(b) <= 1)));
+
+    // a step from 64 to 192 halfway across overshoots on both sides
+    let step = DynamicImage::ImageRgb8(ImageBuffer::from_fn(8, 1, |x, _| image::Rgb([if x < 4 { 64 } else { 192 }; 3])));
+    let sharpened = sharpen(&step, 1.0).to_rgb8();
+    assert!(sharpened.get_pixel(3, 0).0[0] < 64);
+    assert!(sharpened.get_pixel(4, 0).0[0] > 192);
+    assert_eq!(sharpened.get_pixel(0, 0).0[0], 64);
+}
+
+#[test]
+fn unshifted_colors_round_trip_through_hsv() {
+    let img = DynamicImage::ImageRgba8(RgbaImage::from_fn(64, 64, |x, y| Rgba([(x * 4) as u8, (y * 4) as u8, ((x + y) * 2) as u8, 200])));
+    let shifted = shift_colors(&img, 0.0, 1.0);
+
+    for ((_, _, a), (_, _, b)) in img.pixels().zip(shifted.pixels()) {
+        assert_eq!(a.0[3], b.0[3]);
+        assert!(a.0.iter().zip(&b.0).all(|(&a, &b)| a.abs_diff(b) <= 1), "{:?} became {:?}", a, b);
+    }
+
+    // a third of a turn takes red to green
+    let red = DynamicImage::ImageRgba8(RgbaImage::from_pixel(1, 1, Rgba([255, 0, 0, 255])));
+    assert_eq!(shift_colors(&red, 120.0, 1.0).get_pixel(0, 0), Rgba([0, 255, 0, 255]));
+}
+
+#[test]
+fn jpeg_quality_is_encoded_in_memory() {
+    let mosaic = build_mosaic(&open_fixture("checker.png"), &MosaicConfig::new(16).build().unwrap()).unwrap();
+    let jpeg = |quality| encode_mosaic(&mosaic, ImageFormat::Jpeg, &EncodeOptions { quality: Some(quality), ..EncodeOptions::default() }).unwrap();
+
+    let (low, high) = (jpeg(10), jpeg(95));
+    assert_eq!(image::guess_format(&low).unwrap(), ImageFormat::Jpeg);
+    assert!(low.len() < high.len());
+}
+
+#[test]
+fn default_output_names_follow_the_input_and_tile_size() {
+    assert_eq!(default_output_name("photos/photo.jpg", 20, ImageFormat::Png), "photo_mosaic_t20.png");
+    assert_eq!(default_output_name("photo.png", 8, ImageFormat::Jpeg), "photo_mosaic_t8.jpg");
+    assert_eq!(default_output_name("-", 20, ImageFormat::Png), "stdin_mosaic_t20.png");
+    assert_eq!(default_output_name("https://example.com/cat.jpg?size=large#top", 4, ImageFormat::Png), "cat_mosaic_t4.png");
+}
+
+#[test]
+fn png_text_metadata_reads_back() {
+    let mosaic = build_mosaic(&open_fixture("red.png"), &MosaicConfig::new(4).build().unwrap()).unwrap();
+    let text = vec![("Tile Size".to_string(), "4".to_string()), ("Source".to_string(), "ĉiuj.png".to_string())];
+    let options = EncodeOptions { text, ..EncodeOptions::default() };
+
+    let bytes = encode_mosaic(&mosaic, ImageFormat::Png, &options).unwrap();
+    let reader = png::Decoder::new(Cursor::new(bytes)).read_info().unwrap();
+    let info = reader.info();
+
+    // Latin-1 text goes into tEXt, anything else into iTXt
+    assert!(info.uncompressed_latin1_text.iter().any(|chunk| chunk.keyword == "Tile Size" && chunk.text == "4"));
+    assert!(info.utf8_text.iter().any(|chunk| chunk.keyword == "Source" && chunk.get_text().unwrap() == "ĉiuj.png"));
+
+    // formats without text metadata skip it
+    assert!(encode_mosaic(&mosaic, ImageFormat::Jpeg, &options).is_ok());
+}
+
+#[test]
+fn identical_images_do_not_differ() {
+    let mosaic = build_mosaic(&open_fixture("checker.png"), &MosaicConfig::new(4).build().unwrap()).unwrap();
+
+    assert_eq!(compare_images(&mosaic, &mosaic.clone()), Some(Difference { mean: 0.0, max: 0.0 }));
+}
+
+#[test]
+fn slightly_different_images_report_their_difference() {
+    let a = DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 2, Rgba([100, 100, 100, 255])));
+    let mut b = a.to_rgba8();
+    b.put_pixel(0, 0, Rgba([104, 98, 100, 255]));
+
+    // the largest channel difference of the one changed pixel out of four
+    assert_eq!(compare_images(&a, &DynamicImage::ImageRgba8(b)), Some(Difference { mean: 1.0, max: 4.0 }));
+
+    let taller = DynamicImage::ImageRgba8(RgbaImage::new(2, 3));
+    assert_eq!(compare_images(&a, &taller), None);
+}
+
+#[test]
+fn saving_into_a_missing_directory_says_so() {
+    let mosaic = build_mosaic(&open_fixture("red.png"), &MosaicConfig::new(4).build().unwrap()).unwrap();
+    let path = temp_path("missing").join("mosaic.png");
+
+    let err = save_mosaic(&mosaic, &path, &EncodeOptions::default()).unwrap_err();
+    assert!(matches!(err, MosaicError::OutputDirMissing(_)), "{}", err);
+}
+
+#[test]
+fn icc_profiles_survive_the_round_trip() {
+    let source = std::fs::read(fixture("icc.png")).unwrap();
+    let profile = icc_profile(&source).unwrap();
+    assert_eq!(profile, (0..300u32).map(|i| (i * 7 % 251) as u8).collect::<Vec<_>>());
+
+    let mosaic = build_mosaic(&open_fixture("icc.png"), &MosaicConfig::new(4).build().unwrap()).unwrap();
+    let encode = |format, profile: &[u8], gray| {
+        let options = EncodeOptions { icc_profile: Some(profile.to_vec()), gray, text: vec![("Tile Size".to_string(), "4".to_string())], ..EncodeOptions::default() };
+        encode_mosaic(&mosaic, format, &options).unwrap()
+    };
+
+    // the pixels are the same with the profile or without
+    for gray in [false, true] {
+        let png = encode(ImageFormat::Png, &profile, gray);
+        assert_eq!(icc_profile(&png).as_ref(), Some(&profile));
+        let plain = encode_mosaic(&mosaic, ImageFormat::Png, &EncodeOptions { gray, ..EncodeOptions::default() }).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8(), image::load_from_memory(&plain).unwrap().to_rgba8());
+    }
+
+    // JPEG splits large profiles over several segments
+    let large: Vec<u8> = (0..150_000u32).map(|i| (i % 253) as u8).collect();
+    let jpeg = encode(ImageFormat::Jpeg, &large, false);
+    assert_eq!(icc_profile(&jpeg), Some(large));
+    assert_eq!(image::load_from_memory(&jpeg).unwrap().dimensions(), (8, 8));
+
+    // formats without a place for it skip it
+    assert_eq!(icc_profile(&encode(ImageFormat::Bmp, &profile, false)), None);
+}
+
+#[test]
+fn every_supported_format_round_trips_in_memory() {
+    let source = open_fixture("red.png");
+    let mosaic = build_mosaic(&source, &MosaicConfig::new(4).build().unwrap()).unwrap();
+
+    let mut formats = vec![ImageFormat::Png, ImageFormat::Jpeg, ImageFormat::Bmp, ImageFormat::Tiff];
+    if cfg!(feature = "webp") {
+        formats.push(ImageFormat::WebP);
+    }
+
+    for format in formats {
+        let bytes = encode_mosaic(&mosaic, format, &EncodeOptions::default()).unwrap();
+        let decoded = image::load_from_memory_with_format(&bytes, format).unwrap();
+        assert_eq!(decoded.dimensions(), (12, 8), "{:?}", format);
+    }
+}